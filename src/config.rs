@@ -0,0 +1,72 @@
+use crate::directories::config_file;
+use crate::error::Error;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use url::Url;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Where downloaded Python interpreters are stored.
+    pub pythons_dir: Option<PathBuf>,
+    /// Where Python interpreters shared by every user are stored, and installed with `--system`.
+    pub shared_pythons_dir: Option<PathBuf>,
+    /// Where virtualenvs are stored.
+    pub virtualenvs_dir: Option<PathBuf>,
+    /// A mirror of the python-build-standalone release assets, laid out as `<release_tag>/<name>`.
+    pub download_url: Option<Url>,
+    /// The GitHub repository to list python-build-standalone releases from, as `owner/repo`.
+    pub cpython_repository: Option<String>,
+    /// A mirror of the PyPy downloads, laid out like `PYPY_DOWNLOAD_URL`.
+    pub pypy_download_url: Option<Url>,
+    /// Ignore python-build-standalone releases created before this time.
+    pub releases_since: Option<chrono::DateTime<chrono::Utc>>,
+    /// The shell to use when none has been set with `lilyenv set-shell`.
+    pub default_shell: Option<String>,
+    /// The prompt shown in an activated virtualenv, with `{project}`, `{version}` and
+    /// `{interpreter}` placeholders.
+    pub prompt: Option<String>,
+    /// How many times to try network requests that fail with transient errors.
+    pub retries: Option<u32>,
+    /// How many seconds to wait to connect, or for more data, before a request times out.
+    pub timeout: Option<u64>,
+    /// Extract downloads as they arrive instead of saving the archive in the downloads directory.
+    pub stream_downloads: Option<bool>,
+    /// Skip the test suites and static libraries when extracting Pythons.
+    pub minimal: Option<bool>,
+    /// What creates virtualenvs and installs packages into them: `venv`, `uv` or `auto`.
+    pub backend: Option<crate::virtualenvs::Backend>,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Read the config file, if it exists. Must be called before `config()` is first used.
+pub fn load_config() -> Result<(), Error> {
+    let file = config_file();
+    let config = match std::fs::read_to_string(&file) {
+        Ok(config) => toml::from_str(&config)
+            .map_err(|err| Error::Config(file.display().to_string(), err.to_string()))?,
+        Err(err) => match err.kind() {
+            std::io::ErrorKind::NotFound => Config::default(),
+            _ => Err(err)?,
+        },
+    };
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+static SYSTEM: OnceLock<bool> = OnceLock::new();
+
+/// Install and uninstall Pythons in the shared pythons directory instead of the user's own.
+pub fn set_system() {
+    let _ = SYSTEM.set(true);
+}
+
+pub(crate) fn system() -> bool {
+    SYSTEM.get().copied().unwrap_or(false)
+}
@@ -1,27 +1,135 @@
+use crate::config::{config, system};
 use crate::version::Version;
 
 fn lilyenv_dir() -> directories::ProjectDirs {
     directories::ProjectDirs::from("", "", "Lilyenv").expect("Could not find the home directory")
 }
 
+/// The root for lilyenv's data, overridden by the `LILYENV_HOME` environment variable.
+fn data_root() -> std::path::PathBuf {
+    match std::env::var_os("LILYENV_HOME") {
+        Some(home) => std::path::PathBuf::from(home),
+        None => lilyenv_dir().data_local_dir().to_path_buf(),
+    }
+}
+
+pub fn cache_dir() -> std::path::PathBuf {
+    lilyenv_dir().cache_dir().to_path_buf()
+}
+
 pub fn downloads_dir() -> std::path::PathBuf {
-    lilyenv_dir().cache_dir().join("downloads")
+    cache_dir().join("downloads")
 }
 
-pub fn python_dir(version: &Version) -> std::path::PathBuf {
-    lilyenv_dir()
-        .data_local_dir()
+/// Where download locks are kept. With `--system` they are shared by every user installing into
+/// the shared pythons directory.
+pub fn locks_dir() -> std::path::PathBuf {
+    match system() {
+        true => shared_pythons_dir().join(".locks"),
+        false => cache_dir().join("locks"),
+    }
+}
+
+pub fn config_file() -> std::path::PathBuf {
+    lilyenv_dir().config_dir().join("config.toml")
+}
+
+/// The startup file interactive `shell`s read, for `lilyenv shell-config --install`.
+pub fn shell_startup_file(shell: &str) -> Option<std::path::PathBuf> {
+    let dirs = directories::BaseDirs::new()?;
+    let home = dirs.home_dir();
+    match shell {
+        "bash" => Some(home.join(".bashrc")),
+        "zsh" => Some(
+            std::env::var_os("ZDOTDIR")
+                .map_or_else(|| home.to_path_buf(), std::path::PathBuf::from)
+                .join(".zshrc"),
+        ),
+        "fish" => Some(dirs.config_dir().join("fish").join("config.fish")),
+        "nu" => Some(dirs.config_dir().join("nushell").join("config.nu")),
+        "pwsh" | "powershell" => {
+            let profile = match cfg!(windows) {
+                true => directories::UserDirs::new()?
+                    .document_dir()?
+                    .join("PowerShell"),
+                false => dirs.config_dir().join("powershell"),
+            };
+            Some(profile.join("Microsoft.PowerShell_profile.ps1"))
+        }
+        _ => None,
+    }
+}
+
+pub fn pythons_dir() -> std::path::PathBuf {
+    match &config().pythons_dir {
+        Some(pythons_dir) => pythons_dir.clone(),
+        None => data_root().join("pythons"),
+    }
+}
+
+pub fn releases_cache_file() -> std::path::PathBuf {
+    cache_dir().join("releases.json.gz")
+}
+
+pub fn pypy_cache_file() -> std::path::PathBuf {
+    cache_dir().join("pypy-downloads.json.gz")
+}
+
+pub fn kept_archives_file() -> std::path::PathBuf {
+    cache_dir().join("kept-archives.json")
+}
+
+pub fn pypy_index_cache_file() -> std::path::PathBuf {
+    cache_dir().join("pypy-index.json.gz")
+}
+
+/// Pythons installed here for every user are used before downloading them again.
+pub fn shared_pythons_dir() -> std::path::PathBuf {
+    match &config().shared_pythons_dir {
+        Some(shared_pythons_dir) => shared_pythons_dir.clone(),
+        None => default_shared_pythons_dir(),
+    }
+}
+
+#[cfg(not(windows))]
+fn default_shared_pythons_dir() -> std::path::PathBuf {
+    std::path::PathBuf::from("/opt/lilyenv/pythons")
+}
+
+#[cfg(windows)]
+fn default_shared_pythons_dir() -> std::path::PathBuf {
+    std::env::var_os("ProgramData")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| std::path::PathBuf::from(r"C:\ProgramData"))
+        .join("lilyenv")
         .join("pythons")
-        .join(version.to_string())
+}
+
+/// The directory for `version`: the shared one with `--system` or if it is installed there,
+/// otherwise the user's own.
+pub fn python_dir(version: &Version) -> std::path::PathBuf {
+    let shared = shared_pythons_dir().join(version.to_string());
+    match system() || is_downloaded(&shared).unwrap_or(false) {
+        true => shared,
+        false => pythons_dir().join(version.to_string()),
+    }
+}
+
+/// Where lilyenv records where a downloaded Python came from.
+pub fn python_metadata_file(python_dir: &std::path::Path) -> std::path::PathBuf {
+    python_dir.join(".lilyenv.json")
 }
 
 pub fn virtualenvs_dir() -> std::path::PathBuf {
-    lilyenv_dir().data_local_dir().join("virtualenvs")
+    match &config().virtualenvs_dir {
+        Some(virtualenvs_dir) => virtualenvs_dir.clone(),
+        None => data_root().join("virtualenvs"),
+    }
 }
 
 pub fn shell_file(project: Option<&str>) -> std::path::PathBuf {
     match project {
-        None => lilyenv_dir().data_local_dir().join("shell"),
+        None => data_root().join("shell"),
         Some(project) => project_dir(project).join("shell"),
     }
 }
@@ -34,10 +142,112 @@ pub fn virtualenv_dir(project: &str, version: &Version) -> std::path::PathBuf {
     project_dir(project).join(version.to_string())
 }
 
+/// A virtualenv given a name of its own instead of being named after its version.
+pub fn named_virtualenv_dir(project: &str, name: &str) -> std::path::PathBuf {
+    project_dir(project).join(name)
+}
+
+/// Records a named virtualenv's version, which its directory name doesn't give.
+pub fn virtualenv_version_file(virtualenv: &std::path::Path) -> std::path::PathBuf {
+    virtualenv.join("lilyenv-version")
+}
+
 pub fn project_file(project: &str) -> std::path::PathBuf {
     project_dir(project).join("directory")
 }
 
+pub fn default_version_file(project: &str) -> std::path::PathBuf {
+    project_dir(project).join("default-version")
+}
+
+pub fn last_version_file(project: &str) -> std::path::PathBuf {
+    project_dir(project).join("last-version")
+}
+
 pub fn is_downloaded(python_dir: &std::path::Path) -> std::io::Result<bool> {
-    Ok(python_dir.exists() && std::fs::read_dir(&python_dir)?.next().is_some())
+    Ok(python_dir.exists() && std::fs::read_dir(python_dir)?.next().is_some())
+}
+
+#[cfg(not(windows))]
+pub fn venv_bin_dir(root: &std::path::Path) -> std::path::PathBuf {
+    root.join("bin")
+}
+
+#[cfg(windows)]
+pub fn venv_bin_dir(root: &std::path::Path) -> std::path::PathBuf {
+    root.join("Scripts")
+}
+
+#[cfg(not(windows))]
+pub fn venv_python(root: &std::path::Path) -> std::path::PathBuf {
+    venv_bin_dir(root).join("python")
+}
+
+#[cfg(windows)]
+pub fn venv_python(root: &std::path::Path) -> std::path::PathBuf {
+    venv_bin_dir(root).join("python.exe")
+}
+
+/// The virtualenv's site-packages, in the single `lib/pythonX.Y` directory, if it has one.
+#[cfg(not(windows))]
+pub fn venv_site_packages(root: &std::path::Path) -> std::io::Result<Option<std::path::PathBuf>> {
+    match std::fs::read_dir(root.join("lib"))?.next() {
+        Some(entry) => Ok(Some(entry?.path().join("site-packages"))),
+        None => Ok(None),
+    }
+}
+
+#[cfg(windows)]
+pub fn venv_site_packages(root: &std::path::Path) -> std::io::Result<Option<std::path::PathBuf>> {
+    let site_packages = root.join("Lib").join("site-packages");
+    Ok(site_packages.is_dir().then_some(site_packages))
+}
+
+pub fn pyvenv_cfg(root: &std::path::Path) -> std::path::PathBuf {
+    root.join("pyvenv.cfg")
+}
+
+/// Linux and macOS builds share this layout, where `bin/python3` is a symlink to the versioned
+/// executable.
+#[cfg(not(windows))]
+pub fn python_executable(python_dir: &std::path::Path) -> std::path::PathBuf {
+    python_dir.join("bin").join("python3")
+}
+
+#[cfg(windows)]
+pub fn python_executable(python_dir: &std::path::Path) -> std::path::PathBuf {
+    python_dir.join("python.exe")
+}
+
+/// How many directories deep the interpreter can be in an extracted download.
+const INTERPRETER_ROOT_DEPTH: usize = 2;
+
+/// Find the directory containing the interpreter in an extracted download. This is normally the
+/// download's `python` entry, but full CPython archives keep it in `python/install` and other
+/// archives may have it at the top. The shallowest match wins, then the first by name, so stray
+/// entries next to it never change which one is used.
+pub fn find_interpreter_root(
+    python_dir: &std::path::Path,
+) -> std::io::Result<Option<std::path::PathBuf>> {
+    let mut level = vec![python_dir.to_path_buf()];
+    for depth in 0..=INTERPRETER_ROOT_DEPTH {
+        if let Some(root) = level.iter().find(|dir| python_executable(dir).is_file()) {
+            return Ok(Some(root.clone()));
+        }
+        if depth == INTERPRETER_ROOT_DEPTH {
+            break;
+        }
+        let mut next = Vec::new();
+        for dir in level {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    next.push(path);
+                }
+            }
+        }
+        next.sort_unstable();
+        level = next;
+    }
+    Ok(None)
 }
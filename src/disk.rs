@@ -0,0 +1,172 @@
+use crate::directories::locks_dir;
+use crate::error::Error;
+use fs2::FileExt;
+use std::path::Path;
+
+/// Extracted archives take several times the space of the compressed download.
+pub const EXTRACTION_FACTOR: u64 = 5;
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    match unit {
+        "B" => format!("{bytes} B"),
+        _ => format!("{size:.1} {unit}"),
+    }
+}
+
+pub fn dir_size(path: &Path) -> Result<u64, Error> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path).with_path(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+pub fn check_available_space(path: &Path, required: u64) -> Result<(), Error> {
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .expect("The filesystem root should exist.");
+    let available = fs2::available_space(existing)?;
+    if available < required {
+        return Err(Error::DiskSpace {
+            path: existing.display().to_string(),
+            required,
+            available,
+        });
+    }
+    Ok(())
+}
+
+/// Attaches the path an io operation failed on to its error.
+pub trait WithPath<T> {
+    fn with_path(self, path: impl AsRef<Path>) -> Result<T, Error>;
+}
+
+impl<T> WithPath<T> for std::io::Result<T> {
+    fn with_path(self, path: impl AsRef<Path>) -> Result<T, Error> {
+        self.map_err(|source| Error::Path {
+            path: path.as_ref().to_path_buf(),
+            source,
+        })
+    }
+}
+
+/// Write `contents` to `<path>.part` and rename it over `path`, so a crash part way through never
+/// leaves a truncated file at `path`.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), Error> {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    std::fs::write(&part, contents).with_path(&part)?;
+    std::fs::rename(&part, path).with_path(path)
+}
+
+/// Read a gzip compressed JSON cache file, decompressing and deserializing it as it's read.
+/// `None` if it's missing or can't be parsed, so it's fetched again.
+pub fn read_cache<T: serde::de::DeserializeOwned>(path: &Path) -> Option<T> {
+    let file = std::fs::File::open(path).ok()?;
+    let reader = flate2::read::GzDecoder::new(std::io::BufReader::new(file));
+    serde_json::from_reader(reader).ok()
+}
+
+/// Write `value` to a cache file as gzip compressed JSON, removing the uncompressed `.json` file
+/// earlier versions of lilyenv wrote instead.
+pub fn write_cache<T: serde::Serialize>(path: &Path, value: &T) -> Result<(), Error> {
+    let dir = path.parent().expect("cache file always has a parent");
+    std::fs::create_dir_all(dir).with_path(dir)?;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    serde_json::to_writer(&mut encoder, value).expect("Caches can always be serialized.");
+    write_atomic(path, encoder.finish()?)?;
+    let _ = std::fs::remove_file(path.with_extension(""));
+    Ok(())
+}
+
+/// An exclusive lock shared with other lilyenv processes, held until it is dropped. The
+/// operating system releases it if lilyenv exits first, like on Ctrl-C.
+pub struct Lock {
+    _file: std::fs::File,
+}
+
+/// Take the lock called `name`, waiting for whoever holds it to finish with `what`.
+pub fn lock(name: &str, what: &str) -> Result<Lock, Error> {
+    let locks = locks_dir();
+    std::fs::create_dir_all(&locks).with_path(&locks)?;
+    let path = locks.join(format!("{name}.lock"));
+    let file = match std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+    {
+        // Another user may have created a shared lock, which can still be locked read only.
+        Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+            std::fs::File::open(&path).with_path(&path)?
+        }
+        file => file?,
+    };
+    if let Err(err) = file.try_lock_exclusive() {
+        if err.kind() != std::io::ErrorKind::WouldBlock {
+            return Err(err.into());
+        }
+        log::info!("Waiting for another lilyenv to finish with {what}");
+        file.lock_exclusive()?;
+    }
+    Ok(Lock { _file: file })
+}
+
+/// A new directory for a test to write files in, removed along with them when it's dropped.
+#[cfg(test)]
+pub(crate) fn test_dir() -> tempfile::TempDir {
+    tempfile::Builder::new()
+        .prefix("lilyenv-test-")
+        .tempdir()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KB");
+        assert_eq!(format_size(30 * 1024 * 1024 + 512 * 1024), "30.5 MB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let temp = test_dir();
+        let dir = temp.path();
+        let file = dir.join("releases.json.gz");
+        std::fs::write(dir.join("releases.json"), "[]").unwrap();
+
+        assert_eq!(read_cache::<Vec<String>>(&file), None);
+        write_cache(&file, &vec!["3.12.4".to_string()]).unwrap();
+        assert_eq!(
+            read_cache::<Vec<String>>(&file),
+            Some(vec!["3.12.4".to_string()])
+        );
+        assert!(!dir.join("releases.json").exists());
+        // The old uncompressed format isn't mistaken for a cache.
+        std::fs::write(&file, "[\"3.12.4\"]").unwrap();
+        assert_eq!(read_cache::<Vec<String>>(&file), None);
+    }
+}
@@ -1,185 +1,2341 @@
-use crate::directories::{downloads_dir, is_downloaded, python_dir};
+use crate::config::{config, system};
+use crate::directories::{
+    cache_dir, downloads_dir, find_interpreter_root, is_downloaded, kept_archives_file,
+    pypy_cache_file, pypy_index_cache_file, python_dir, python_executable, python_metadata_file,
+    pythons_dir, releases_cache_file, shared_pythons_dir, virtualenvs_dir,
+};
+use crate::disk::{
+    check_available_space, dir_size, format_size, lock, write_atomic, WithPath, EXTRACTION_FACTOR,
+};
 use crate::error::Error;
-use crate::releases::{cpython_releases, pypy_releases};
-use crate::version::{Interpreter, Version};
+use crate::http::{block_on, client, offline, with_retry};
+use crate::interrupt::PartialGuard;
+use crate::output::{
+    print_releases, sort_releases, styled_version, OutputFormat, ReleaseOrder, RELEASE_TAG, VERSION,
+};
+use crate::releases::{
+    asset_matches_platform, cpython_asset_names, cpython_releases, downloaded_releases,
+    foreign_platform, graalpy_releases, install_only_platform, newest_compatible, pypy_releases,
+    target_platform, version_key, Python,
+};
+use crate::version::{
+    matches, parse_cpython_filename, Interpreter, PreRelease, Version, VersionRequest,
+    VersionSpecifier,
+};
+use crate::virtualenvs::{broken_virtualenvs, projects_using, remove_empty_project};
 use bzip2::read::BzDecoder;
 use flate2::read::GzDecoder;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::fs::File;
+use std::io::Write;
 use std::path::Path;
 use tar::Archive;
 use url::Url;
 use zstd::stream::read::Decoder as ZstDecoder;
 
-pub fn download_python(version: &Version, upgrade: bool) -> Result<(), Error> {
+/// Download and install a Python version, replacing an existing install when `upgrade` is set.
+/// Builds for another platform are only downloaded, to copy to a machine that can run them.
+pub fn download_python(version: &Version, upgrade: bool, refresh: bool) -> Result<(), Error> {
+    download_python_release(version, None, upgrade, refresh)?;
+    Ok(())
+}
+
+/// Upgrade an installed Python version to its newest release, returning whether a newer release
+/// was installed.
+pub fn upgrade_python(version: &Version) -> Result<bool, Error> {
+    let python = find_release(version, None, true)?;
+    let current = read_metadata(&python_dir(version)).is_some_and(|metadata| {
+        metadata.release == python.version && metadata.release_tag == python.release_tag
+    });
+    if current {
+        log::info!(
+            "Python {version} is already the newest release, {} from {}",
+            python.version,
+            python.release_tag
+        );
+        return Ok(false);
+    }
+    download_python_release(version, Some(&python.release_tag), true, false)?;
+    Ok(true)
+}
+
+/// A release that was installed, or downloaded for another platform.
+pub struct Download {
+    pub python: Python,
+    /// The size of the archive downloaded, or `None` if it had already been downloaded.
+    pub downloaded: Option<u64>,
+}
+
+/// Download and install a Python version like `download_python`, from the release with
+/// `release_tag` if one is given. An install of the version from another release is replaced.
+/// Returns `None` if the version was already installed or downloaded.
+pub fn download_python_release(
+    version: &Version,
+    release_tag: Option<&str>,
+    upgrade: bool,
+    refresh: bool,
+) -> Result<Option<Download>, Error> {
+    // Whoever gets the lock second finds the Python already installed.
+    let _lock = lock(&format!("python-{version}"), &format!("Python {version}"))?;
+    if let Some(platform) = foreign_platform() {
+        return download_archive(version, release_tag, upgrade, refresh, platform);
+    }
+    let python_dir = python_dir(version);
+    let upgrade = upgrade
+        || match release_tag {
+            Some(release_tag) => {
+                is_downloaded(&python_dir).with_path(&python_dir)?
+                    && read_metadata(&python_dir)
+                        .is_none_or(|metadata| metadata.release_tag != release_tag)
+            }
+            None => false,
+        };
     match version.interpreter {
-        Interpreter::CPython => download_cpython(version, upgrade),
-        Interpreter::PyPy => download_pypy(version, upgrade),
+        Interpreter::CPython => download_cpython(version, release_tag, upgrade, refresh),
+        Interpreter::PyPy => download_pypy(version, release_tag, upgrade),
+        Interpreter::GraalPy => download_graalpy(version, release_tag, upgrade),
     }
 }
 
-pub fn print_available_downloads() -> Result<(), Error> {
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()?;
-    let mut releases = rt.block_on(cpython_releases())?;
-    releases.sort_unstable_by_key(|p| p.version);
-    for python in releases {
-        println!("{} ({})", python.version, python.release_tag);
+/// Download the archive for `version` to the downloads directory without installing it.
+fn download_archive(
+    version: &Version,
+    release_tag: Option<&str>,
+    upgrade: bool,
+    refresh: bool,
+    platform: &str,
+) -> Result<Option<Download>, Error> {
+    let python = find_release(version, release_tag, upgrade || refresh)?;
+    let _lock = lock(&python.name, &python.name)?;
+    let downloads = downloads_dir();
+    std::fs::create_dir_all(&downloads).with_path(&downloads)?;
+    let path = downloads.join(&python.name);
+    if path.exists() && !upgrade {
+        if let Some(problem) = cached_archive_problem(&python, &path)? {
+            log::warn!(
+                "The downloaded {} {problem}, downloading it again.",
+                python.name
+            );
+            std::fs::remove_file(&path).with_path(&path)?;
+        }
     }
-    let mut pypy_releases = pypy_releases()?;
-    pypy_releases.sort_unstable_by_key(|p| p.version);
-    for python in pypy_releases {
-        println!("{} ({})", python.version, python.release_tag);
+    let downloaded = match (upgrade && !offline()) || !path.exists() {
+        true => {
+            download_and_verify(&python, &path)?;
+            Some(path.metadata().with_path(&path)?.len())
+        }
+        false => None,
+    };
+    if archive_retention() == ArchiveRetention::Keep {
+        set_kept(&python.name, true)?;
     }
-    Ok(())
+    log::info!(
+        "Downloaded {} for {platform} to {}",
+        python.name,
+        path.display()
+    );
+    Ok(downloaded.map(|size| Download {
+        python,
+        downloaded: Some(size),
+    }))
 }
 
-fn download_cpython(version: &Version, upgrade: bool) -> Result<(), Error> {
-    let python_dir = python_dir(version);
-    if !upgrade && is_downloaded(&python_dir)? {
+const MAX_CONCURRENT_DOWNLOADS: usize = 4;
+
+static NO_CACHE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+/// Stream every download straight into extraction, never keeping or reusing archives in the
+/// downloads directory.
+pub fn set_no_cache() {
+    let _ = NO_CACHE.set(true);
+}
+
+fn no_cache() -> bool {
+    NO_CACHE.get().copied().unwrap_or(false)
+}
+
+static MINIMAL: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+/// Skip the parts of archives only needed to test or embed Python when extracting them.
+pub fn set_minimal() {
+    let _ = MINIMAL.set(true);
+}
+
+fn minimal() -> bool {
+    MINIMAL
+        .get()
+        .copied()
+        .unwrap_or(config().minimal == Some(true))
+}
+
+/// What to do with an archive once it has been downloaded and installed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveRetention {
+    /// Keep it until it is cleaned, pruned or garbage collected.
+    Default,
+    /// Keep it even when cleaning, pruning or garbage collecting, for reinstalling offline.
+    Keep,
+    /// Remove it as soon as it has been extracted.
+    Delete,
+}
+
+static ARCHIVE_RETENTION: std::sync::OnceLock<ArchiveRetention> = std::sync::OnceLock::new();
+
+/// Keep or delete the archives downloaded from now on. Must be called before downloading.
+pub fn set_archive_retention(retention: ArchiveRetention) {
+    let _ = ARCHIVE_RETENTION.set(retention);
+}
+
+fn archive_retention() -> ArchiveRetention {
+    ARCHIVE_RETENTION
+        .get()
+        .copied()
+        .unwrap_or(ArchiveRetention::Default)
+}
+
+/// The names of the archives downloaded with `--keep-archive`.
+fn kept_archives() -> Result<Vec<String>, Error> {
+    match std::fs::read_to_string(kept_archives_file()) {
+        Ok(kept) => Ok(serde_json::from_str(&kept).unwrap_or_default()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Record whether the archive `name` is kept when cleaning, pruning or garbage collecting.
+fn set_kept(name: &str, kept: bool) -> Result<(), Error> {
+    let mut archives = kept_archives()?;
+    if archives.iter().any(|archive| archive == name) == kept {
         return Ok(());
     }
+    match kept {
+        true => archives.push(name.to_string()),
+        false => archives.retain(|archive| archive != name),
+    }
+    archives.sort_unstable();
+    std::fs::create_dir_all(cache_dir()).with_path(cache_dir())?;
+    write_atomic(
+        &kept_archives_file(),
+        serde_json::to_string(&archives).expect("Archive names can always be serialized."),
+    )?;
+    Ok(())
+}
 
-    let downloads = downloads_dir();
-    std::fs::create_dir_all(&downloads)?;
+/// Leave out the archives kept with `--keep-archive`.
+fn without_kept(paths: Vec<std::path::PathBuf>) -> Result<Vec<std::path::PathBuf>, Error> {
+    let kept = kept_archives()?;
+    Ok(paths
+        .into_iter()
+        .filter(|path| {
+            let name = path.file_name().and_then(|name| name.to_str());
+            let keep = name.is_some_and(|name| kept.iter().any(|archive| archive == name));
+            if keep {
+                log::debug!(
+                    "Keeping {}, as it was downloaded with --keep-archive",
+                    path.display()
+                );
+            }
+            !keep
+        })
+        .collect())
+}
+
+/// Download the Python for `request` and extract it into `output_dir` instead of lilyenv's
+/// pythons directory. lilyenv doesn't keep track of it, so virtualenvs won't use it. With
+/// `force`, anything already in `output_dir` is replaced.
+pub fn download_python_to(
+    request: &VersionRequest,
+    output_dir: &Path,
+    force: bool,
+    refresh: bool,
+    full: bool,
+) -> Result<(), Error> {
+    let [(version, ref release_tag)] =
+        resolve_requests(std::slice::from_ref(request), refresh, full)?[..]
+    else {
+        unreachable!("One request always resolves to one version.");
+    };
+    // The sysconfig paths are rewritten to point here, so they mustn't be relative.
+    let output_dir = &std::path::absolute(output_dir)?;
+    let occupied = match std::fs::read_dir(output_dir) {
+        Ok(mut entries) => entries.next().is_some(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => false,
+        Err(err) => return Err(err.into()),
+    };
+    if occupied && !force {
+        return Err(Error::OutputDirExists(output_dir.display().to_string()));
+    }
 
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()?;
-    let python = match rt
-        .block_on(cpython_releases())?
+    let python = find_release(&version, release_tag.as_deref(), refresh)?;
+    // Offline, `install` reuses the downloaded archive even when replacing the extraction.
+    install(&python, output_dir, occupied)?;
+    if output_dir.join("python").join("install").is_dir() {
+        move_install(output_dir)?;
+    }
+    if version.interpreter == Interpreter::CPython {
+        fixup_sysconfig_paths(output_dir)?;
+    }
+    if foreign_platform().is_none() {
+        check_interpreter(&version, output_dir)?;
+    }
+    report_installed(&version, output_dir)?;
+    log::info!(
+        "lilyenv doesn't manage {}, so virtualenvs won't use it and `lilyenv uninstall` won't remove it.",
+        output_dir.display()
+    );
+    Ok(())
+}
+
+/// Resolve a version specifier to the newest CPython release satisfying it.
+fn resolve_specifier(specifier: &VersionSpecifier, refresh: bool) -> Result<Version, Error> {
+    let releases = match offline() {
+        true => downloaded_releases()?,
+        false => block_on(cpython_releases(refresh))?,
+    };
+    if releases.is_empty() && !offline() {
+        return Err(Error::Platform(target_platform().to_string()));
+    }
+    let version = releases
+        .into_iter()
+        .map(|python| python.version)
+        .filter(|version| specifier.matches(version))
+        .max()
+        .ok_or_else(|| Error::VersionNotFound(specifier.to_string()))?;
+    log::info!("Resolved {specifier} to {version}");
+    Ok(version)
+}
+
+/// The releases of `interpreter` available to download, or offline, the downloaded archives.
+fn available_releases(interpreter: Interpreter, refresh: bool) -> Result<Vec<Python>, Error> {
+    let releases = match (offline(), interpreter) {
+        (true, _) => downloaded_releases()?
+            .into_iter()
+            .filter(|python| python.version.interpreter == interpreter)
+            .collect(),
+        (false, Interpreter::CPython) => block_on(cpython_releases(refresh))?,
+        (false, Interpreter::PyPy) => pypy_releases()?,
+        (false, Interpreter::GraalPy) => block_on(graalpy_releases())?,
+    };
+    Ok(releases)
+}
+
+/// Resolve a bare major version like `3` or `pypy3` to its newest installed minor version, or
+/// with `latest` or if none is installed, its newest release.
+fn resolve_major(
+    interpreter: Interpreter,
+    major: u16,
+    latest: bool,
+    refresh: bool,
+) -> Result<Version, Error> {
+    let candidate = |version: &Version| {
+        version.interpreter == interpreter
+            && version.major == major
+            && version.prerelease == PreRelease::None
+            && !version.debug
+            && !version.freethreaded
+            && !version.full
+    };
+    let installed = installed_pythons()?
         .into_iter()
-        .find(|python| python.version.compatible(version))
+        .map(|python| python.version)
+        .filter(candidate)
+        .max();
+    let version = match installed {
+        Some(version) if !latest => version,
+        _ => {
+            let releases = available_releases(interpreter, refresh)?;
+            // Downloading the x.y version gets its newest bugfix release.
+            let newest = releases
+                .into_iter()
+                .map(|python| python.version)
+                .filter(candidate)
+                .max()
+                .map(|version| Version {
+                    bugfix: None,
+                    ..version
+                });
+            match (newest, installed) {
+                (Some(version), _) | (None, Some(version)) => version,
+                (None, None) => {
+                    return Err(Error::VersionNotFound(format!(
+                        "{}{major}",
+                        interpreter.prefix()
+                    )))
+                }
+            }
+        }
+    };
+    log::info!("Resolved {}{major} to {version}", interpreter.prefix());
+    Ok(version)
+}
+
+/// Resolve `latest` or `latest-pypy` to the x.y version of the interpreter's newest stable
+/// release.
+fn resolve_latest(interpreter: Interpreter, refresh: bool) -> Result<Version, Error> {
+    let request = VersionRequest::Latest(interpreter);
+    let newest = available_releases(interpreter, refresh)?
+        .into_iter()
+        .map(|python| python.version)
+        .filter(|version| {
+            version.prerelease == PreRelease::None
+                && !version.debug
+                && !version.freethreaded
+                && !version.full
+        })
+        .max()
+        .ok_or_else(|| Error::VersionNotFound(request.to_string()))?;
+    let version = Version {
+        bugfix: None,
+        ..newest
+    };
+    log::info!("Resolved {request} to {version}");
+    Ok(version)
+}
+
+/// The version to use for `request`, resolving bare major versions and specifiers. Bare major
+/// versions prefer an installed Python unless `latest` is set.
+pub fn resolve_request(
+    request: &VersionRequest,
+    latest: bool,
+    refresh: bool,
+) -> Result<Version, Error> {
+    match request {
+        VersionRequest::Version(version) => Ok(*version),
+        VersionRequest::Major(interpreter, major) => {
+            resolve_major(*interpreter, *major, latest, refresh)
+        }
+        VersionRequest::Specifier(specifier) => resolve_specifier(specifier, refresh),
+        VersionRequest::Latest(interpreter) => resolve_latest(*interpreter, refresh),
+        VersionRequest::Release(..) => Err(Error::ReleaseTagUnsupported(request.to_string())),
+    }
+}
+
+/// Download several Python versions concurrently, then print a table of what happened to each. With
+/// `force`, existing installs are replaced. With `full`, CPython's full distributions are
+/// downloaded instead of the `install_only` ones.
+pub fn download_pythons(
+    requests: &[VersionRequest],
+    refresh: bool,
+    force: bool,
+    full: bool,
+) -> Result<(), Error> {
+    if let [request] = requests {
+        let versions = resolve_requests(std::slice::from_ref(request), refresh, full)?;
+        if let [(version, ref release_tag)] = versions[..] {
+            download_python_release(&version, release_tag.as_deref(), force, refresh)?;
+            return Ok(());
+        }
+    }
+    // A version that can't be resolved is reported with the others instead of stopping them.
+    let mut versions = vec![];
+    let mut unresolved = vec![];
+    for request in requests {
+        match resolve_requests(std::slice::from_ref(request), refresh, full) {
+            Ok(resolved) => versions.extend(resolved),
+            Err(err) => unresolved.push((request.to_string(), Outcome::Failed(err))),
+        }
+    }
+    versions.sort_unstable();
+    versions.dedup();
+    if !offline()
+        && versions
+            .iter()
+            .any(|(version, _)| version.interpreter == Interpreter::CPython)
     {
-        Some(python) => python,
-        None => {
-            return Err(Error::VersionNotFound(version.to_string()));
+        // Fetch the releases once up front instead of in every thread.
+        block_on(cpython_releases(refresh))?;
+    }
+
+    let queue = std::sync::Mutex::new(versions.iter());
+    let results = std::sync::Mutex::new(vec![]);
+    std::thread::scope(|scope| {
+        for _ in 0..MAX_CONCURRENT_DOWNLOADS.min(versions.len()) {
+            scope.spawn(|| loop {
+                let next = queue.lock().expect("Download queue lock poisoned.").next();
+                let Some((version, release_tag)) = next else {
+                    break;
+                };
+                let outcome = download_outcome(version, release_tag.as_deref(), force);
+                results
+                    .lock()
+                    .expect("Download results lock poisoned.")
+                    .push((version, outcome));
+            });
         }
+    });
+
+    let mut results = results
+        .into_inner()
+        .expect("Download results lock poisoned.");
+    results.sort_unstable_by_key(|(version, _)| **version);
+    let results = results
+        .into_iter()
+        .map(|(version, outcome)| (version.to_string(), outcome))
+        .chain(unresolved)
+        .collect::<Vec<_>>();
+    print_download_summary(&results);
+    match results
+        .iter()
+        .filter(|(_, outcome)| matches!(outcome, Outcome::Failed(_)))
+        .count()
+    {
+        0 => Ok(()),
+        failed => Err(Error::DownloadsFailed(failed)),
+    }
+}
+
+/// What downloading one of several versions did, for the summary.
+enum Outcome {
+    /// Installed, with the size of the archive if it had to be downloaded.
+    Installed(Option<u64>),
+    AlreadyInstalled,
+    Failed(Error),
+}
+
+fn download_outcome(version: &Version, release_tag: Option<&str>, force: bool) -> Outcome {
+    match download_python_release(version, release_tag, force, false) {
+        Ok(Some(download)) => Outcome::Installed(download.downloaded),
+        Ok(None) => Outcome::AlreadyInstalled,
+        Err(err) => Outcome::Failed(err),
+    }
+}
+
+/// Print a table of each version's outcome once all the downloads have finished.
+fn print_download_summary(results: &[(String, Outcome)]) {
+    let rows = results
+        .iter()
+        .map(|(version, outcome)| {
+            let (size, outcome) = match outcome {
+                Outcome::Installed(size) => (size.map(format_size), "installed".to_string()),
+                Outcome::AlreadyInstalled => (None, "already installed".to_string()),
+                Outcome::Failed(err) => (None, format!("failed: {err}")),
+            };
+            (version, size.unwrap_or_else(|| "-".to_string()), outcome)
+        })
+        .collect::<Vec<_>>();
+    let width = rows
+        .iter()
+        .map(|(version, _, _)| version.len())
+        .max()
+        .unwrap_or(0);
+    let size_width = rows
+        .iter()
+        .map(|(_, size, _)| size.len())
+        .max()
+        .unwrap_or(0);
+    for (version, size, outcome) in rows {
+        println!("{version:width$}  {size:>size_width$}  {outcome}");
+    }
+}
+
+/// Print the release each request would download and where it would be installed, without
+/// downloading anything.
+pub fn print_download_plan(
+    requests: &[VersionRequest],
+    refresh: bool,
+    full: bool,
+) -> Result<(), Error> {
+    for (i, (version, release_tag)) in resolve_requests(requests, refresh, full)?
+        .iter()
+        .enumerate()
+    {
+        if i > 0 {
+            println!();
+        }
+        let python = find_release(version, release_tag.as_deref(), refresh)?;
+        let url = mirror_url(&python, &python.url)?;
+        let archive = downloads_dir().join(&python.name);
+        let size = match archive.exists() {
+            true => Some(archive.metadata()?.len()),
+            false => download_size(&url)?,
+        };
+        let python_dir = python_dir(version);
+        anstream::println!(
+            "{} {RELEASE_TAG}({}){RELEASE_TAG:#}",
+            styled_version(&python.version),
+            python.release_tag
+        );
+        println!("  Archive: {}", python.name);
+        println!("  URL: {url}");
+        match (size, archive.exists()) {
+            (Some(size), true) => println!("  Size: {} (already downloaded)", format_size(size)),
+            (Some(size), false) => println!("  Size: {}", format_size(size)),
+            (None, _) => println!("  Size: unknown"),
+        }
+        if foreign_platform().is_some() {
+            println!("  Download to: {}", archive.display());
+            continue;
+        }
+        match is_downloaded(&python_dir)? {
+            true => println!(
+                "  Install to: {} (already installed, use --force to replace it)",
+                python_dir.display()
+            ),
+            false => println!("  Install to: {}", python_dir.display()),
+        }
+    }
+    Ok(())
+}
+
+/// The size of the file at `url` from a HEAD request, if the server reports it.
+fn download_size(url: &Url) -> Result<Option<u64>, Error> {
+    let response = with_retry(|| Ok(client()?.head(url.clone()).send()?.error_for_status()?))?;
+    // reqwest reports a zero `content_length()` for HEAD responses, so read the header itself.
+    Ok(response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse().ok()))
+}
+
+/// The requested versions and release tags, with specifiers resolved to the newest matching
+/// release.
+fn resolve_requests(
+    requests: &[VersionRequest],
+    refresh: bool,
+    full: bool,
+) -> Result<Vec<(Version, Option<String>)>, Error> {
+    let mut versions = requests
+        .iter()
+        .map(|request| {
+            let (mut version, release_tag) = match request {
+                VersionRequest::Release(version, release_tag) => {
+                    (*version, Some(release_tag.clone()))
+                }
+                // Downloading a bare major version means its newest release, not one already
+                // installed.
+                request => (resolve_request(request, true, refresh)?, None),
+            };
+            // Debug builds are always full distributions.
+            if full && version.interpreter == Interpreter::CPython && !version.debug {
+                version.full = true;
+            }
+            Ok((version, release_tag))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    versions.sort_unstable();
+    versions.dedup();
+    Ok(versions)
+}
+
+/// Every interpreter's releases for this platform, or only the downloaded ones offline.
+fn all_available_releases(refresh: bool) -> Result<Vec<Python>, Error> {
+    if offline() {
+        return downloaded_releases();
+    }
+    let mut releases = block_on(cpython_releases(refresh))?;
+    releases.extend(pypy_releases()?);
+    releases.extend(block_on(graalpy_releases())?);
+    Ok(releases)
+}
+
+pub fn print_available_downloads(
+    refresh: bool,
+    format: OutputFormat,
+    order: ReleaseOrder,
+) -> Result<(), Error> {
+    let mut releases = all_available_releases(refresh)?;
+    // Version orders by interpreter first, so CPython releases are listed before PyPy and GraalPy.
+    sort_releases(&mut releases, order);
+    print_releases(&releases, format);
+    Ok(())
+}
+
+/// The requests with `latest` resolved to the newest x.y version, to list its builds.
+fn resolve_latest_requests(
+    requests: &[VersionRequest],
+    refresh: bool,
+) -> Result<Vec<VersionRequest>, Error> {
+    requests
+        .iter()
+        .map(|request| match request {
+            VersionRequest::Latest(interpreter) => Ok(VersionRequest::Version(resolve_latest(
+                *interpreter,
+                refresh,
+            )?)),
+            request => Ok(request.clone()),
+        })
+        .collect()
+}
+
+/// Whether the build of `version` from the release with `release_tag` is one `request` asks for.
+fn request_matches(request: &VersionRequest, version: &Version, release_tag: &str) -> bool {
+    match request {
+        VersionRequest::Version(requested) => matches(requested, version),
+        VersionRequest::Major(interpreter, major) => {
+            version.interpreter == *interpreter && version.major == *major
+        }
+        VersionRequest::Specifier(specifier) => specifier.matches(version),
+        VersionRequest::Latest(_) => unreachable!("latest is resolved before matching"),
+        VersionRequest::Release(requested, requested_tag) => {
+            matches(requested, version) && release_tag == requested_tag
+        }
+    }
+}
+
+/// Print the platforms with CPython `install_only` builds matching the requests, whatever the
+/// target platform, to see why a version isn't found for it.
+pub fn print_release_platforms(requests: &[VersionRequest], refresh: bool) -> Result<(), Error> {
+    if let Some(request) = requests.iter().find(|request| match request {
+        VersionRequest::Version(version) | VersionRequest::Release(version, _) => {
+            version.interpreter != Interpreter::CPython
+        }
+        VersionRequest::Major(interpreter, _) | VersionRequest::Latest(interpreter) => {
+            *interpreter != Interpreter::CPython
+        }
+        VersionRequest::Specifier(_) => false,
+    }) {
+        return Err(Error::PlatformsUnavailable(request.to_string()));
+    }
+    let requests = resolve_latest_requests(requests, refresh)?;
+    let names = block_on(cpython_asset_names())?;
+    let mut platforms = names
+        .iter()
+        .filter_map(|name| {
+            let platform = install_only_platform(name)?;
+            let (release_tag, version) = parse_cpython_filename(name).ok()?;
+            requests
+                .iter()
+                .any(|request| request_matches(request, &version, &release_tag))
+                .then_some((platform, asset_matches_platform(name)))
+        })
+        .collect::<Vec<_>>();
+    platforms.sort_unstable();
+    platforms.dedup();
+    if platforms.is_empty() {
+        let requests = requests.iter().map(ToString::to_string).collect::<Vec<_>>();
+        return Err(Error::VersionNotFound(requests.join(", ")));
+    }
+    for (platform, target) in platforms {
+        match target {
+            true => println!("{platform} (target platform)"),
+            false => println!("{platform}"),
+        }
+    }
+    Ok(())
+}
+
+/// List the releases available to download for each request, like every `3.11.x` build for
+/// `3.11`, instead of downloading one.
+pub fn print_matching_downloads(
+    requests: &[VersionRequest],
+    refresh: bool,
+    format: OutputFormat,
+    order: ReleaseOrder,
+) -> Result<(), Error> {
+    let requests = resolve_latest_requests(requests, refresh)?;
+    let requested = |python: &Python| {
+        requests
+            .iter()
+            .any(|request| request_matches(request, &python.version, &python.release_tag))
     };
-    let path = downloads.join(&python.name);
-    if upgrade || !path.exists() {
-        download_file(python.url.clone(), &path)?;
+    let mut interpreters = requests
+        .iter()
+        .map(|request| match request {
+            VersionRequest::Version(version) => version.interpreter,
+            VersionRequest::Major(interpreter, _) => *interpreter,
+            VersionRequest::Specifier(_) => Interpreter::CPython,
+            VersionRequest::Latest(interpreter) => *interpreter,
+            VersionRequest::Release(version, _) => version.interpreter,
+        })
+        .collect::<Vec<_>>();
+    interpreters.sort_unstable();
+    interpreters.dedup();
+    let mut releases = vec![];
+    for interpreter in interpreters {
+        releases.extend(
+            available_releases(interpreter, refresh)?
+                .into_iter()
+                .filter(|python| requested(python)),
+        );
     }
-    match python.debug || python.freethreaded {
-        false => extract_tar_gz(&path, &python_dir)?,
-        true => {
-            extract_tar_zst(&path, &python_dir)?;
-            move_install(&python_dir)?;
+    sort_releases(&mut releases, order);
+    print_releases(&releases, format);
+    Ok(())
+}
+
+/// Where a downloaded Python came from, stored alongside it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PythonMetadata {
+    /// The exact version downloaded, which may be newer than the requested x.y version.
+    pub release: Version,
+    pub release_tag: String,
+    pub url: Url,
+    pub installed_at: i64,
+    pub platform: String,
+}
+
+impl PythonMetadata {
+    /// The name of the archive the Python was extracted from.
+    fn archive_name(&self) -> Option<String> {
+        self.url.path_segments()?.next_back().map(str::to_string)
+    }
+}
+
+#[derive(Debug)]
+pub struct InstalledPython {
+    pub version: Version,
+    /// Missing for Pythons downloaded before lilyenv recorded metadata.
+    pub metadata: Option<PythonMetadata>,
+}
+
+fn write_metadata(python: &Python, python_dir: &Path) -> Result<(), Error> {
+    let metadata = PythonMetadata {
+        release: python.version,
+        release_tag: python.release_tag.clone(),
+        url: mirror_url(python, &python.url)?,
+        installed_at: chrono::Utc::now().timestamp(),
+        platform: target_platform().to_string(),
+    };
+    write_atomic(
+        &python_metadata_file(python_dir),
+        serde_json::to_string(&metadata).expect("Metadata can always be serialized."),
+    )?;
+    Ok(())
+}
+
+fn read_metadata(python_dir: &Path) -> Option<PythonMetadata> {
+    let metadata = std::fs::read_to_string(python_metadata_file(python_dir)).ok()?;
+    serde_json::from_str(&metadata).ok()
+}
+
+/// The Pythons installed for this user or shared with every user.
+pub fn installed_pythons() -> Result<Vec<InstalledPython>, Error> {
+    let mut versions = vec![];
+    for dir in [shared_pythons_dir(), pythons_dir()] {
+        let pythons = match std::fs::read_dir(dir) {
+            Ok(pythons) => pythons,
+            Err(err) => match err.kind() {
+                std::io::ErrorKind::NotFound => continue,
+                _ => return Err(err)?,
+            },
+        };
+        versions.extend(
+            pythons
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .filter(|python| python.path().is_dir())
+                .filter_map(|python| {
+                    Some(InstalledPython {
+                        version: python.file_name().to_str()?.parse::<Version>().ok()?,
+                        metadata: read_metadata(&python.path()),
+                    })
+                }),
+        );
+    }
+    // A shared Python is used instead of the user's own one.
+    versions.sort_by_key(|python| python.version);
+    versions.dedup_by_key(|python| python.version);
+    Ok(versions)
+}
+
+pub fn print_installed_pythons() -> Result<(), Error> {
+    for python in installed_pythons()? {
+        let version = python.version;
+        match python.metadata {
+            Some(metadata) => {
+                let installed_at = chrono::DateTime::from_timestamp(metadata.installed_at, 0)
+                    .map(|installed_at| installed_at.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default();
+                anstream::println!(
+                    "{} {RELEASE_TAG}(installed {}, release {}, on {installed_at}){RELEASE_TAG:#}",
+                    styled_version(&version),
+                    metadata.release,
+                    metadata.release_tag
+                );
+            }
+            None => anstream::println!(
+                "{} {RELEASE_TAG}(installed){RELEASE_TAG:#}",
+                styled_version(&version)
+            ),
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Outdated {
+    version: String,
+    installed: String,
+    available: String,
+    release_tag: String,
+}
+
+/// List the installed Pythons with a newer bugfix release than the one they were installed from,
+/// which `lilyenv upgrade` would install.
+pub fn print_outdated(format: OutputFormat, refresh: bool) -> Result<(), Error> {
+    let mut releases = std::collections::BTreeMap::new();
+    let mut outdated = vec![];
+    for python in installed_pythons()? {
+        let Some(metadata) = python.metadata else {
+            log::warn!(
+                "Skipping Python {}, as lilyenv didn't record which release it was installed from.",
+                python.version
+            );
+            continue;
+        };
+        let interpreter = python.version.interpreter;
+        let releases = match releases.entry(interpreter) {
+            std::collections::btree_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::btree_map::Entry::Vacant(entry) => {
+                entry.insert(available_releases(interpreter, refresh)?)
+            }
+        };
+        let newest = releases
+            .iter()
+            .filter(|release: &&Python| matches(&python.version, &release.version))
+            .max_by(|a, b| {
+                (a.version_key(), &a.release_tag).cmp(&(b.version_key(), &b.release_tag))
+            });
+        let installed = version_key(&metadata.release, &metadata.release_tag);
+        if let Some(newest) = newest.filter(|newest| newest.version_key() > installed) {
+            outdated.push(Outdated {
+                version: python.version.to_string(),
+                installed: metadata.release.to_string(),
+                available: newest.version.to_string(),
+                release_tag: newest.release_tag.clone(),
+            });
+        }
+    }
+    match format {
+        OutputFormat::Text => {
+            for python in &outdated {
+                anstream::println!(
+                    "{VERSION}{}{VERSION:#} installed, {VERSION}{}{VERSION:#} available {RELEASE_TAG}({}){RELEASE_TAG:#}",
+                    python.installed,
+                    python.available,
+                    python.release_tag
+                );
+            }
+            if let Some(python) = outdated.first() {
+                log::info!(
+                    "Run `lilyenv upgrade {}` to install the newer release.",
+                    python.version
+                );
+            }
         }
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&outdated).expect("Versions can always be serialized.")
+        ),
+    }
+    Ok(())
+}
+
+/// Which versions `lilyenv versions` lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionsFilter {
+    All,
+    Installed,
+    Available,
+}
+
+/// List installed and available versions together, each once with whether it is installed and
+/// whether it can be downloaded. Installed Pythons are listed by the exact release they were
+/// installed from, if lilyenv recorded it.
+pub fn print_versions(filter: VersionsFilter, refresh: bool) -> Result<(), Error> {
+    // (installed, available) for each version, in Version order.
+    let mut versions = std::collections::BTreeMap::<Version, (bool, bool)>::new();
+    for python in installed_pythons()? {
+        let version = python
+            .metadata
+            .map_or(python.version, |metadata| metadata.release);
+        versions.entry(version).or_default().0 = true;
+    }
+    if filter != VersionsFilter::Installed {
+        for python in all_available_releases(refresh)? {
+            versions.entry(python.version).or_default().1 = true;
+        }
+    }
+    for (version, (installed, available)) in versions {
+        let shown = match filter {
+            VersionsFilter::All => true,
+            VersionsFilter::Installed => installed,
+            VersionsFilter::Available => available,
+        };
+        if !shown {
+            continue;
+        }
+        let status = [(installed, "[installed]"), (available, "[available]")]
+            .into_iter()
+            .filter_map(|(flag, label)| flag.then_some(label))
+            .collect::<Vec<_>>()
+            .join(" ");
+        anstream::println!(
+            "{} {RELEASE_TAG}{status}{RELEASE_TAG:#}",
+            styled_version(&version)
+        );
+    }
+    Ok(())
+}
+
+pub fn uninstall_python(version: &Version, force: bool) -> Result<(), Error> {
+    // Only `--system` removes a shared Python, which other users may be using.
+    let python_dir = match system() {
+        true => python_dir(version),
+        false => pythons_dir().join(version.to_string()),
     };
-    fixup_sysconfig_paths(&python_dir)?;
+    if !python_dir.exists() {
+        return Err(Error::NotDownloaded(version.to_string()));
+    }
+    let projects = projects_using(version)?;
+    if !projects.is_empty() {
+        match force {
+            false => {
+                return Err(Error::PythonInUse {
+                    version: version.to_string(),
+                    projects: projects.join(", "),
+                })
+            }
+            true => log::warn!(
+                "virtualenvs for {} will no longer work.",
+                projects.join(", ")
+            ),
+        }
+    }
+    let size = dir_size(&python_dir)?;
+    std::fs::remove_dir_all(&python_dir).with_path(&python_dir)?;
+    log::info!("Removed Python {version}, freeing {}.", format_size(size));
     Ok(())
 }
 
-fn download_pypy(version: &Version, upgrade: bool) -> Result<(), Error> {
+/// The release installed for `version` and its archive's SHA-256 checksum, from the release's
+/// published checksum or else the downloaded archive, for `lilyenv lock`.
+pub fn installed_release(version: &Version) -> Result<(PythonMetadata, Option<String>), Error> {
     let python_dir = python_dir(version);
-    if !upgrade && python_dir.exists() {
+    if !is_downloaded(&python_dir).with_path(&python_dir)? {
+        return Err(Error::NotDownloaded(version.to_string()));
+    }
+    let metadata =
+        read_metadata(&python_dir).ok_or_else(|| Error::UnknownRelease(version.to_string()))?;
+    let published = match offline() {
+        true => None,
+        false => {
+            let python = find_release(&metadata.release, Some(&metadata.release_tag), false)?;
+            match &python.sha256_url {
+                Some(url) => Some(expected_checksum(mirror_url(&python, url)?)?),
+                None => None,
+            }
+        }
+    };
+    let sha256 = match published {
+        Some(sha256) => Some(sha256),
+        None => {
+            let archive = metadata
+                .archive_name()
+                .map(|name| downloads_dir().join(name))
+                .filter(|archive| archive.exists());
+            archive.map(|archive| file_checksum(&archive)).transpose()?
+        }
+    };
+    Ok((metadata, sha256))
+}
+
+/// Install exactly the `release` of `version` from the release with `release_tag`, as recorded by
+/// `lilyenv lock`, replacing an install of any other release. With `sha256`, the archive must
+/// still have that checksum.
+pub fn sync_release(
+    version: &Version,
+    release: &Version,
+    release_tag: &str,
+    sha256: Option<&str>,
+    refresh: bool,
+) -> Result<(), Error> {
+    let python_dir = python_dir(version);
+    let installed = read_metadata(&python_dir).is_some_and(|metadata| {
+        metadata.release == *release && metadata.release_tag == release_tag
+    });
+    if installed {
+        log::debug!("Python {version} is already {release} from {release_tag}");
         return Ok(());
     }
+    let python = find_release(release, Some(release_tag), refresh)?;
+    if python.version != *release {
+        return Err(Error::VersionNotFound(format!("{release}@{release_tag}")));
+    }
+    let Some(expected) = sha256 else {
+        download_python_release(version, Some(release_tag), false, refresh)?;
+        return Ok(());
+    };
+    let check = |actual: String| match actual == expected {
+        true => Ok(()),
+        false => Err(Error::LockedChecksumMismatch {
+            release: format!("{release}@{release_tag}"),
+            expected: expected.to_string(),
+            actual,
+        }),
+    };
+    let archive = downloads_dir().join(&python.name);
+    // Downloads are verified against the published checksum, so it must be the locked one.
+    let checked = match (&python.sha256_url, offline()) {
+        (Some(url), false) => check(expected_checksum(mirror_url(&python, url)?)?).map(|_| true),
+        _ if archive.exists() => check(file_checksum(&archive)?).map(|_| true),
+        _ => Ok(false),
+    }?;
+    download_python_release(version, Some(release_tag), false, refresh)?;
+    // Without a published checksum, only the downloaded archive can be checked.
+    if !checked && archive.exists() {
+        if let Err(err) = check(file_checksum(&archive)?) {
+            std::fs::remove_dir_all(&python_dir).with_path(&python_dir)?;
+            return Err(err);
+        }
+    }
+    Ok(())
+}
 
-    let downloads = downloads_dir();
-    std::fs::create_dir_all(&downloads)?;
+/// Remove a Python and the archive it was extracted from, then download and install it again, to
+/// repair a broken install. A Python that isn't installed is just downloaded.
+pub fn reinstall_python(version: &Version, refresh: bool) -> Result<(), Error> {
+    // Like `uninstall_python`, only `--system` replaces a shared Python.
+    let python_dir = match system() {
+        true => python_dir(version),
+        false => pythons_dir().join(version.to_string()),
+    };
+    if python_dir.exists() {
+        // Offline, the archive is the only way to install the Python again.
+        if !offline() {
+            let archive = read_metadata(&python_dir)
+                .and_then(|metadata| metadata.archive_name())
+                .map(|name| downloads_dir().join(name));
+            if let Some(archive) = archive.filter(|archive| archive.exists()) {
+                std::fs::remove_file(&archive).with_path(&archive)?;
+            }
+        }
+        std::fs::remove_dir_all(&python_dir).with_path(&python_dir)?;
+        log::info!("Removed Python {version}");
+    }
+    download_python(version, false, refresh)?;
+    let projects = projects_using(version)?;
+    if !projects.is_empty() {
+        log::warn!(
+            "Virtualenvs for {} use Python {version}. If they don't work, recreate them with `lilyenv virtualenv --force`.",
+            projects.join(", ")
+        );
+    }
+    Ok(())
+}
 
-    let python = match pypy_releases()?
-        .into_iter()
-        .find(|python| python.version.compatible(version))
-    {
-        Some(python) => python,
-        None => {
-            return Err(Error::VersionNotFound(version.to_string()));
+fn downloads() -> Result<Vec<std::path::PathBuf>, Error> {
+    let mut paths = match std::fs::read_dir(downloads_dir()) {
+        Ok(entries) => entries
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>, std::io::Error>>()?,
+        Err(err) => match err.kind() {
+            std::io::ErrorKind::NotFound => vec![],
+            _ => return Err(err)?,
+        },
+    };
+    paths.sort_unstable();
+    Ok(paths)
+}
+
+/// Remove downloaded archives, and the cached releases list if `releases` is set.
+pub fn clean(releases: bool, dry_run: bool) -> Result<(), Error> {
+    let mut paths = without_kept(downloads()?)?;
+    if releases {
+        for cache in [
+            releases_cache_file(),
+            pypy_cache_file(),
+            pypy_index_cache_file(),
+        ] {
+            if cache.exists() {
+                paths.push(cache);
+            }
         }
+    }
+    remove_paths(paths, dry_run)
+}
+
+/// Parse an age like `30d`, in hours, days or weeks.
+pub fn parse_age(age: &str) -> Result<std::time::Duration, String> {
+    let invalid = || format!("{age} is not an age like 12h, 30d or 2w");
+    let (number, unit) = age.split_at(age.trim_end_matches(char::is_alphabetic).len());
+    let number = number.parse::<u64>().map_err(|_| invalid())?;
+    let hours = match unit {
+        "h" => 1,
+        "d" => 24,
+        "w" => 24 * 7,
+        _ => return Err(invalid()),
     };
-    let path = downloads.join(python.name);
-    if upgrade || !path.exists() {
-        download_file(python.url, &path)?;
+    // An age too large to count in seconds would otherwise wrap around to a tiny one.
+    let seconds = number.checked_mul(hours * 60 * 60).ok_or_else(invalid)?;
+    Ok(std::time::Duration::from_secs(seconds))
+}
+
+/// Remove downloaded archives last modified more than `older_than` ago, keeping recent ones for
+/// reinstalling quickly.
+pub fn prune(older_than: std::time::Duration, dry_run: bool) -> Result<(), Error> {
+    let mut paths = vec![];
+    for path in without_kept(downloads()?)? {
+        // A modified time in the future counts as recent.
+        let age = path.metadata()?.modified()?.elapsed().unwrap_or_default();
+        if age > older_than {
+            paths.push(path);
+        }
+    }
+    remove_paths(paths, dry_run)
+}
+
+/// Downloaded archives no installed Python was extracted from. Pythons installed before lilyenv
+/// recorded metadata keep any archive of a matching version.
+fn orphaned_downloads() -> Result<Vec<std::path::PathBuf>, Error> {
+    let installed = installed_pythons()?;
+    let referenced = installed
+        .iter()
+        .filter_map(|python| python.metadata.as_ref())
+        .filter_map(PythonMetadata::archive_name)
+        .collect::<Vec<_>>();
+    let unrecorded = installed
+        .iter()
+        .filter(|python| python.metadata.is_none())
+        .map(|python| python.version)
+        .collect::<Vec<_>>();
+    let unrecorded_archives = downloaded_releases()?
+        .into_iter()
+        .filter(|release| {
+            unrecorded
+                .iter()
+                .any(|version| matches(version, &release.version))
+        })
+        .map(|release| release.name)
+        .collect::<Vec<_>>();
+    Ok(downloads()?
+        .into_iter()
+        .filter(|path| {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            // Partial downloads may still be in progress.
+            !name.ends_with(".part")
+                && !referenced.iter().any(|archive| archive == name)
+                && !unrecorded_archives.iter().any(|archive| archive == name)
+        })
+        .collect())
+}
+
+/// Remove archives no installed Python was extracted from and virtualenvs whose base interpreter
+/// has gone.
+pub fn gc(dry_run: bool) -> Result<(), Error> {
+    let mut paths = without_kept(orphaned_downloads()?)?;
+    let broken = broken_virtualenvs()?;
+    paths.extend(broken.iter().map(|(_, virtualenv)| virtualenv.clone()));
+    remove_paths(paths, dry_run)?;
+    if !dry_run {
+        for (project, _) in broken {
+            remove_empty_project(&project)?;
+        }
     }
-    extract_tar_bz2(&path, &python_dir)?;
     Ok(())
 }
 
-fn download_file(url: Url, target: &Path) -> Result<(), Error> {
-    let client = reqwest::blocking::Client::builder()
-        .user_agent("lilyenv")
-        .build()?;
-    let response = client.get(url).send()?;
-    let mut file = File::create(target)?;
-    let mut content = std::io::Cursor::new(response.bytes()?);
-    std::io::copy(&mut content, &mut file)?;
+fn remove_paths(paths: Vec<std::path::PathBuf>, dry_run: bool) -> Result<(), Error> {
+    let mut freed = 0;
+    for path in paths {
+        let size = match path.is_dir() {
+            true => dir_size(&path)?,
+            false => path.metadata()?.len(),
+        };
+        freed += size;
+        match dry_run {
+            true => println!("Would remove {} ({})", path.display(), format_size(size)),
+            false => {
+                log::debug!("Removing {}", path.display());
+                match path.is_dir() {
+                    true => std::fs::remove_dir_all(&path).with_path(&path)?,
+                    false => std::fs::remove_file(&path).with_path(&path)?,
+                }
+            }
+        }
+    }
+    match dry_run {
+        true => println!("Would free {}.", format_size(freed)),
+        false => log::info!("Freed {}.", format_size(freed)),
+    }
     Ok(())
 }
 
-fn extract_tar_gz(source: &Path, target: &Path) -> Result<(), std::io::Error> {
-    let tar_gz = File::open(source)?;
-    let tar = GzDecoder::new(tar_gz);
-    let mut archive = Archive::new(tar);
-    archive.unpack(target)?;
+/// Remove every downloaded Python, virtualenv and cached file, asking first unless `yes` is set.
+pub fn purge(yes: bool) -> Result<(), Error> {
+    let dirs = [pythons_dir(), virtualenvs_dir(), cache_dir()];
+    if let Some(active) = std::env::var_os("VIRTUAL_ENV") {
+        if Path::new(&active).starts_with(virtualenvs_dir()) {
+            return Err(Error::VirtualenvActive(
+                active.to_string_lossy().into_owned(),
+            ));
+        }
+    }
+    if !yes {
+        eprintln!("This will remove everything in:");
+        for dir in &dirs {
+            eprintln!("  {}", dir.display());
+        }
+        eprint!("Continue? [y/N] ");
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            log::info!("Nothing was removed.");
+            return Ok(());
+        }
+    }
+
+    let mut freed = 0;
+    for dir in &dirs {
+        if dir.exists() {
+            freed += dir_size(dir)?;
+            log::debug!("Removing {}", dir.display());
+            std::fs::remove_dir_all(dir).with_path(dir)?;
+        }
+    }
+    for dir in &dirs {
+        std::fs::create_dir_all(dir).with_path(dir)?;
+    }
+    log::info!("Freed {}.", format_size(freed));
+    Ok(())
+}
+
+fn download_cpython(
+    version: &Version,
+    release_tag: Option<&str>,
+    upgrade: bool,
+    refresh: bool,
+) -> Result<Option<Download>, Error> {
+    let python_dir = python_dir(version);
+    if !upgrade && is_downloaded(&python_dir)? {
+        log::debug!("Python {version} is already in {}", python_dir.display());
+        return Ok(None);
+    }
+
+    let python = find_release(version, release_tag, upgrade || refresh)?;
+    let downloaded = install(&python, &python_dir, upgrade)?;
+    // Full archives keep the install in `python/install` alongside the build artifacts.
+    if python_dir.join("python").join("install").is_dir() {
+        move_install(&python_dir)?;
+    }
+    fixup_sysconfig_paths(&python_dir)?;
+    check_interpreter(version, &python_dir)?;
+    write_metadata(&python, &python_dir)?;
+    report_installed(version, &python_dir)?;
+    Ok(Some(Download { python, downloaded }))
+}
+
+/// The release that downloading `version` would install, limited to the release with
+/// `release_tag` if one is given.
+fn find_release(
+    version: &Version,
+    release_tag: Option<&str>,
+    refresh: bool,
+) -> Result<Python, Error> {
+    if offline() {
+        return offline_python(version, release_tag);
+    }
+    let python = match version.interpreter {
+        Interpreter::CPython => return find_cpython(version, release_tag, refresh),
+        Interpreter::PyPy => newest_compatible(tagged(pypy_releases()?, release_tag), version),
+        Interpreter::GraalPy => {
+            let releases = block_on(graalpy_releases())?;
+            newest_compatible(tagged(releases, release_tag), version)
+        }
+    };
+    python.ok_or_else(|| Error::VersionNotFound(release_name(version, release_tag)))
+}
+
+/// The releases with `release_tag`, or all of them if none is given.
+fn tagged(releases: Vec<Python>, release_tag: Option<&str>) -> Vec<Python> {
+    match release_tag {
+        Some(release_tag) => releases
+            .into_iter()
+            .filter(|python| python.release_tag == release_tag)
+            .collect(),
+        None => releases,
+    }
+}
+
+/// How to refer to `version` from the release with `release_tag` in errors.
+fn release_name(version: &Version, release_tag: Option<&str>) -> String {
+    match release_tag {
+        Some(release_tag) => format!("{version}@{release_tag}"),
+        None => version.to_string(),
+    }
+}
+
+fn find_cpython(
+    version: &Version,
+    release_tag: Option<&str>,
+    refresh: bool,
+) -> Result<Python, Error> {
+    let find = |refresh| -> Result<Option<Python>, Error> {
+        let releases = block_on(cpython_releases(refresh))?;
+        // No CPython builds at all means the platform isn't supported, not that the version
+        // doesn't exist.
+        if releases.is_empty() {
+            return Err(Error::Platform(target_platform().to_string()));
+        }
+        Ok(newest_compatible(tagged(releases, release_tag), version))
+    };
+    let not_found = || Error::VersionNotFound(release_name(version, release_tag));
+    match find(refresh)? {
+        Some(python) => Ok(python),
+        // The cached releases may predate the requested version.
+        None if !refresh => find(true)?.ok_or_else(not_found),
+        None => Err(not_found()),
+    }
+}
+
+/// The newest release of `version` with an archive in the downloads directory.
+fn offline_python(version: &Version, release_tag: Option<&str>) -> Result<Python, Error> {
+    newest_compatible(tagged(downloaded_releases()?, release_tag), version)
+        .ok_or_else(|| Error::OfflineUnavailable(release_name(version, release_tag)))
+}
+
+fn download_pypy(
+    version: &Version,
+    release_tag: Option<&str>,
+    upgrade: bool,
+) -> Result<Option<Download>, Error> {
+    let python_dir = python_dir(version);
+    if !upgrade && python_dir.exists() {
+        return Ok(None);
+    }
+
+    let python = find_release(version, release_tag, false)?;
+    let downloaded = install(&python, &python_dir, upgrade)?;
+    check_interpreter(version, &python_dir)?;
+    write_metadata(&python, &python_dir)?;
+    report_installed(version, &python_dir)?;
+    Ok(Some(Download { python, downloaded }))
+}
+
+fn download_graalpy(
+    version: &Version,
+    release_tag: Option<&str>,
+    upgrade: bool,
+) -> Result<Option<Download>, Error> {
+    let python_dir = python_dir(version);
+    if !upgrade && python_dir.exists() {
+        return Ok(None);
+    }
+
+    let python = find_release(version, release_tag, false)?;
+    let downloaded = install(&python, &python_dir, upgrade)?;
+    check_interpreter(version, &python_dir)?;
+    write_metadata(&python, &python_dir)?;
+    report_installed(version, &python_dir)?;
+    Ok(Some(Download { python, downloaded }))
+}
+
+/// Say where the interpreter ended up, as the data directory isn't somewhere users would guess.
+fn report_installed(version: &Version, python_dir: &Path) -> Result<(), Error> {
+    let install = find_interpreter_root(python_dir)?.unwrap_or_else(|| python_dir.to_path_buf());
+    log::info!(
+        "Installed Python {version} to {}",
+        python_executable(&install).display()
+    );
     Ok(())
 }
 
-fn extract_tar_zst(source: &Path, target: &Path) -> Result<(), std::io::Error> {
-    let tar_zst = File::open(source)?;
-    let tar = ZstDecoder::new(tar_zst)?;
-    let mut archive = Archive::new(tar);
-    archive.unpack(target)?;
+/// Run the extracted interpreter to check it works and is the version that was asked for,
+/// removing it if not so that it is downloaded again next time.
+fn check_interpreter(version: &Version, python_dir: &Path) -> Result<(), Error> {
+    let reason = match interpreter_problem(version, python_dir) {
+        Ok(None) => return Ok(()),
+        Ok(Some(reason)) => reason,
+        Err(err) => err.to_string(),
+    };
+    std::fs::remove_dir_all(python_dir).with_path(python_dir)?;
+    Err(Error::BrokenInterpreter {
+        version: version.to_string(),
+        path: python_dir.display().to_string(),
+        reason,
+    })
+}
+
+/// Why the interpreter in `python_dir` doesn't work as `version`, if it doesn't.
+pub fn interpreter_problem(version: &Version, python_dir: &Path) -> Result<Option<String>, Error> {
+    let install = match find_interpreter_root(python_dir)? {
+        Some(install) => install,
+        None => return Ok(Some("no Python executable was found".to_string())),
+    };
+    let actual = match reported_version(&python_executable(&install)) {
+        Ok(actual) => actual,
+        Err(reason) => return Ok(Some(reason)),
+    };
+    // GraalPy versions are GraalPy's own release numbers, so can't be compared to the Python
+    // version it reports.
+    if version.interpreter == Interpreter::GraalPy {
+        return Ok(None);
+    }
+    // PyPy's bugfix version is the PyPy release, not the Python one it reports.
+    let bugfix_matches = match version.interpreter {
+        Interpreter::CPython => version.bugfix.is_none() || version.bugfix == actual.bugfix,
+        _ => true,
+    };
+    match (actual.major, actual.minor) == (version.major, version.minor) && bugfix_matches {
+        true => Ok(None),
+        false => Ok(Some(format!("it reports Python {actual}"))),
+    }
+}
+
+/// Run `executable --version` and parse the Python version it reports, or describe why it could
+/// not be.
+pub fn reported_version(executable: &Path) -> Result<Version, String> {
+    let output = std::process::Command::new(executable)
+        .arg("--version")
+        .output()
+        .map_err(|err| format!("could not run {}: {err}", executable.display()))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} --version failed:\n{}",
+            executable.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    // Very old Pythons print their version to stderr.
+    let reported = match output.stdout.is_empty() {
+        true => String::from_utf8_lossy(&output.stderr).to_string(),
+        false => String::from_utf8_lossy(&output.stdout).to_string(),
+    };
+    log::debug!("{} reports {}", executable.display(), reported.trim());
+    reported
+        .split_whitespace()
+        .nth(1)
+        .and_then(|actual| actual.parse::<Version>().ok())
+        .ok_or_else(|| format!("unexpected version {}", reported.trim()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// The compression of a tar archive, from its file extension.
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Compression::Gzip)
+        } else if name.ends_with(".tar.zst") {
+            Some(Compression::Zstd)
+        } else if name.ends_with(".tar.bz2") {
+            Some(Compression::Bzip2)
+        } else {
+            None
+        }
+    }
+}
+
+/// Install `python` into `python_dir`, streaming the archive straight into extraction if
+/// `stream_downloads` is configured and it hasn't already been downloaded, or always with
+/// `--no-cache`. Returns the size of the archive if it had to be downloaded.
+fn install(python: &Python, python_dir: &Path, upgrade: bool) -> Result<Option<u64>, Error> {
+    let _lock = lock(&python.name, &python.name)?;
+    let downloads = downloads_dir();
+    let path = downloads.join(&python.name);
+    // Offline, the archive is already downloaded and can't be fetched again.
+    let redownload = upgrade && !offline();
+    let stream = (no_cache() && !offline())
+        || (config().stream_downloads == Some(true)
+            && archive_retention() != ArchiveRetention::Keep
+            && (redownload || !path.exists()));
+    if stream {
+        let compression = Compression::from_name(&python.name)
+            .ok_or_else(|| Error::UnsupportedArchive(python.name.clone()))?;
+        stream_install(python, python_dir, compression)?;
+        return Ok(python.size);
+    }
+    std::fs::create_dir_all(&downloads).with_path(&downloads)?;
+    if !redownload && path.exists() {
+        if let Some(problem) = cached_archive_problem(python, &path)? {
+            log::warn!(
+                "The downloaded {} {problem}, downloading it again.",
+                python.name
+            );
+            std::fs::remove_file(&path).with_path(&path)?;
+        }
+    }
+    let downloaded = match redownload || !path.exists() {
+        true => {
+            download_and_verify(python, &path)?;
+            Some(path.metadata().with_path(&path)?.len())
+        }
+        false => None,
+    };
+    check_available_space(python_dir, path.metadata()?.len() * EXTRACTION_FACTOR)?;
+    // The existing install is only replaced once the new one has been extracted.
+    let part = part_dir(python_dir);
+    log::debug!("Extracting {} to {}", path.display(), part.display());
+    let _guard = PartialGuard::new(&part);
+    if part.exists() {
+        std::fs::remove_dir_all(&part).with_path(&part)?;
+    }
+    if let Err(err) = extract_archive(&path, &part) {
+        if part.exists() {
+            std::fs::remove_dir_all(&part).with_path(&part)?;
+        }
+        return Err(err);
+    }
+    replace_dir(&part, python_dir)?;
+    match archive_retention() {
+        ArchiveRetention::Default => {}
+        ArchiveRetention::Keep => set_kept(&python.name, true)?,
+        ArchiveRetention::Delete => {
+            log::debug!("Removing {}", path.display());
+            std::fs::remove_file(&path).with_path(&path)?;
+            set_kept(&python.name, false)?;
+        }
+    }
+    Ok(downloaded)
+}
+
+/// The directory a Python is extracted into before it replaces `python_dir`.
+fn part_dir(python_dir: &Path) -> std::path::PathBuf {
+    let mut part = python_dir.as_os_str().to_owned();
+    part.push(".part");
+    std::path::PathBuf::from(part)
+}
+
+/// Move the extracted `part` directory to `python_dir`, replacing any install already there.
+fn replace_dir(part: &Path, python_dir: &Path) -> Result<(), Error> {
+    if python_dir.exists() {
+        std::fs::remove_dir_all(python_dir).with_path(python_dir)?;
+    }
+    std::fs::rename(part, python_dir).with_path(python_dir)
+}
+
+/// Extract the archive into `<python_dir>.part` as it downloads, only replacing `python_dir` once
+/// the archive is complete and verified.
+fn stream_install(
+    python: &Python,
+    python_dir: &Path,
+    compression: Compression,
+) -> Result<(), Error> {
+    let part = part_dir(python_dir);
+
+    let expected = match &python.sha256_url {
+        Some(url) => Some(expected_checksum(mirror_url(python, url)?)?),
+        None => {
+            log::warn!(
+                "No checksum available for {}, skipping verification.",
+                python.name
+            );
+            None
+        }
+    };
+    let url = mirror_url(python, &python.url)?;
+    log::info!("Downloading {}", python.name);
+    log::debug!("Extracting {url} to {}", part.display());
+    let _guard = PartialGuard::new(&part);
+    let actual = with_retry(|| {
+        // A stream can't be resumed, so start each attempt from scratch.
+        if part.exists() {
+            std::fs::remove_dir_all(&part).with_path(&part)?;
+        }
+        stream_file(url.clone(), &part, compression)
+    })?;
+    if let Some(expected) = expected {
+        if actual != expected {
+            std::fs::remove_dir_all(&part).with_path(&part)?;
+            return Err(Error::ChecksumMismatch { expected, actual });
+        }
+    }
+    replace_dir(&part, python_dir)
+}
+
+/// Hashes everything read through it, so a streamed archive can be verified after extraction.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: std::io::Read> std::io::Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Download `url` and extract it into `target`, returning the archive's SHA-256 checksum.
+fn stream_file(url: Url, target: &Path, compression: Compression) -> Result<String, Error> {
+    let response = client()?.get(url).send()?.error_for_status()?;
+    if let Some(length) = response.content_length() {
+        check_available_space(target, length * EXTRACTION_FACTOR)?;
+    }
+    let progress = progress_bars().add(progress_bar(response.content_length()));
+    let mut reader = HashingReader {
+        inner: progress.wrap_read(response),
+        hasher: Sha256::new(),
+    };
+    unpack(
+        &mut reader,
+        compression,
+        target,
+        &std::cell::Cell::new(false),
+    )?;
+    // Drain any trailing bytes the decoder didn't need, so the whole archive is hashed.
+    std::io::copy(&mut reader, &mut std::io::sink())?;
+    progress.finish_and_clear();
+    Ok(format!("{:x}", reader.hasher.finalize()))
+}
+
+/// The url to download `url` from, using the configured mirror for CPython release assets.
+/// Assets are still found with GitHub's API, only their bytes come from the mirror.
+pub(crate) fn mirror_url(python: &Python, url: &Url) -> Result<Url, Error> {
+    match (&config().download_url, python.version.interpreter) {
+        (Some(mirror), Interpreter::CPython) => mirror_asset_url(mirror, &python.release_tag, url),
+        _ => Ok(url.clone()),
+    }
+}
+
+/// Rewrite the release asset `url` to `<mirror>/<release_tag>/<name>`, checking it still ends
+/// with the asset's name.
+fn mirror_asset_url(mirror: &Url, release_tag: &str, url: &Url) -> Result<Url, Error> {
+    let last_segment = |url: &Url| {
+        url.path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(str::to_string)
+    };
+    let name = last_segment(url).expect("Release asset urls end with the asset name.");
+    // Without a trailing slash, joining would replace the mirror's last path segment.
+    let mut mirror = mirror.clone();
+    if !mirror.path().ends_with('/') {
+        mirror.set_path(&format!("{}/", mirror.path()));
+    }
+    let mirrored = mirror.join(&format!("{release_tag}/{name}"))?;
+    if last_segment(&mirrored).as_ref() != Some(&name) {
+        return Err(Error::MirrorUrl {
+            url: mirrored.to_string(),
+            name,
+        });
+    }
+    Ok(mirrored)
+}
+
+/// Download to `<target>.part`, resuming a previous partial download, and only move it to `target`
+/// once it is complete and verified.
+fn download_and_verify(python: &Python, target: &Path) -> Result<(), Error> {
+    let mut part = target.as_os_str().to_owned();
+    part.push(".part");
+    let part = std::path::PathBuf::from(part);
+
+    let url = mirror_url(python, &python.url)?;
+    log::info!("Downloading {}", python.name);
+    log::debug!("Downloading {url} to {}", part.display());
+    let actual = with_retry(|| download_file(url.clone(), &part))?;
+    match &python.sha256_url {
+        Some(url) => {
+            let expected = expected_checksum(mirror_url(python, url)?)?;
+            if actual != expected {
+                std::fs::remove_file(&part).with_path(&part)?;
+                return Err(Error::ChecksumMismatch { expected, actual });
+            }
+        }
+        None => {
+            log::warn!(
+                "No checksum available for {}, skipping verification.",
+                python.name
+            );
+        }
+    }
+    std::fs::rename(&part, target).with_path(target)?;
     Ok(())
 }
 
-fn extract_tar_bz2(source: &Path, target: &Path) -> Result<(), std::io::Error> {
-    let tar_gz = File::open(source)?;
-    let tar = BzDecoder::new(tar_gz);
-    let mut archive = Archive::new(tar);
-    archive.unpack(target)?;
+/// Why a previously downloaded archive can't be trusted, checked against the release's size and
+/// checksum when they are known.
+fn cached_archive_problem(python: &Python, path: &Path) -> Result<Option<String>, Error> {
+    let size = path.metadata()?.len();
+    match python.size {
+        Some(expected) if size != expected => {
+            return Ok(Some(format!(
+                "is {} instead of {}",
+                format_size(size),
+                format_size(expected)
+            )))
+        }
+        _ => {}
+    }
+    if let (Some(url), false) = (&python.sha256_url, offline()) {
+        if file_checksum(path)? != expected_checksum(mirror_url(python, url)?)? {
+            return Ok(Some("doesn't match its checksum".to_string()));
+        }
+    }
+    Ok(None)
+}
+
+fn expected_checksum(url: Url) -> Result<String, Error> {
+    let expected = with_retry(|| {
+        Ok(client()?
+            .get(url.clone())
+            .send()?
+            .error_for_status()?
+            .text()?)
+    })?;
+    Ok(expected
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase())
+}
+
+fn file_checksum(path: &Path) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path).with_path(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Shared so that concurrent downloads' progress bars stack instead of overwriting each other.
+fn progress_bars() -> &'static MultiProgress {
+    static PROGRESS_BARS: std::sync::OnceLock<MultiProgress> = std::sync::OnceLock::new();
+    PROGRESS_BARS.get_or_init(MultiProgress::new)
+}
+
+fn progress_bar(length: Option<u64>) -> ProgressBar {
+    match length {
+        Some(length) => ProgressBar::new(length).with_style(
+            ProgressStyle::with_template("{bar:40} {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
+                .expect("Could not parse hardcoded progress bar template."),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("{spinner} {bytes} ({bytes_per_sec})")
+                .expect("Could not parse hardcoded progress bar template."),
+        ),
+    }
+}
+
+fn extraction_progress_bar(length: u64) -> ProgressBar {
+    ProgressBar::new(length).with_style(
+        ProgressStyle::with_template("Extracting {bar:40} {percent}% ({elapsed})")
+            .expect("Could not parse hardcoded progress bar template."),
+    )
+}
+
+/// Download `url` to `target`, resuming a partial download, and return the SHA-256 checksum of
+/// the whole file. The checksum is computed as the download streams in, so only a resumed
+/// download's existing bytes are read back.
+fn download_file(url: Url, target: &Path) -> Result<String, Error> {
+    let existing = match target.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+    let request = client()?.get(url.clone());
+    let request = match existing {
+        0 => request,
+        _ => request.header(reqwest::header::RANGE, format!("bytes={existing}-")),
+    };
+    let response = request.send()?;
+    let (response, existing) = match response.status() {
+        reqwest::StatusCode::PARTIAL_CONTENT => (response, existing),
+        // The partial download can't be resumed, so start again.
+        reqwest::StatusCode::RANGE_NOT_SATISFIABLE => (client()?.get(url).send()?, 0),
+        _ => (response, 0),
+    };
+    let response = response.error_for_status()?;
+    if let Some(length) = response.content_length() {
+        check_available_space(target, length)?;
+    }
+    let length = response.content_length().map(|length| existing + length);
+    let progress = progress_bars().add(progress_bar(length));
+    progress.set_position(existing);
+    let mut hasher = Sha256::new();
+    let mut file = match existing {
+        0 => File::create(target).with_path(target)?,
+        _ => {
+            std::io::copy(
+                &mut std::io::Read::take(File::open(target).with_path(target)?, existing),
+                &mut hasher,
+            )?;
+            std::fs::OpenOptions::new()
+                .append(true)
+                .open(target)
+                .with_path(target)?
+        }
+    };
+    let mut reader = HashingReader {
+        inner: progress.wrap_read(response),
+        hasher,
+    };
+    std::io::copy(&mut reader, &mut file)?;
+    progress.finish_and_clear();
+    Ok(format!("{:x}", reader.hasher.finalize()))
+}
+
+/// Extract a tar archive, choosing the decompressor from its file extension.
+fn extract_archive(source: &Path, target: &Path) -> Result<(), Error> {
+    let name = source
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let compression =
+        Compression::from_name(&name).ok_or_else(|| Error::UnsupportedArchive(name.clone()))?;
+    let archive = File::open(source).with_path(source)?;
+    // Progress is how much of the archive has been decompressed, as the extracted size isn't
+    // known until the end.
+    let progress = progress_bars().add(extraction_progress_bar(archive.metadata()?.len()));
+    let corrupt = std::cell::Cell::new(false);
+    let unpacked = unpack(progress.wrap_read(archive), compression, target, &corrupt);
+    progress.finish_and_clear();
+    match unpacked {
+        Err(err) if corrupt.get() => {
+            log::debug!("Could not decompress {}: {err}", source.display());
+            Err(Error::CorruptArchive(source.display().to_string()))
+        }
+        unpacked => unpacked,
+    }
+}
+
+/// Extract `source`, setting `corrupt` if it couldn't be decompressed, as opposed to failing to
+/// write what it contains.
+fn unpack(
+    source: impl std::io::Read,
+    compression: Compression,
+    target: &Path,
+    corrupt: &std::cell::Cell<bool>,
+) -> Result<(), Error> {
+    match compression {
+        Compression::Gzip => unpack_tar(
+            Archive::new(DecodeErrors::new(GzDecoder::new(source), corrupt)),
+            target,
+        ),
+        Compression::Zstd => unpack_tar(
+            Archive::new(DecodeErrors::new(ZstDecoder::new(source)?, corrupt)),
+            target,
+        ),
+        Compression::Bzip2 => unpack_tar(
+            Archive::new(DecodeErrors::new(BzDecoder::new(source), corrupt)),
+            target,
+        ),
+    }
+}
+
+/// Notes when the decompressor it wraps fails, which means the archive is corrupt or truncated.
+struct DecodeErrors<'a, R> {
+    inner: R,
+    corrupt: &'a std::cell::Cell<bool>,
+}
+
+impl<'a, R> DecodeErrors<'a, R> {
+    fn new(inner: R, corrupt: &'a std::cell::Cell<bool>) -> Self {
+        Self { inner, corrupt }
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for DecodeErrors<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf);
+        if read.is_err() {
+            self.corrupt.set(true);
+        }
+        read
+    }
+}
+
+/// Whether `path`, relative to the extraction directory, stays inside it.
+fn stays_inside(path: &Path) -> bool {
+    let mut depth = 0;
+    for component in path.components() {
+        match component {
+            std::path::Component::Normal(_) => depth += 1,
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Whether an archive entry is only needed to test Python or embed it in another program, like
+/// the standard library's test suite or `libpython3.x.a`, which virtualenvs never use.
+fn is_bloat(path: &Path) -> bool {
+    let components = path
+        .components()
+        .filter_map(|component| component.as_os_str().to_str())
+        .collect::<Vec<_>>();
+    // The standard library is lib/python3.x or lib/pypy3.x, or just Lib on Windows.
+    let in_stdlib = |index: usize| match components[..index] {
+        [.., "Lib"] => true,
+        [.., "lib", stdlib] => stdlib.starts_with("python") || stdlib.starts_with("pypy"),
+        _ => false,
+    };
+    let test_suite = components.iter().enumerate().any(|(index, component)| {
+        (*component == "test" && in_stdlib(index)) || *component == "idle_test"
+    });
+    let static_library = components
+        .last()
+        .is_some_and(|name| name.starts_with("libpython") && name.ends_with(".a"));
+    test_suite || static_library
+}
+
+/// Extract every entry, refusing any that would write, or link, outside of `target`.
+fn unpack_tar(mut archive: Archive<impl std::io::Read>, target: &Path) -> Result<(), Error> {
+    std::fs::create_dir_all(target).with_path(target)?;
+    let minimal = minimal();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        if minimal && is_bloat(&path) {
+            continue;
+        }
+        let link = match entry.header().entry_type() {
+            // Symlinks are relative to their own directory, hard links to the archive root.
+            tar::EntryType::Symlink => entry
+                .link_name()?
+                .map(|link| path.parent().unwrap_or(Path::new("")).join(link)),
+            tar::EntryType::Link => entry.link_name()?.map(|link| link.to_path_buf()),
+            _ => None,
+        };
+        if !stays_inside(&path) || link.is_some_and(|link| !stays_inside(&link)) {
+            return Err(Error::UnsafeArchive(path.display().to_string()));
+        }
+        entry.unpack_in(target).with_path(target.join(&path))?;
+    }
     Ok(())
 }
 
 fn fixup_sysconfig_paths(python_dir: &Path) -> Result<(), Error> {
     let root = python_dir.join("python");
-    let lib = root
-        .join("lib")
-        .read_dir()?
-        .collect::<Result<Vec<std::fs::DirEntry>, std::io::Error>>()?
+    let lib = root.join("lib");
+    let entries = match lib.read_dir() {
+        Ok(entries) => entries
+            .collect::<Result<Vec<std::fs::DirEntry>, std::io::Error>>()
+            .with_path(&lib)?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).with_path(&lib),
+    };
+    // Windows builds keep the standard library in `Lib`, with no `_sysconfigdata_` to fix.
+    let Some(lib) = entries
         .into_iter()
-        .find(|dir| dir.file_name().to_str().unwrap().starts_with("python"))
-        .unwrap();
+        .find(|dir| dir.file_name().to_string_lossy().starts_with("python"))
+    else {
+        return Ok(());
+    };
     let sysconfig = lib
         .path()
-        .read_dir()?
+        .read_dir()
+        .with_path(lib.path())?
         .collect::<Result<Vec<std::fs::DirEntry>, std::io::Error>>()?
         .into_iter()
         .find(|dir| {
             dir.file_name()
-                .to_str()
-                .unwrap()
+                .to_string_lossy()
                 .contains("_sysconfigdata_")
         })
-        .unwrap()
+        .ok_or_else(|| Error::SysconfigMissing(lib.path().display().to_string()))?
         .path();
-    let data = std::fs::read_to_string(&sysconfig)?;
-    let install_dir = root.to_str().unwrap();
+    let install_dir = root
+        .to_str()
+        .ok_or_else(|| Error::NonUnicodePath(root.display().to_string()))?;
+    let data = std::fs::read_to_string(&sysconfig).with_path(&sysconfig)?;
     let data = data.replace("'/install", &format!("'{}", install_dir));
     let data = data.replace(" /install", &format!(" {}", install_dir));
     let data = data.replace("=/install", &format!("={}", install_dir));
-    std::fs::write(&sysconfig, data)?;
+    std::fs::write(&sysconfig, data).with_path(&sysconfig)?;
 
     let pkgconfig = root.join("lib").join("pkgconfig");
-    for dir in pkgconfig.read_dir()? {
+    if !pkgconfig.is_dir() {
+        return Ok(());
+    }
+    for dir in pkgconfig.read_dir().with_path(&pkgconfig)? {
         let path = dir?.path();
         if path.is_symlink() {
             continue;
         }
-        let data = std::fs::read_to_string(&path)?;
+        let data = std::fs::read_to_string(&path).with_path(&path)?;
         let data = data.replace("=/install", &format!("={}", install_dir));
-        std::fs::write(&path, data)?;
+        std::fs::write(&path, data).with_path(&path)?;
     }
     Ok(())
 }
 
-fn move_install(python_dir: &Path) -> Result<(), std::io::Error> {
+fn move_install(python_dir: &Path) -> Result<(), Error> {
     let temp = python_dir.join("temp");
     let python_dir = python_dir.join("python");
     let install = python_dir.join("install");
-    std::fs::rename(&install, &temp)?;
-    std::fs::remove_dir_all(&python_dir)?;
-    std::fs::rename(&temp, &python_dir)?;
+    std::fs::rename(&install, &temp).with_path(&install)?;
+    std::fs::remove_dir_all(&python_dir).with_path(&python_dir)?;
+    std::fs::rename(&temp, &python_dir).with_path(&python_dir)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::test_dir;
+
+    #[test]
+    fn test_compression_from_name() {
+        let name = "cpython-3.12.8+20241219-x86_64-unknown-linux-gnu-install_only.tar.gz";
+        assert_eq!(Compression::from_name(name), Some(Compression::Gzip));
+        let name =
+            "cpython-3.13.1+20241219-x86_64-unknown-linux-gnu-freethreaded+pgo+lto-full.tar.zst";
+        assert_eq!(Compression::from_name(name), Some(Compression::Zstd));
+        let name = "pypy3.10-v7.3.17-linux64.tar.bz2";
+        assert_eq!(Compression::from_name(name), Some(Compression::Bzip2));
+        assert_eq!(Compression::from_name("pypy3.10-v7.3.17-win64.zip"), None);
+        assert_eq!(Compression::from_name("python.tar.gz.sha256"), None);
+    }
+
+    #[test]
+    fn test_mirror_asset_url() {
+        let url = Url::parse("https://github.com/astral-sh/python-build-standalone/releases/download/20240713/cpython-3.12.4+20240713-x86_64-unknown-linux-gnu-install_only.tar.gz").unwrap();
+        let expected = "https://mirror.example.com/pbs/20240713/cpython-3.12.4+20240713-x86_64-unknown-linux-gnu-install_only.tar.gz";
+        for mirror in [
+            "https://mirror.example.com/pbs/",
+            "https://mirror.example.com/pbs",
+        ] {
+            let mirror = Url::parse(mirror).unwrap();
+            let mirrored = mirror_asset_url(&mirror, "20240713", &url).unwrap();
+            assert_eq!(mirrored.as_str(), expected);
+        }
+        let mirror = Url::parse("https://mirror.example.com/").unwrap();
+        assert!(matches!(
+            mirror_asset_url(&mirror, "20240713?", &url),
+            Err(Error::MirrorUrl { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_age() {
+        let day = 24 * 60 * 60;
+        assert_eq!(
+            parse_age("30d"),
+            Ok(std::time::Duration::from_secs(30 * day))
+        );
+        assert_eq!(
+            parse_age("2w"),
+            Ok(std::time::Duration::from_secs(14 * day))
+        );
+        assert_eq!(
+            parse_age("12h"),
+            Ok(std::time::Duration::from_secs(day / 2))
+        );
+        for age in [
+            "",
+            "d",
+            "30",
+            "30x",
+            "-1d",
+            "1.5d",
+            "30days",
+            "99999999999999999w",
+        ] {
+            assert!(parse_age(age).is_err(), "{age}");
+        }
+    }
+
+    #[test]
+    fn test_cached_archive_problem() {
+        let temp = test_dir();
+        let dir = temp.path();
+        let name = "cpython-3.12.8+20241219-x86_64-unknown-linux-gnu-install_only.tar.gz";
+        let archive = dir.join(name);
+        std::fs::write(&archive, b"truncated").unwrap();
+        let mut python = Python {
+            name: name.to_string(),
+            url: Url::parse("https://example.com/").unwrap(),
+            version: "3.12.8".parse().unwrap(),
+            release_tag: "20241219".to_string(),
+            debug: false,
+            freethreaded: false,
+            sha256_url: None,
+            size: Some(100),
+            released: None,
+        };
+        assert_eq!(
+            cached_archive_problem(&python, &archive).unwrap(),
+            Some("is 9 B instead of 100 B".to_string())
+        );
+        python.size = Some(9);
+        assert_eq!(cached_archive_problem(&python, &archive).unwrap(), None);
+        python.size = None;
+        assert_eq!(cached_archive_problem(&python, &archive).unwrap(), None);
+    }
+
+    #[test]
+    fn test_find_interpreter_root() {
+        let temp = test_dir();
+        let dir = temp.path();
+        let layout = |root: &str, executables: &[&str]| {
+            let root = dir.join(root);
+            std::fs::create_dir_all(root.join("stray")).unwrap();
+            std::fs::write(root.join("LICENSE"), b"").unwrap();
+            for executable in executables {
+                let executable = python_executable(&root.join(executable));
+                std::fs::create_dir_all(executable.parent().unwrap()).unwrap();
+                std::fs::write(executable, b"").unwrap();
+            }
+            find_interpreter_root(&root).unwrap()
+        };
+        assert_eq!(
+            layout("install_only", &["python"]),
+            Some(dir.join("install_only/python"))
+        );
+        assert_eq!(
+            layout("full", &["python/install"]),
+            Some(dir.join("full/python/install"))
+        );
+        assert_eq!(layout("flat", &[""]), Some(dir.join("flat/")));
+        assert_eq!(
+            layout("several", &["b/install", "c", "a"]),
+            Some(dir.join("several/a"))
+        );
+        assert_eq!(layout("empty", &[]), None);
+    }
+
+    #[test]
+    fn test_fixup_sysconfig_paths() {
+        let temp = test_dir();
+        let dir = temp.path();
+        let windows = dir.join("windows");
+        std::fs::create_dir_all(windows.join("python/Lib")).unwrap();
+        std::fs::write(windows.join("python/Lib/os.py"), b"").unwrap();
+        fixup_sysconfig_paths(&windows).unwrap();
+
+        let unix = dir.join("unix");
+        let lib = unix.join("python/lib/python3.12");
+        std::fs::create_dir_all(&lib).unwrap();
+        assert!(matches!(
+            fixup_sysconfig_paths(&unix),
+            Err(Error::SysconfigMissing(_))
+        ));
+        std::fs::write(
+            lib.join("_sysconfigdata__linux_x86_64-linux-gnu.py"),
+            "{'prefix': '/install'}",
+        )
+        .unwrap();
+        fixup_sysconfig_paths(&unix).unwrap();
+        let data =
+            std::fs::read_to_string(lib.join("_sysconfigdata__linux_x86_64-linux-gnu.py")).unwrap();
+        assert_eq!(
+            data,
+            format!("{{'prefix': '{}'}}", unix.join("python").display())
+        );
+    }
+
+    #[test]
+    fn test_is_bloat() {
+        for path in [
+            "python/lib/python3.12/test/test_os.py",
+            "python/install/lib/python3.13t/test/__init__.py",
+            "python/Lib/test/test_os.py",
+            "pypy3.10-v7.3.17-linux64/lib/pypy3.10/test/test_os.py",
+            "python/lib/python3.12/idlelib/idle_test/test_run.py",
+            "python/lib/python3.12/config-3.12-x86_64-linux-gnu/libpython3.12.a",
+        ] {
+            assert!(is_bloat(Path::new(path)), "{path}");
+        }
+        for path in [
+            "python/bin/python3",
+            "python/lib/python3.12/os.py",
+            "python/lib/python3.12/unittest/__init__.py",
+            "python/lib/python3.12/site-packages/test/__init__.py",
+            "python/lib/libpython3.12.so",
+            "python/test/README",
+        ] {
+            assert!(!is_bloat(Path::new(path)), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_extract_archive() {
+        let temp = test_dir();
+        let dir = temp.path();
+        let archive = dir.join("python.tar.gz");
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            File::create(&archive).unwrap(),
+            flate2::Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "python/README", &b"hello"[..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        extract_archive(&archive, &dir.join("extracted")).unwrap();
+        let extracted = std::fs::read_to_string(dir.join("extracted/python/README")).unwrap();
+        assert_eq!(extracted, "hello");
+
+        let truncated = dir.join("truncated.tar.gz");
+        let bytes = std::fs::read(&archive).unwrap();
+        std::fs::write(&truncated, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(matches!(
+            extract_archive(&truncated, &dir.join("truncated")),
+            Err(Error::CorruptArchive(_))
+        ));
+        let mislabelled = dir.join("mislabelled.tar.gz");
+        std::fs::write(&mislabelled, b"<html>Not found</html>").unwrap();
+        assert!(matches!(
+            extract_archive(&mislabelled, &dir.join("mislabelled")),
+            Err(Error::CorruptArchive(_))
+        ));
+
+        let unsupported = dir.join("python.zip");
+        std::fs::write(&unsupported, b"").unwrap();
+        assert!(matches!(
+            extract_archive(&unsupported, &dir.join("extracted")),
+            Err(Error::UnsupportedArchive(_))
+        ));
+    }
+
+    fn tar_gz(path: &Path, entries: &[(&str, tar::EntryType, &str)]) {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            File::create(path).unwrap(),
+            flate2::Compression::default(),
+        ));
+        for (name, entry_type, link) in entries {
+            let mut header = tar::Header::new_gnu();
+            // Write the name directly, as `set_path` refuses the unsafe paths being tested.
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o755);
+            header.set_size(0);
+            if !link.is_empty() {
+                header.set_link_name(link).unwrap();
+            }
+            header.set_cksum();
+            builder.append(&header, &b""[..]).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn test_extract_archive_refuses_escaping_entries() {
+        let temp = test_dir();
+        let dir = temp.path();
+        let archive = dir.join("python.tar.gz");
+        let regular = tar::EntryType::Regular;
+        let symlink = tar::EntryType::Symlink;
+
+        tar_gz(
+            &archive,
+            &[
+                ("python/bin/python3.12", regular, ""),
+                ("python/bin/python3", symlink, "python3.12"),
+                ("python/lib/link", symlink, "../bin/python3.12"),
+            ],
+        );
+        extract_archive(&archive, &dir.join("safe")).unwrap();
+        assert!(dir.join("safe/python/lib/link").exists());
+
+        for entries in [
+            [("python/../../escaped", regular, "")],
+            [("python/escaped", symlink, "/etc/passwd")],
+            [("python/escaped", symlink, "../../escaped")],
+            [("python/escaped", tar::EntryType::Link, "../escaped")],
+        ] {
+            tar_gz(&archive, &entries);
+            assert!(matches!(
+                extract_archive(&archive, &dir.join("unsafe")),
+                Err(Error::UnsafeArchive(_))
+            ));
+        }
+        assert!(!dir.join("escaped").exists());
+    }
+}
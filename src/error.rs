@@ -1,5 +1,7 @@
+use crate::disk::format_size;
 use thiserror::Error;
 
+/// Everything that can go wrong in lilyenv.
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(transparent)]
@@ -12,14 +14,324 @@ pub enum Error {
     Url(#[from] url::ParseError),
     #[error(transparent)]
     Fs(#[from] std::io::Error),
+    #[error("{}: {source}", path.display())]
+    Path {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
     #[error("Could not find {0} to download.")]
     VersionNotFound(String),
     #[error("{0} is not a valid Python version")]
     InvalidVersion(String),
     #[error("Could not parse version and release_tag from {0}.")]
     ParseAsset(String),
-    #[error("{0} is not supported.")]
+    #[error("No builds are available for {0}.")]
     Platform(String),
     #[error(transparent)]
     EnvVar(#[from] std::env::VarError),
+    #[error("No virtualenv found for {0}.")]
+    VirtualenvNotFound(String),
+    #[error("Could not list the packages installed in {0}.")]
+    PipFreeze(String),
+    #[error(
+        "Not enough disk space in {path}: {} required, {} available.",
+        format_size(*required),
+        format_size(*available)
+    )]
+    DiskSpace {
+        path: String,
+        required: u64,
+        available: u64,
+    },
+    #[error("Checksum mismatch: expected {expected}, got {actual}. The download has been removed, please retry.")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("{0} is not a supported shell. Supported shells are bash, zsh, fish, PowerShell and nushell.")]
+    UnsupportedShell(String),
+    #[error("Cannot remove {0} while it is active.")]
+    VirtualenvActive(String),
+    #[error("Python {0} has not been downloaded.")]
+    NotDownloaded(String),
+    #[error(
+        "Python {version} is used by virtualenvs for {projects}. Use --force to remove it anyway."
+    )]
+    PythonInUse { version: String, projects: String },
+    #[error("No version given and no .python-version file found.")]
+    NoVersionFile,
+    #[error("Could not use {0} as a project name. Please specify a project.")]
+    NoProject(String),
+    #[error("pip install failed:\n{0}")]
+    PipInstall(String),
+    #[error("Could not create the virtualenv:\n{stderr}")]
+    VenvCreation { stderr: String },
+    #[error("Could not read config file {0}: {1}")]
+    Config(String, String),
+    #[error("{0} downloads failed.")]
+    DownloadsFailed(usize),
+    #[error("Python {version} in {path} is broken: {reason}")]
+    BrokenInterpreter {
+        version: String,
+        path: String,
+        reason: String,
+    },
+    #[error("Could not use {path} as a Python interpreter: {reason}")]
+    ExternalInterpreter { path: String, reason: String },
+    #[error(
+        "The GitHub API rate limit has been exceeded{}. Set the GITHUB_TOKEN environment variable to raise the limit.",
+        reset.as_ref().map(|reset| format!(" until {reset}")).unwrap_or_default()
+    )]
+    RateLimited { reset: Option<String> },
+    #[error("A request timed out after {0} seconds. Use --timeout or the timeout setting to wait longer.")]
+    Timeout(u64),
+    #[error("{0} is not a supported archive format.")]
+    UnsupportedArchive(String),
+    #[error("{0} checks failed.")]
+    ChecksFailed(usize),
+    #[error("{project} has virtualenvs for {versions}. Please specify a version, or set a default with `lilyenv virtualenv --default`.")]
+    AmbiguousVersion { project: String, versions: String },
+    #[error("Refusing to extract {0}, which would be written outside the Python's directory.")]
+    UnsafeArchive(String),
+    #[error("Python {0} has not been downloaded, so it can't be installed with --offline.")]
+    OfflineUnavailable(String),
+    #[error("Virtualenv {0} already exists.")]
+    VirtualenvExists(String),
+    #[error("Could not read the manifest {path}: {reason}")]
+    InvalidManifest { path: String, reason: String },
+    #[error("Pythons for {0} can't run on this machine, so they can only be downloaded.")]
+    ForeignPlatform(String),
+    #[error("The shell-config --hook hook isn't available for {0}.")]
+    UnsupportedHook(String),
+    #[error("{0:?} is not a valid project name. Project names can't be empty, . or .., or contain / or \\.")]
+    InvalidProject(String),
+    #[error("{0:?} can't name a virtualenv. Names follow the rules for project names and can't be Python versions.")]
+    InvalidVirtualenvName(String),
+    #[error(
+        "Can't rename {project} because it has named virtualenvs ({names}). Remove them first."
+    )]
+    NamedVirtualenvs { project: String, names: String },
+    #[error("The Python virtualenv {virtualenv} was created from is missing from {home}. Recreate the virtualenv with `{command}`.")]
+    BaseInterpreterMissing {
+        virtualenv: String,
+        home: String,
+        command: String,
+    },
+    #[error("The mirrored url {url} doesn't end with the asset name {name}. Check download_url.")]
+    MirrorUrl { url: String, name: String },
+    #[error("{0} is not a virtualenv, as it has no pyvenv.cfg.")]
+    NotAVirtualenv(String),
+    #[error("--output-dir extracts a single Python, but {0} versions were given.")]
+    OutputDirVersions(usize),
+    #[error("{0} already exists and isn't empty. Pass --force to replace it.")]
+    OutputDirExists(String),
+    #[error("{0} names a release, which only lilyenv download accepts.")]
+    ReleaseTagUnsupported(String),
+    #[error("The uv backend was chosen, but uv isn't on PATH.")]
+    UvNotFound,
+    #[error(
+        "{0} isn't a CPython version. Only python-build-standalone's platforms can be listed."
+    )]
+    PlatformsUnavailable(String),
+    #[error("{0} has a lilyenv shell-config block without its end marker. Remove the block and run lilyenv shell-config --install again.")]
+    UnterminatedShellConfig(String),
+    #[error("Python {0} was installed before lilyenv recorded its release. Run lilyenv reinstall {0} first.")]
+    UnknownRelease(String),
+    #[error(
+        "{release} doesn't match the checksum in lilyenv.lock: expected {expected}, got {actual}."
+    )]
+    LockedChecksumMismatch {
+        release: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("The activated shell was stopped after {0} seconds.")]
+    ShellTimeout(u64),
+    #[error("{0} is corrupt or truncated, so it could not be extracted. Delete it, or pass --force to download it again.")]
+    CorruptArchive(String),
+    #[error("Could not read the environment file {path}: {reason}")]
+    InvalidEnvFile { path: String, reason: String },
+    #[error("--interpreter {interpreter} conflicts with the version {version}.")]
+    InterpreterConflict {
+        interpreter: String,
+        version: String,
+    },
+    #[error("The virtualenv at {path} was created with Python {actual}, not {requested}, so it was removed.")]
+    VersionMismatch {
+        requested: String,
+        actual: String,
+        path: String,
+    },
+    #[error("{0} is not valid unicode, so the Python installed there can't be configured for it.")]
+    NonUnicodePath(String),
+    #[error("Could not find the _sysconfigdata_ module in {0} to configure it for its install directory.")]
+    SysconfigMissing(String),
+}
+
+impl Error {
+    /// The process exit code for this error, grouping errors scripts may want to tell apart. 2 is
+    /// also what clap exits with for invalid arguments.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidVersion(_)
+            | Error::NoVersionFile
+            | Error::NoProject(_)
+            | Error::InvalidProject(_)
+            | Error::InvalidVirtualenvName(_)
+            | Error::AmbiguousVersion { .. }
+            | Error::UnsupportedShell(_)
+            | Error::UnsupportedHook(_)
+            | Error::OutputDirVersions(_)
+            | Error::ReleaseTagUnsupported(_)
+            | Error::PlatformsUnavailable(_)
+            | Error::InterpreterConflict { .. } => 2,
+            Error::Config(..)
+            | Error::InvalidManifest { .. }
+            | Error::InvalidEnvFile { .. }
+            | Error::MirrorUrl { .. }
+            | Error::UnterminatedShellConfig(_) => 3,
+            Error::VersionNotFound(_)
+            | Error::VirtualenvNotFound(_)
+            | Error::NotDownloaded(_)
+            | Error::OfflineUnavailable(_)
+            | Error::Platform(_)
+            | Error::ForeignPlatform(_)
+            | Error::NotAVirtualenv(_)
+            | Error::UvNotFound
+            | Error::UnknownRelease(_) => 4,
+            Error::Request(_)
+            | Error::Octocrab(_)
+            | Error::Scraper(_)
+            | Error::RateLimited { .. }
+            | Error::Timeout(_) => 5,
+            Error::Fs(_)
+            | Error::Path { .. }
+            | Error::DiskSpace { .. }
+            | Error::NonUnicodePath(_) => 6,
+            Error::ChecksumMismatch { .. }
+            | Error::LockedChecksumMismatch { .. }
+            | Error::UnsafeArchive(_)
+            | Error::CorruptArchive(_)
+            | Error::UnsupportedArchive(_)
+            | Error::ParseAsset(_) => 7,
+            Error::VirtualenvActive(_)
+            | Error::PythonInUse { .. }
+            | Error::VirtualenvExists(_)
+            | Error::NamedVirtualenvs { .. }
+            | Error::OutputDirExists(_) => 8,
+            Error::PipFreeze(_)
+            | Error::PipInstall(_)
+            | Error::VenvCreation { .. }
+            | Error::BrokenInterpreter { .. }
+            | Error::ExternalInterpreter { .. }
+            | Error::BaseInterpreterMissing { .. }
+            | Error::VersionMismatch { .. }
+            | Error::SysconfigMissing(_) => 9,
+            Error::DownloadsFailed(_) | Error::ChecksFailed(_) => 10,
+            Error::Url(_) | Error::EnvVar(_) | Error::ShellTimeout(_) => 1,
+        }
+    }
+
+    /// The variant's name, like `VersionNotFound`, for scripts to match on. This is the start of
+    /// its derived `Debug` output, so it can't get out of step with the variants.
+    pub fn kind(&self) -> String {
+        format!("{self:?}")
+            .chars()
+            .take_while(char::is_ascii_alphanumeric)
+            .collect()
+    }
+
+    /// The fields of errors that have more than the message, for `--json`.
+    pub fn detail(&self) -> Option<serde_json::Value> {
+        let detail = match self {
+            Error::Path { path, .. } => serde_json::json!({ "path": path }),
+            Error::DiskSpace {
+                path,
+                required,
+                available,
+            } => serde_json::json!({ "path": path, "required": required, "available": available }),
+            Error::ChecksumMismatch { expected, actual } => {
+                serde_json::json!({ "expected": expected, "actual": actual })
+            }
+            Error::LockedChecksumMismatch {
+                release,
+                expected,
+                actual,
+            } => serde_json::json!({ "release": release, "expected": expected, "actual": actual }),
+            Error::PythonInUse { version, projects } => {
+                serde_json::json!({ "version": version, "projects": projects })
+            }
+            Error::BrokenInterpreter {
+                version,
+                path,
+                reason,
+            } => serde_json::json!({ "version": version, "path": path, "reason": reason }),
+            Error::ExternalInterpreter { path, reason } => {
+                serde_json::json!({ "path": path, "reason": reason })
+            }
+            Error::RateLimited { reset } => serde_json::json!({ "reset": reset }),
+            Error::Timeout(seconds) | Error::ShellTimeout(seconds) => {
+                serde_json::json!({ "seconds": seconds })
+            }
+            Error::AmbiguousVersion { project, versions } => {
+                serde_json::json!({ "project": project, "versions": versions })
+            }
+            Error::InvalidManifest { path, reason } | Error::InvalidEnvFile { path, reason } => {
+                serde_json::json!({ "path": path, "reason": reason })
+            }
+            Error::VersionMismatch {
+                requested,
+                actual,
+                path,
+            } => serde_json::json!({ "requested": requested, "actual": actual, "path": path }),
+            Error::InterpreterConflict {
+                interpreter,
+                version,
+            } => serde_json::json!({ "interpreter": interpreter, "version": version }),
+            Error::NamedVirtualenvs { project, names } => {
+                serde_json::json!({ "project": project, "names": names })
+            }
+            Error::BaseInterpreterMissing {
+                virtualenv,
+                home,
+                command,
+            } => serde_json::json!({ "virtualenv": virtualenv, "home": home, "command": command }),
+            Error::MirrorUrl { url, name } => serde_json::json!({ "url": url, "name": name }),
+            Error::DownloadsFailed(failed) | Error::ChecksFailed(failed) => {
+                serde_json::json!({ "failed": failed })
+            }
+            _ => return None,
+        };
+        Some(detail)
+    }
+
+    /// The error as a JSON object with its message, kind and any detail, for `--json`.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = serde_json::json!({ "error": self.to_string(), "kind": self.kind() });
+        if let Some(detail) = self.detail() {
+            json["detail"] = detail;
+        }
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_json() {
+        let error = Error::VersionNotFound("3.99".to_string());
+        assert_eq!(
+            error.to_json(),
+            serde_json::json!({ "error": "Could not find 3.99 to download.", "kind": "VersionNotFound" })
+        );
+        assert_eq!(Error::UvNotFound.kind(), "UvNotFound");
+        let error = Error::ChecksumMismatch {
+            expected: "ab".to_string(),
+            actual: "cd".to_string(),
+        };
+        assert_eq!(error.kind(), "ChecksumMismatch");
+        assert_eq!(
+            error.to_json()["detail"],
+            serde_json::json!({ "expected": "ab", "actual": "cd" })
+        );
+    }
 }
@@ -0,0 +1,229 @@
+use crate::config::config;
+use crate::error::Error;
+use reqwest::blocking::Client;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+static PROXY: OnceLock<String> = OnceLock::new();
+static GITHUB: OnceLock<std::sync::Arc<octocrab::Octocrab>> = OnceLock::new();
+static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+static OFFLINE: OnceLock<bool> = OnceLock::new();
+static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Send all requests through `proxy` instead of the `HTTP(S)_PROXY` environment variables.
+/// Must be called before the first request.
+pub fn set_proxy(proxy: String) {
+    let _ = PROXY.set(proxy);
+}
+
+/// Only install Pythons that are already downloaded, without making any requests.
+pub fn set_offline() {
+    let _ = OFFLINE.set(true);
+}
+
+pub fn offline() -> bool {
+    OFFLINE.get().copied().unwrap_or(false)
+}
+
+/// Override the configured connect and read timeout. Must be called before the first request.
+pub fn set_timeout(timeout: Duration) {
+    let _ = TIMEOUT.set(timeout);
+}
+
+fn timeout() -> Duration {
+    match TIMEOUT.get() {
+        Some(timeout) => *timeout,
+        None => config()
+            .timeout
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TIMEOUT),
+    }
+}
+
+pub fn client() -> Result<&'static Client, Error> {
+    if let Some(client) = CLIENT.get() {
+        return Ok(client);
+    }
+    // reqwest reads `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` itself unless a proxy is given.
+    // The blocking client applies `timeout` to each read, so large downloads aren't cut short.
+    let builder = Client::builder()
+        .user_agent("lilyenv")
+        .connect_timeout(timeout())
+        .timeout(timeout());
+    let builder = match PROXY.get() {
+        Some(proxy) => builder.proxy(reqwest::Proxy::all(proxy)?),
+        None => builder,
+    };
+    let client = builder.build()?;
+    Ok(CLIENT.get_or_init(|| client))
+}
+
+/// The GitHub API client, authenticated with `GITHUB_TOKEN` if it is set to raise the rate limit.
+/// Must be first called from within `block_on`.
+pub fn github() -> Result<std::sync::Arc<octocrab::Octocrab>, Error> {
+    if let Some(github) = GITHUB.get() {
+        return Ok(github.clone());
+    }
+    let builder = octocrab::Octocrab::builder()
+        .set_connect_timeout(Some(timeout()))
+        .set_read_timeout(Some(timeout()));
+    let builder = match std::env::var("GITHUB_TOKEN") {
+        Ok(token) if !token.is_empty() => {
+            log::debug!("Authenticating with GitHub using GITHUB_TOKEN");
+            builder.personal_token(token)
+        }
+        _ => builder,
+    };
+    let github = std::sync::Arc::new(builder.build()?);
+    Ok(GITHUB.get_or_init(|| github).clone())
+}
+
+const DEFAULT_ATTEMPTS: u32 = 3;
+
+fn is_transient_status(status: reqwest::StatusCode) -> bool {
+    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+fn is_rate_limit(err: &Error) -> bool {
+    match err {
+        Error::Octocrab(octocrab::Error::GitHub { source, .. }) => {
+            matches!(source.status_code.as_u16(), 403 | 429)
+                && source.message.to_lowercase().contains("rate limit")
+        }
+        _ => false,
+    }
+}
+
+/// Explain a GitHub rate limit error, including when the limit resets.
+async fn rate_limit_error() -> Error {
+    // Checking the rate limit doesn't count against it.
+    let reset = match github() {
+        Ok(github) => github.ratelimit().get().await.ok(),
+        Err(_) => None,
+    }
+    .and_then(|limit| chrono::DateTime::from_timestamp(limit.resources.core.reset as i64, 0))
+    .map(|reset| {
+        reset
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    });
+    Error::RateLimited { reset }
+}
+
+fn is_timeout(err: &Error) -> bool {
+    match err {
+        Error::Request(err) => err.is_timeout(),
+        Error::Fs(err) => {
+            err.kind() == std::io::ErrorKind::TimedOut
+                || err
+                    .get_ref()
+                    .and_then(|inner| inner.downcast_ref::<reqwest::Error>())
+                    .is_some_and(reqwest::Error::is_timeout)
+        }
+        _ => false,
+    }
+}
+
+/// Replace a timed out request's error with one saying how to wait longer.
+fn explain_timeout<T>(result: Result<T, Error>) -> Result<T, Error> {
+    match result {
+        Err(err) if is_timeout(&err) => Err(Error::Timeout(timeout().as_secs())),
+        result => result,
+    }
+}
+
+fn is_transient(err: &Error) -> bool {
+    if is_rate_limit(err) {
+        return false;
+    }
+    match err {
+        Error::Request(err) => {
+            err.is_timeout()
+                || err.is_connect()
+                || err.is_body()
+                || err.status().is_some_and(is_transient_status)
+        }
+        Error::Octocrab(octocrab::Error::GitHub { source, .. }) => {
+            is_transient_status(source.status_code)
+        }
+        Error::Octocrab(octocrab::Error::Hyper { .. } | octocrab::Error::Service { .. }) => true,
+        // A connection dropped while reading a response body surfaces as an io error.
+        Error::Fs(err) => err
+            .get_ref()
+            .is_some_and(|inner| inner.is::<reqwest::Error>()),
+        _ => false,
+    }
+}
+
+/// Exponential backoff starting at half a second, with up to 50% jitter.
+fn backoff(attempt: u32) -> Duration {
+    let base = Duration::from_millis(500) * 2u32.pow(attempt);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|now| now.subsec_nanos())
+        .unwrap_or_default();
+    base + base.mul_f64(f64::from(nanos % 1000) / 2000.0)
+}
+
+fn attempts() -> u32 {
+    config().retries.unwrap_or(DEFAULT_ATTEMPTS).max(1)
+}
+
+/// Call `f`, retrying transient network errors with exponential backoff.
+pub fn with_retry<T>(mut f: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+    let attempts = attempts();
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Err(err) if attempt + 1 < attempts && is_transient(&err) => {
+                let delay = backoff(attempt);
+                log::warn!("{err} Retrying in {:.1}s.", delay.as_secs_f64());
+                std::thread::sleep(delay);
+                attempt += 1;
+            }
+            result => return explain_timeout(result),
+        }
+    }
+}
+
+/// Await `f`, retrying transient network errors with exponential backoff. Hitting the GitHub rate
+/// limit returns `Error::RateLimited` instead of octocrab's error.
+pub async fn with_retry_async<T, F: std::future::Future<Output = Result<T, Error>>>(
+    mut f: impl FnMut() -> F,
+) -> Result<T, Error> {
+    let attempts = attempts();
+    let mut attempt = 0;
+    loop {
+        match f().await {
+            Err(err) if attempt + 1 < attempts && is_transient(&err) => {
+                let delay = backoff(attempt);
+                log::warn!("{err} Retrying in {:.1}s.", delay.as_secs_f64());
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(err) if is_rate_limit(&err) => return Err(rate_limit_error().await),
+            result => return explain_timeout(result),
+        }
+    }
+}
+
+/// Run `future` on the runtime shared by every async request. Clients like `github()`'s keep
+/// connections on the runtime they were first used on, so each request mustn't get its own.
+pub fn block_on<T>(
+    future: impl std::future::Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    let runtime = match RUNTIME.get() {
+        Some(runtime) => runtime,
+        None => {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            RUNTIME.get_or_init(|| runtime)
+        }
+    };
+    runtime.block_on(future)
+}
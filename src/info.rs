@@ -0,0 +1,327 @@
+use crate::directories::{
+    cache_dir, config_file, downloads_dir, python_dir, pythons_dir, shared_pythons_dir,
+    venv_python, venv_site_packages, virtualenv_dir, virtualenvs_dir,
+};
+use crate::disk::{dir_size, format_size};
+use crate::download::{installed_pythons, interpreter_problem, mirror_url};
+use crate::error::Error;
+use crate::http::{block_on, client, github};
+use crate::releases::{
+    cpython_releases, cpython_repository, pypy_download_url, read_releases_cache, releases_since,
+    target, target_platform,
+};
+use crate::shell::get_shell;
+use crate::version::Version;
+use crate::virtualenvs::{all_virtualenvs, read_pyvenv_cfg};
+use current_platform::CURRENT_PLATFORM;
+
+/// Print where lilyenv keeps things and what it has detected about the environment.
+pub fn print_info() -> Result<(), Error> {
+    let config = config_file();
+    let found = match config.exists() {
+        true => "",
+        false => " (not found)",
+    };
+    println!("Config file: {}{found}", config.display());
+    println!(
+        "Pythons: {} ({} installed)",
+        pythons_dir().display(),
+        installed_pythons()?.len()
+    );
+    let shared = shared_pythons_dir();
+    let found = match shared.exists() {
+        true => "",
+        false => " (not found)",
+    };
+    println!("Shared Pythons: {}{found}", shared.display());
+    println!(
+        "Virtualenvs: {} ({} virtualenvs)",
+        virtualenvs_dir().display(),
+        all_virtualenvs()?.len()
+    );
+    println!("Cache: {}", cache_dir().display());
+    match target_platform() {
+        platform if platform == CURRENT_PLATFORM => println!("Platform: {platform}"),
+        platform => println!("Platform: {CURRENT_PLATFORM} (downloading {platform} builds)"),
+    }
+    if let Some((major, minor)) = target().libc_version {
+        println!("glibc: {major}.{minor}");
+    }
+    match get_shell(None) {
+        Ok(shell) => println!("Shell: {}", shell.trim()),
+        Err(_) => println!("Shell: not detected"),
+    }
+    match std::env::var("GITHUB_TOKEN") {
+        Ok(token) if !token.is_empty() => println!("GITHUB_TOKEN: set"),
+        _ => println!("GITHUB_TOKEN: not set"),
+    }
+    Ok(())
+}
+
+/// Print which builds this lilyenv downloads and what it can install, for bug reports.
+pub fn print_build_info() -> Result<(), Error> {
+    let (owner, repo) = cpython_repository()?;
+    println!("lilyenv {}", env!("CARGO_PKG_VERSION"));
+    println!("Built for: {CURRENT_PLATFORM}");
+    println!("Downloading for: {}", target_platform());
+    println!("CPython releases: https://github.com/{owner}/{repo}");
+    println!("CPython releases since: {}", releases_since().to_rfc3339());
+    println!("Interpreters: cpython, pypy, graalpy");
+    println!("Archive formats: tar.gz, tar.zst, tar.bz2");
+    Ok(())
+}
+
+/// Print what a virtualenv's `pyvenv.cfg` says about its Python and where its site-packages are,
+/// and with `open`, open the site-packages in the file manager.
+pub fn print_virtualenv_info(project: &str, version: &Version, open: bool) -> Result<(), Error> {
+    let virtualenv = virtualenv_dir(project, version);
+    if !virtualenv.exists() {
+        return Err(Error::VirtualenvNotFound(format!("{project} ({version})")));
+    }
+    println!("Virtualenv: {}", virtualenv.display());
+    let cfg = read_pyvenv_cfg(&virtualenv)?;
+    let version_key = ["version", "version_info"]
+        .into_iter()
+        .find_map(|key| cfg.get(key));
+    if let Some(python_version) = version_key {
+        println!("Python version: {python_version}");
+    }
+    // `home` is the base interpreter's directory. Python 3.11 and newer also record the
+    // executable itself.
+    for (key, label) in [
+        ("home", "Base interpreter directory"),
+        ("executable", "Base interpreter"),
+    ] {
+        if let Some(path) = cfg.get(key) {
+            let missing = match std::path::Path::new(path).exists() {
+                true => "",
+                false => " (missing)",
+            };
+            println!("{label}: {path}{missing}");
+        }
+    }
+    if let Some(system) = cfg.get("include-system-site-packages") {
+        println!("System site-packages: {system}");
+    }
+    let site_packages = venv_site_packages(&virtualenv)?;
+    match &site_packages {
+        Some(site_packages) => println!("Site-packages: {}", site_packages.display()),
+        None => println!("Site-packages: not found"),
+    }
+    if open {
+        let site_packages = site_packages.ok_or_else(|| {
+            Error::VirtualenvNotFound(format!("site-packages for {project} ({version})"))
+        })?;
+        open_in_file_manager(&site_packages)?;
+    }
+    Ok(())
+}
+
+fn open_in_file_manager(path: &std::path::Path) -> Result<(), Error> {
+    let opener = match std::env::consts::OS {
+        "macos" => "open",
+        "windows" => "explorer",
+        _ => "xdg-open",
+    };
+    std::process::Command::new(opener).arg(path).spawn()?;
+    Ok(())
+}
+
+/// The size of each file or directory in `dir`, sorted by name. A missing `dir` has none.
+fn entry_sizes(dir: &std::path::Path) -> Result<Vec<(String, u64)>, Error> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err)?,
+    };
+    let mut sizes = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let size = match file_type.is_dir() {
+            true => dir_size(&entry.path())?,
+            false if file_type.is_file() => entry.metadata()?.len(),
+            false => continue,
+        };
+        sizes.push((entry.file_name().to_string_lossy().to_string(), size));
+    }
+    sizes.sort_unstable();
+    Ok(sizes)
+}
+
+/// Print how much disk space the downloaded Pythons, each Project's virtualenvs and the
+/// downloaded archives use.
+pub fn print_disk_usage() -> Result<(), Error> {
+    let sections = [
+        ("Pythons", pythons_dir()),
+        ("Virtualenvs", virtualenvs_dir()),
+        ("Downloads", downloads_dir()),
+    ];
+    let mut total = 0;
+    for (name, dir) in sections {
+        let sizes = entry_sizes(&dir)?;
+        let size = sizes.iter().map(|(_, size)| size).sum::<u64>();
+        total += size;
+        println!("{name}: {} ({})", format_size(size), dir.display());
+        let width = sizes
+            .iter()
+            .map(|(entry, _)| entry.len())
+            .max()
+            .unwrap_or(0);
+        for (entry, size) in sizes {
+            println!("  {entry:width$}  {}", format_size(size));
+        }
+    }
+    println!("Total: {}", format_size(total));
+    Ok(())
+}
+
+enum Check {
+    Pass,
+    Warn,
+    Fail,
+}
+
+fn report(check: Check, message: &str) {
+    let label = match check {
+        Check::Pass => "pass",
+        Check::Warn => "warn",
+        Check::Fail => "FAIL",
+    };
+    println!("{label}: {message}");
+}
+
+/// Check for problems that would stop lilyenv downloading Pythons or activating virtualenvs.
+pub fn doctor() -> Result<(), Error> {
+    let mut failed = 0;
+    let mut fail = |message: &str| {
+        failed += 1;
+        report(Check::Fail, message);
+    };
+
+    match block_on(async { github()?.ratelimit().get().await.map_err(Error::from) }) {
+        Ok(limit) => report(
+            Check::Pass,
+            &format!(
+                "Reached the GitHub API ({} of {} requests left)",
+                limit.resources.core.remaining, limit.resources.core.limit
+            ),
+        ),
+        Err(err) => fail(&format!("Could not reach the GitHub API: {err}")),
+    }
+    let pypy = client().and_then(|client| {
+        Ok(client
+            .get("https://www.pypy.org/download.html")
+            .send()?
+            .error_for_status()?)
+    });
+    match pypy {
+        Ok(_) => report(Check::Pass, "Reached the PyPy download page"),
+        Err(err) => report(
+            Check::Warn,
+            &format!("Could not reach the PyPy download page, so PyPy can't be downloaded: {err}"),
+        ),
+    }
+    match block_on(cpython_releases(false)) {
+        Ok(releases) if !releases.is_empty() => report(
+            Check::Pass,
+            &format!(
+                "Found {} CPython builds for {}",
+                releases.len(),
+                target_platform()
+            ),
+        ),
+        Ok(_) => fail(&format!(
+            "No CPython builds found for {}",
+            target_platform()
+        )),
+        Err(err) => fail(&format!("Could not list CPython builds: {err}")),
+    }
+
+    for python in installed_pythons()? {
+        let version = python.version;
+        match interpreter_problem(&version, &python_dir(&version)) {
+            Ok(None) => report(Check::Pass, &format!("Python {version} runs")),
+            Ok(Some(problem)) => fail(&format!("Python {version} is broken: {problem}")),
+            Err(err) => fail(&format!("Python {version} is broken: {err}")),
+        }
+    }
+
+    for (project, version) in all_virtualenvs()? {
+        let Ok(parsed) = version.parse::<Version>() else {
+            report(
+                Check::Warn,
+                &format!("{project} has a virtualenv for unknown version {version}"),
+            );
+            continue;
+        };
+        // The virtualenv's python is a symlink to the interpreter it was created from.
+        match venv_python(&virtualenv_dir(&project, &parsed)).exists() {
+            true => report(Check::Pass, &format!("Virtualenv {project} ({version}) has its Python")),
+            false => fail(&format!(
+                "Virtualenv {project} ({version}) points at a missing Python. Recreate it with `lilyenv remove-virtualenv {project} {version}` and `lilyenv virtualenv {project} {version}`."
+            )),
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::ChecksFailed(failed)),
+    }
+}
+
+/// How long a request to `url` took to succeed. Pages and assets only need a `HEAD` request.
+fn probe(method: reqwest::Method, url: &str) -> Result<std::time::Duration, Error> {
+    let start = std::time::Instant::now();
+    client()?.request(method, url).send()?.error_for_status()?;
+    Ok(start.elapsed())
+}
+
+/// Check that lilyenv can reach everywhere it downloads from, through the configured proxy and
+/// within the timeout, showing how long each took.
+pub fn check_network() -> Result<(), Error> {
+    let (owner, repo) = cpython_repository()?;
+    // The newest cached release, or else the releases page, stands in for the release assets.
+    let asset = match read_releases_cache().as_deref() {
+        Some([python, ..]) => mirror_url(python, &python.url)?.to_string(),
+        _ => format!("https://github.com/{owner}/{repo}/releases/latest"),
+    };
+    let checks = [
+        (
+            "the GitHub API",
+            reqwest::Method::GET,
+            "https://api.github.com/rate_limit".to_string(),
+        ),
+        ("a CPython release asset", reqwest::Method::HEAD, asset),
+        (
+            "the PyPy download page",
+            reqwest::Method::HEAD,
+            "https://www.pypy.org/download.html".to_string(),
+        ),
+        (
+            "the PyPy downloads",
+            reqwest::Method::HEAD,
+            pypy_download_url(),
+        ),
+    ];
+    let mut failed = 0;
+    for (name, method, url) in checks {
+        match probe(method, &url) {
+            Ok(elapsed) => report(
+                Check::Pass,
+                &format!("Reached {name} in {} ms ({url})", elapsed.as_millis()),
+            ),
+            Err(err) => {
+                failed += 1;
+                report(
+                    Check::Fail,
+                    &format!("Could not reach {name} ({url}): {err}"),
+                );
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::ChecksFailed(failed)),
+    }
+}
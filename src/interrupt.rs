@@ -0,0 +1,133 @@
+//! Remove partly extracted Pythons when lilyenv is interrupted with Ctrl-C, so the next run
+//! doesn't mistake them for complete installs.
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Directories being extracted into, which are incomplete until their guard is dropped.
+static PARTIAL: Mutex<Vec<PathBuf>> = Mutex::new(vec![]);
+
+/// Removes its directory if lilyenv is interrupted while it is alive. Dropping it keeps the
+/// directory, whether the extraction finished or failed.
+pub struct PartialGuard(PathBuf);
+
+impl PartialGuard {
+    pub fn new(path: &Path) -> Self {
+        install_handler();
+        partial().push(path.to_path_buf());
+        Self(path.to_path_buf())
+    }
+}
+
+impl Drop for PartialGuard {
+    fn drop(&mut self) {
+        let mut partial = partial();
+        if let Some(index) = partial.iter().position(|path| *path == self.0) {
+            partial.remove(index);
+        }
+    }
+}
+
+fn partial() -> std::sync::MutexGuard<'static, Vec<PathBuf>> {
+    PARTIAL
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    /// The end of the pipe the signal handler wakes the clean up thread with.
+    static WAKE: AtomicI32 = AtomicI32::new(-1);
+
+    pub fn install_handler() {
+        static INSTALLED: std::sync::Once = std::sync::Once::new();
+        INSTALLED.call_once(|| {
+            let mut fds = [0; 2];
+            // SAFETY: `fds` has room for the two file descriptors `pipe` writes.
+            if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+                log::debug!(
+                    "Could not handle Ctrl-C: {}",
+                    std::io::Error::last_os_error()
+                );
+                return;
+            }
+            WAKE.store(fds[1], Ordering::SeqCst);
+            std::thread::spawn(move || {
+                let mut byte = 0u8;
+                // SAFETY: reads at most one byte into `byte`.
+                let read = unsafe { libc::read(fds[0], (&mut byte as *mut u8).cast(), 1) };
+                if read == 1 {
+                    super::remove_partial();
+                    // Die from the signal as if it hadn't been handled.
+                    // SAFETY: restoring the default disposition and raising a signal are always
+                    // allowed.
+                    unsafe {
+                        libc::signal(libc::SIGINT, libc::SIG_DFL);
+                        libc::raise(libc::SIGINT);
+                    }
+                }
+            });
+            // SAFETY: `on_interrupt` only makes async-signal-safe calls.
+            unsafe {
+                libc::signal(
+                    libc::SIGINT,
+                    on_interrupt as *const () as libc::sighandler_t,
+                )
+            };
+        });
+    }
+
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        // Removing files isn't safe in a signal handler, so leave it to the waiting thread.
+        let byte = 1u8;
+        // SAFETY: `write` is async-signal-safe and writes one byte from `byte`.
+        unsafe { libc::write(WAKE.load(Ordering::SeqCst), (&byte as *const u8).cast(), 1) };
+    }
+}
+
+#[cfg(unix)]
+use unix::install_handler;
+
+/// Ignores Ctrl-C while it is alive, for waiting on a child that handles it itself, like an
+/// activated shell. It must be created after spawning the child, which would otherwise inherit
+/// the ignored disposition.
+pub struct IgnoreInterrupts {
+    #[cfg(unix)]
+    previous: libc::sighandler_t,
+}
+
+impl IgnoreInterrupts {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            // SAFETY: ignoring a signal is always allowed.
+            #[cfg(unix)]
+            previous: unsafe { libc::signal(libc::SIGINT, libc::SIG_IGN) },
+        }
+    }
+}
+
+impl Drop for IgnoreInterrupts {
+    fn drop(&mut self) {
+        // SAFETY: `previous` is the disposition `signal` returned, so is valid to restore.
+        #[cfg(unix)]
+        unsafe {
+            libc::signal(libc::SIGINT, self.previous)
+        };
+    }
+}
+
+#[cfg(not(unix))]
+fn install_handler() {}
+
+fn remove_partial() {
+    for path in partial().drain(..) {
+        if path.exists() {
+            match std::fs::remove_dir_all(&path) {
+                Ok(()) => eprintln!("Removed the partly extracted {}", path.display()),
+                Err(err) => eprintln!("Could not remove {}: {err}", path.display()),
+            }
+        }
+    }
+}
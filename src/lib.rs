@@ -0,0 +1,25 @@
+//! Download standalone Python interpreters and manage virtualenvs built from them.
+//!
+//! This is the library behind the `lilyenv` command line tool. The most commonly needed types and
+//! functions are re-exported at the crate root.
+
+pub mod config;
+mod directories;
+mod disk;
+pub mod download;
+pub mod error;
+pub mod http;
+pub mod info;
+mod interrupt;
+pub mod output;
+pub mod project;
+pub mod releases;
+pub mod shell;
+pub mod version;
+pub mod virtualenvs;
+
+pub use crate::download::download_python;
+pub use crate::error::Error;
+pub use crate::releases::{cpython_releases, Python};
+pub use crate::version::{Interpreter, Version};
+pub use crate::virtualenvs::create_virtualenv;
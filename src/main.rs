@@ -1,37 +1,235 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+use std::io::Write;
 
-mod directories;
-mod download;
-mod error;
-mod releases;
-mod shell;
-mod version;
-mod virtualenvs;
-use crate::download::{download_python, print_available_downloads};
-use crate::error::Error;
-use crate::shell::{print_shell_config, set_shell};
-use crate::version::Version;
-use crate::virtualenvs::{
-    activate_virtualenv, cd_site_packages, create_virtualenv, print_all_versions,
-    print_project_versions, remove_project, remove_virtualenv, set_project_directory,
-    unset_project_directory,
+use lilyenv::config::{load_config, set_system};
+use lilyenv::download::{
+    clean, download_python_to, download_pythons, gc, parse_age, print_available_downloads,
+    print_download_plan, print_installed_pythons, print_matching_downloads, print_outdated,
+    print_release_platforms, print_versions, prune, purge, reinstall_python, resolve_request,
+    set_archive_retention, set_minimal, set_no_cache, uninstall_python, upgrade_python,
+    ArchiveRetention, VersionsFilter,
+};
+use lilyenv::error::Error;
+use lilyenv::http::{set_offline, set_proxy, set_timeout};
+use lilyenv::info::{
+    check_network, doctor, print_build_info, print_disk_usage, print_info, print_virtualenv_info,
+};
+use lilyenv::output::{OutputFormat, ReleaseOrder};
+use lilyenv::project::{
+    default_project, python_version_from_file, requires_python, set_local_version,
+};
+use lilyenv::releases::{set_libc, set_platform, set_releases_since, Libc};
+use lilyenv::shell::{
+    install_shell_config, print_hook, print_shell_config, set_shell, shell_env_format,
+};
+use lilyenv::version::{Interpreter, Prefixed, Version, VersionRequest};
+use lilyenv::virtualenvs::{
+    activate_external_virtualenv, activate_virtualenv, cd_site_packages, clone_virtualenv,
+    create_local_virtualenv, create_virtualenv_from, create_virtualenv_with, direnv,
+    discard_virtualenv, export_project, import_project, install_into, install_packages,
+    install_requirements, local_virtualenv, lock_project, pinned_deps, print_activation_env,
+    print_all_versions, print_project_versions, print_python_path, project_version, projects_using,
+    recreate_virtualenv, remove_named_virtualenv, remove_project, remove_virtualenv,
+    rename_project, require_virtualenv, requires_python_version, run_in_virtualenv, run_matrix,
+    set_backend, set_default_version, set_project_directory, set_prompt, sync_project,
+    unset_project_directory, upgrade_deps, virtualenv_version, Activation, Backend, EnvFormat,
+    LOCAL_VIRTUALENV,
 };
 
 #[derive(Parser)]
-#[command(author, version, about, long_about=None)]
+#[command(author, version, about, long_about=None, arg_required_else_help = true)]
 struct Cli {
     #[command(subcommand)]
-    cmd: Commands,
+    cmd: Option<Commands>,
+    /// Show the platform, release repository, interpreters and archive formats this lilyenv
+    /// supports, for bug reports
+    #[arg(long, exclusive = true)]
+    build_info: bool,
+    /// Show more detail about what lilyenv is doing. Repeat for even more detail
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only show errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// The libc of the Linux builds to download, instead of detecting it
+    #[arg(long, global = true, value_enum)]
+    libc: Option<Libc>,
+    /// The target triple of the builds to download, like aarch64-apple-darwin, to prepare
+    /// downloads for another machine
+    #[arg(long, global = true, value_name = "TRIPLE")]
+    platform: Option<String>,
+    /// The proxy to download through, instead of the HTTP_PROXY and HTTPS_PROXY environment variables
+    #[arg(long, global = true)]
+    proxy: Option<String>,
+    /// How many seconds to wait to connect, or for more data, before a request times out
+    #[arg(long, global = true, value_name = "SECONDS")]
+    timeout: Option<u64>,
+    /// Ignore CPython releases created before this RFC 3339 time. Defaults to
+    /// 2022-02-26T00:00:00Z, as older python-build-standalone releases lack the archives lilyenv
+    /// installs
+    #[arg(long, global = true, value_name = "TIME")]
+    releases_since: Option<chrono::DateTime<chrono::Utc>>,
+    /// The prompt for activated virtualenvs, with {project}, {version} and {interpreter}
+    /// placeholders. Defaults to "{project} ({version}) "
+    #[arg(long, global = true)]
+    prompt: Option<String>,
+    /// Never use the network, only installing Pythons from already downloaded archives
+    #[arg(long, global = true)]
+    offline: bool,
+    /// Extract downloads as they arrive, never saving or reusing archives in the downloads
+    /// directory
+    #[arg(long, global = true, conflicts_with = "offline")]
+    no_cache: bool,
+    /// Install and uninstall Pythons in the shared pythons directory, for every user of this
+    /// machine. Virtualenvs are still the user's own
+    #[arg(long, global = true)]
+    system: bool,
+    /// What creates virtualenvs and installs packages into them. auto uses uv if it's on PATH.
+    /// Defaults to venv
+    #[arg(long, global = true, value_enum)]
+    backend: Option<Backend>,
+    /// Print errors to stderr as JSON objects with error, kind and detail fields
+    #[arg(long, global = true)]
+    json: bool,
 }
 
 #[derive(Subcommand, Debug, Clone)]
 enum Commands {
     /// Activate a virtualenv given a Project string and a Python version
-    Activate { project: String, version: Version },
+    ///
+    /// The Project defaults to the current directory's name and the Python version to the one in
+    /// the nearest .python-version file, then one satisfying the nearest pyproject.toml's
+    /// requires-python, then the Project's default version, the version it was last activated
+    /// with or its only virtualenv.
+    Activate {
+        project: Option<String>,
+        /// A Python version, or a bare major version like 3 or pypy3 for the newest one installed
+        version: Option<Prefixed<VersionRequest>>,
+        /// Resolve a bare major version to the newest release, even if an older one is installed
+        #[arg(long)]
+        latest: bool,
+        /// The interpreter of the version, so --interpreter pypy 3.11 means pypy3.11. Errors if
+        /// the version's prefix names another
+        #[arg(long, value_enum, requires = "version")]
+        interpreter: Option<Interpreter>,
+        /// Activate the Project's virtualenv with this name instead of the one named after the
+        /// version, creating it if needed. The version defaults to the virtualenv's own
+        #[arg(long)]
+        name: Option<String>,
+        /// A package to install in the virtualenv. Can be repeated
+        #[arg(long = "with", value_name = "PACKAGE")]
+        packages: Vec<String>,
+        /// Upgrade pip, setuptools and wheel in the virtualenv
+        #[arg(long)]
+        upgrade_deps: bool,
+        /// Install this version of pip in the virtualenv, like 23.2 or "<24"
+        #[arg(long, value_name = "SPEC")]
+        pip_version: Option<String>,
+        /// Install this version of setuptools in the virtualenv, like 69.0 or "<70"
+        #[arg(long, value_name = "SPEC")]
+        setuptools_version: Option<String>,
+        /// Print export statements to activate the virtualenv in the current shell, instead of
+        /// starting a subshell
+        #[arg(long)]
+        export: bool,
+        /// The format of the --export statements. Defaults to the one for the project's shell
+        #[arg(long, value_enum, requires = "export")]
+        format: Option<EnvFormat>,
+        /// The shell to start, instead of the one set with set-shell or $SHELL
+        #[arg(long, conflicts_with = "export")]
+        shell: Option<String>,
+        /// Stop the shell after this many seconds
+        #[arg(long, value_name = "SECONDS", conflicts_with = "export")]
+        shell_timeout: Option<u64>,
+        /// Start the shell with only the essential environment variables, like HOME and TERM,
+        /// instead of inheriting the whole environment
+        #[arg(long, conflicts_with = "export")]
+        clean_env: bool,
+        /// A variable for --clean-env to keep. Can be repeated
+        #[arg(long, value_name = "VAR", requires = "clean_env")]
+        keep: Vec<String>,
+        /// Fail if the virtualenv doesn't exist, instead of downloading Python and creating it
+        #[arg(long)]
+        no_create: bool,
+        /// Activate this virtualenv, created by another tool, instead of one of a Project's
+        #[arg(long, value_name = "PATH", conflicts_with_all = [
+            "project", "version", "latest", "interpreter", "name", "packages", "upgrade_deps",
+            "pip_version", "setuptools_version", "no_create",
+        ])]
+        venv: Option<std::path::PathBuf>,
+        /// Activate .venv in the current directory, the same as --venv .venv
+        #[arg(long, conflicts_with_all = [
+            "project", "version", "latest", "interpreter", "name", "packages", "upgrade_deps",
+            "pip_version", "setuptools_version", "no_create", "venv",
+        ])]
+        local: bool,
+    },
+    /// Print the environment variables set when activating a virtualenv
+    Env {
+        project: String,
+        version: Version,
+        /// Defaults to the format for the project's shell
+        #[arg(long, value_enum)]
+        format: Option<EnvFormat>,
+        /// Write the environment to a temporary file and print its path
+        #[arg(long)]
+        temp_file: bool,
+    },
+    /// Print a direnv .envrc snippet that activates a virtualenv
+    ///
+    /// The Project and Python version default as for activate.
+    Direnv {
+        project: Option<String>,
+        version: Option<Version>,
+        /// Add the snippet to .envrc in the current directory instead of printing it
+        #[arg(long)]
+        write: bool,
+    },
+    /// Run a command in a virtualenv without starting a shell
+    Run {
+        project: String,
+        version: Version,
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Run a command in every virtualenv for a Project and summarise which versions passed
+    Matrix {
+        project: String,
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
     /// List all available virtualenvs, or those for the given Project
     List { project: Option<String> },
+    /// List all downloaded Python versions
+    ListPythons,
+    /// List installed Pythons with a newer bugfix release available
+    Outdated {
+        /// The format to list them in. The global --json flag also selects json
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+        /// Fetch the list of CPython releases instead of using the cached list
+        #[arg(long)]
+        refresh: bool,
+    },
+    /// List installed and available Python versions together, marking which are which
+    Versions {
+        /// Only list installed versions, without fetching the available ones
+        #[arg(long, conflicts_with = "available")]
+        installed: bool,
+        /// Only list versions that can be downloaded
+        #[arg(long)]
+        available: bool,
+        /// Fetch the available releases instead of using the cached list
+        #[arg(long)]
+        refresh: bool,
+    },
     /// Upgrade a Python version to the latest bugfix release
-    Upgrade { version: Version },
+    Upgrade {
+        version: Version,
+        /// Recreate the virtualenvs using this Python version, reinstalling their packages
+        #[arg(long)]
+        recreate: bool,
+    },
     /// Open a subshell in a virtualenv's site packages
     SitePackages { project: String, version: Version },
     /// Set the default directory for a project
@@ -42,53 +240,794 @@ enum Commands {
     /// Unset the default directory for a project
     UnsetProjectDirectory { project: String },
     /// Create a virtualenv given a Project string and a Python version
-    Virtualenv { project: String, version: Version },
+    Virtualenv {
+        /// The Project. With --local, this is only used for the prompt and can be left out
+        project: String,
+        #[arg(required_unless_present_any = ["python", "local"])]
+        version: Option<Prefixed<Version>>,
+        /// The interpreter of the version, so --interpreter pypy 3.11 means pypy3.11. Errors if
+        /// the version's prefix names another
+        #[arg(long, value_enum, conflicts_with = "python")]
+        interpreter: Option<Interpreter>,
+        /// A Python executable to create the virtualenv with, instead of downloading one
+        #[arg(long, conflicts_with = "version")]
+        python: Option<std::path::PathBuf>,
+        /// A requirements file to install, defaulting to requirements.txt if it exists
+        #[arg(long)]
+        requirements: Option<std::path::PathBuf>,
+        /// A package to install in the virtualenv. Can be repeated
+        #[arg(long = "with", value_name = "PACKAGE")]
+        packages: Vec<String>,
+        /// Name the virtualenv, instead of after its version, so a Project can have several
+        /// virtualenvs using the same version
+        #[arg(long, conflicts_with_all = ["python", "default"])]
+        name: Option<String>,
+        /// Recreate the virtualenv if it already exists, for example after uninstalling the
+        /// Python it was created from
+        #[arg(long, conflicts_with = "python")]
+        force: bool,
+        /// Activate this virtualenv when activating the Project without a version
+        #[arg(long)]
+        default: bool,
+        /// Give the virtualenv access to the Python's own site-packages
+        #[arg(long)]
+        system_site_packages: bool,
+        /// An argument to pass to `python -m venv`, like --without-pip. Can be repeated
+        #[arg(long = "venv-arg", value_name = "ARG", allow_hyphen_values = true)]
+        venv_args: Vec<String>,
+        /// Upgrade pip, setuptools and wheel in the virtualenv
+        #[arg(long)]
+        upgrade_deps: bool,
+        /// Install this version of pip in the virtualenv, like 23.2 or "<24"
+        #[arg(long, value_name = "SPEC")]
+        pip_version: Option<String>,
+        /// Install this version of setuptools in the virtualenv, like 69.0 or "<70"
+        #[arg(long, value_name = "SPEC")]
+        setuptools_version: Option<String>,
+        /// Start a subshell with the virtualenv activated once it's ready, downloading Python and
+        /// creating the virtualenv first as needed
+        #[arg(long)]
+        activate: bool,
+        /// Create the virtualenv in .venv in the current directory, where other tools expect it,
+        /// instead of as one of the Project's
+        #[arg(long, conflicts_with_all = [
+            "python", "name", "default", "upgrade_deps", "pip_version", "setuptools_version",
+        ])]
+        local: bool,
+    },
+    /// Create a virtualenv for a new Project with the packages installed in another Project's virtualenv
+    #[command(visible_alias = "copy")]
+    Clone {
+        version: Version,
+        source: String,
+        target: String,
+    },
+    /// Write a JSON manifest of a Project's virtualenvs and their installed packages
+    Export {
+        project: String,
+        /// Write the manifest to this file instead of printing it
+        #[arg(long, short)]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Recreate the virtualenvs in a manifest written by `lilyenv export`
+    Import {
+        manifest: std::path::PathBuf,
+        /// Create the virtualenvs for this Project instead of the one in the manifest
+        #[arg(long)]
+        project: Option<String>,
+    },
+    /// Write the exact Python release each of a Project's virtualenvs uses to lilyenv.lock in the
+    /// current directory
+    Lock { project: String },
+    /// Install exactly the Pythons in lilyenv.lock and create the virtualenvs using them,
+    /// ignoring newer releases
+    Sync {
+        /// Create the virtualenvs for this Project instead of the one in the lock file
+        #[arg(long)]
+        project: Option<String>,
+        /// Fetch the list of CPython releases instead of using the cached list
+        #[arg(long)]
+        refresh: bool,
+    },
     /// Remove a virtualenv
-    RemoveVirtualenv { project: String, version: Version },
+    RemoveVirtualenv {
+        project: String,
+        #[arg(required_unless_present = "name")]
+        version: Option<Version>,
+        /// Remove the virtualenv with this name instead
+        #[arg(long, conflicts_with = "version")]
+        name: Option<String>,
+    },
     /// Remove all virtualenvs for a project
     RemoveProject { project: String },
-    /// Download a specific Python version or list all Python versions available to download
-    Download { version: Option<Version> },
+    /// Rename a Project, recreating its virtualenvs with the same packages under the new name
+    Rename { old: String, new: String },
+    /// Download specific Python versions or list all Python versions available to download
+    Download {
+        /// Python versions, or PEP 440 specifiers like ">=3.10,<3.12" resolved to the newest matching CPython release
+        versions: Vec<Prefixed<VersionRequest>>,
+        /// The interpreter of the versions, so --interpreter pypy 3.11 means pypy3.11. Errors if
+        /// a version's prefix names another
+        #[arg(long, value_enum, requires = "versions")]
+        interpreter: Option<Interpreter>,
+        /// Fetch the list of CPython releases instead of using the cached list
+        #[arg(long)]
+        refresh: bool,
+        /// Download and extract the versions again even if they are already installed
+        #[arg(long)]
+        force: bool,
+        /// Download CPython's full distribution, with the build artifacts, instead of the smaller
+        /// install_only one. The same as adding -full to the versions
+        #[arg(long)]
+        full: bool,
+        /// List the releases available for the versions instead of downloading them, like every
+        /// 3.11.x build for 3.11
+        #[arg(long, conflicts_with_all = ["force", "dry_run"])]
+        list: bool,
+        /// List the platforms with install_only builds of the versions, whatever platform
+        /// lilyenv downloads for, to see why a version isn't found
+        #[arg(long, requires = "versions", conflicts_with_all = ["force", "dry_run", "list", "output_dir"])]
+        list_platforms: bool,
+        /// Show which release would be downloaded and where it would be installed, without
+        /// downloading anything
+        #[arg(long)]
+        dry_run: bool,
+        /// The format to list available versions in
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+        /// The order to list available versions in
+        #[arg(long, value_enum, default_value_t)]
+        sort: ReleaseOrder,
+        /// Keep the downloaded archives when cleaning, pruning or garbage collecting, for
+        /// reinstalling offline
+        #[arg(long, conflicts_with = "delete_archive")]
+        keep_archive: bool,
+        /// Remove the downloaded archives as soon as they are extracted
+        #[arg(long)]
+        delete_archive: bool,
+        /// Skip the standard library's test suite and the static libraries when extracting, to
+        /// save disk space
+        #[arg(long, conflicts_with_all = ["list", "list_platforms"])]
+        minimal: bool,
+        /// Extract the version into this directory instead of lilyenv's pythons directory. It
+        /// won't be used for virtualenvs
+        #[arg(long, value_name = "DIR", requires = "versions", conflicts_with_all = ["list", "dry_run"])]
+        output_dir: Option<std::path::PathBuf>,
+    },
+    /// Remove a downloaded Python version
+    Uninstall {
+        version: Version,
+        /// Remove the Python even if virtualenvs are using it
+        #[arg(long)]
+        force: bool,
+    },
+    /// Remove a downloaded Python version and its archive, then download it again
+    ///
+    /// For repairing a broken install. A version that isn't installed is just downloaded.
+    Reinstall {
+        version: Version,
+        /// Fetch the list of CPython releases again instead of using the cached list
+        #[arg(long)]
+        refresh: bool,
+    },
+    /// Remove downloaded archives, which are only needed while installing a Python version
+    Clean {
+        /// Also remove the cached lists of CPython and PyPy releases
+        #[arg(long)]
+        releases: bool,
+        /// Show what would be removed without removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Remove downloaded archives that haven't been modified recently
+    Prune {
+        /// How old an archive must be to be removed, like 12h, 30d or 2w
+        #[arg(long, value_parser = parse_age)]
+        older_than: std::time::Duration,
+        /// Show what would be removed without removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Remove downloaded archives no installed Python was extracted from, and virtualenvs whose
+    /// Python is missing
+    Gc {
+        /// Show what would be removed without removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Remove all downloaded Pythons, virtualenvs and cached files
+    Purge {
+        /// Remove everything without asking for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Print the path of the Python executable for a version, or for a Project's virtualenv
+    Which {
+        version: Version,
+        project: Option<String>,
+    },
+    /// Print the absolute path of a Project's virtualenv's python, like for VS Code's
+    /// python.defaultInterpreterPath
+    ///
+    /// The Project and Python version default as for activate.
+    PythonPath {
+        project: Option<String>,
+        version: Option<Version>,
+    },
+    /// Show where lilyenv keeps its files and what it has detected about this system, or with a
+    /// Project, what one of its virtualenvs was created from
+    Info {
+        project: Option<String>,
+        /// The virtualenv's Python version, defaulting as for activate
+        #[arg(requires = "project")]
+        version: Option<Version>,
+        /// Open the virtualenv's site-packages in the file manager
+        #[arg(long, requires = "project")]
+        open: bool,
+    },
+    /// Show how much disk space downloaded Pythons, virtualenvs and archives use
+    Du,
+    /// Check for problems downloading Pythons or using virtualenvs
+    Doctor,
+    /// Check that GitHub and PyPy can be reached, showing how long each request took
+    CheckNetwork,
+    /// Write a Python version to .python-version in the current directory, or print the version
+    /// in the nearest .python-version file
+    Local {
+        #[arg(conflicts_with = "unset")]
+        version: Option<Version>,
+        /// Remove the current directory's .python-version file instead
+        #[arg(long)]
+        unset: bool,
+    },
     /// Explicitly set the shell for lilyenv to use
     SetShell {
         shell: String,
         project: Option<String>,
     },
+    /// Print a shell completion script
+    Completions { shell: clap_complete::Shell },
     /// Show information to include in a shell config file
-    ShellConfig { project: Option<String> },
+    ShellConfig {
+        project: Option<String>,
+        /// The shell to show configuration for, instead of the configured shell
+        #[arg(long)]
+        shell: Option<String>,
+        /// Also include a hook that activates the virtualenv for the nearest .python-version file
+        /// when changing directory
+        #[arg(long)]
+        hook: bool,
+        /// Only print the statement that puts lilyenv on PATH, for shell startup files that
+        /// don't need the prompt or activation functions
+        #[arg(long, conflicts_with = "hook")]
+        print_path: bool,
+        /// Add the configuration to the shell's startup file, like ~/.bashrc, between marker
+        /// comments, instead of printing it
+        #[arg(long, conflicts_with_all = ["print_path", "update"])]
+        install: bool,
+        /// Replace the configuration added with --install by this lilyenv's, adding it if it's
+        /// missing
+        #[arg(long, conflicts_with = "print_path")]
+        update: bool,
+    },
+    /// Print the shell statements run by the shell-config --hook hook
+    #[command(hide = true)]
+    Hook {
+        #[arg(long)]
+        shell: Option<String>,
+    },
 }
 
-fn run() -> Result<(), Error> {
-    let cli = Cli::parse();
+fn init_logging(verbose: u8, quiet: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => log::LevelFilter::Error,
+        (false, 0) => log::LevelFilter::Info,
+        (false, 1) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format(|f, record| match record.level() {
+            log::Level::Error => {
+                let style = f.default_level_style(log::Level::Error);
+                writeln!(f, "{style}{}{style:#}", record.args())
+            }
+            log::Level::Info => writeln!(f, "{}", record.args()),
+            log::Level::Warn => writeln!(f, "Warning: {}", record.args()),
+            level => writeln!(f, "[{}] {}", level.as_str().to_lowercase(), record.args()),
+        })
+        .init();
+}
+
+/// The Project and Python version to activate, defaulting to the current directory's name and
+/// the nearest .python-version file, a version satisfying the nearest pyproject.toml's
+/// requires-python, then the Project's default version, the version it was last activated with or
+/// its only virtualenv.
+fn project_and_version(
+    project: Option<String>,
+    version: Option<Version>,
+) -> Result<(String, Version), Error> {
+    let project = match project {
+        Some(project) => project,
+        None => default_project()?,
+    };
+    let version = match version {
+        Some(version) => version,
+        None => match python_version_from_file() {
+            Err(Error::NoVersionFile) => match requires_python()? {
+                Some(specifier) => requires_python_version(&project, &specifier)?,
+                None => project_version(&project)?,
+            },
+            version => version?,
+        },
+    };
+    Ok((project, version))
+}
+
+/// The requested versions for the interpreter given with `--interpreter`, if any.
+fn with_interpreter(
+    versions: Vec<Prefixed<VersionRequest>>,
+    interpreter: Option<Interpreter>,
+) -> Result<Vec<VersionRequest>, Error> {
+    versions
+        .into_iter()
+        .map(|request| request.for_interpreter(interpreter))
+        .collect()
+}
 
-    match cli.cmd {
-        Commands::Download { version: None } => print_available_downloads()?,
+fn run(cli: Cli) -> Result<(), Error> {
+    let json = cli.json;
+    init_logging(cli.verbose, cli.quiet);
+    load_config()?;
+    match (cli.platform, cli.libc) {
+        (Some(platform), libc) => set_platform(&platform, libc)?,
+        (None, Some(libc)) => set_libc(libc),
+        (None, None) => {}
+    }
+    if let Some(proxy) = cli.proxy {
+        set_proxy(proxy);
+    }
+    if let Some(timeout) = cli.timeout {
+        set_timeout(std::time::Duration::from_secs(timeout));
+    }
+    if let Some(since) = cli.releases_since {
+        set_releases_since(since);
+    }
+    if cli.offline {
+        set_offline();
+    }
+    if cli.no_cache {
+        set_no_cache();
+    }
+    if cli.system {
+        set_system();
+    }
+    if let Some(prompt) = cli.prompt {
+        set_prompt(prompt);
+    }
+    if let Some(backend) = cli.backend {
+        set_backend(backend);
+    }
+
+    let cmd = match (cli.build_info, cli.cmd) {
+        (true, None) => return print_build_info(),
+        (false, Some(cmd)) => cmd,
+        (true, Some(_)) => Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--build-info can't be used with a subcommand",
+            )
+            .exit(),
+        (false, None) => Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingSubcommand,
+                "a subcommand is required",
+            )
+            .exit(),
+    };
+    if matches!(cmd, Commands::CheckNetwork) && cli.offline {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "check-network can't be used with --offline",
+            )
+            .exit();
+    }
+    if let Commands::Download {
+        keep_archive,
+        delete_archive,
+        list_platforms,
+        minimal,
+        ..
+    } = cmd
+    {
+        if minimal {
+            set_minimal();
+        }
+        if list_platforms && cli.offline {
+            Cli::command()
+                .error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    "--list-platforms can't be used with --offline, as it lists the releases on GitHub",
+                )
+                .exit();
+        }
+        match (keep_archive, delete_archive) {
+            (true, _) if cli.no_cache => Cli::command()
+                .error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    "--keep-archive can't be used with --no-cache, which never saves archives",
+                )
+                .exit(),
+            (true, _) => set_archive_retention(ArchiveRetention::Keep),
+            (_, true) => set_archive_retention(ArchiveRetention::Delete),
+            _ => {}
+        }
+    }
+    match cmd {
+        Commands::Download {
+            versions,
+            interpreter,
+            refresh,
+            force,
+            full,
+            output_dir: Some(output_dir),
+            ..
+        } => match &with_interpreter(versions, interpreter)?[..] {
+            [request] => download_python_to(request, &output_dir, force, refresh, full)?,
+            versions => return Err(Error::OutputDirVersions(versions.len())),
+        },
         Commands::Download {
-            version: Some(version),
+            versions,
+            interpreter,
+            refresh,
+            force,
+            full,
+            list,
+            list_platforms,
+            dry_run,
+            format,
+            sort,
+            output_dir: None,
+            ..
         } => {
-            download_python(&version, false)?;
+            let versions = with_interpreter(versions, interpreter)?;
+            match (versions.is_empty(), dry_run) {
+                (true, _) => print_available_downloads(refresh, format, sort)?,
+                (false, _) if list_platforms => print_release_platforms(&versions, refresh)?,
+                (false, _) if list => print_matching_downloads(&versions, refresh, format, sort)?,
+                (false, true) => print_download_plan(&versions, refresh, full)?,
+                (false, false) => download_pythons(&versions, refresh, force, full)?,
+            }
         }
-        Commands::Virtualenv { version, project } => {
-            create_virtualenv(&version, &project)?;
+        Commands::Uninstall { version, force } => uninstall_python(&version, force)?,
+        Commands::Reinstall { version, refresh } => reinstall_python(&version, refresh)?,
+        Commands::Clean { releases, dry_run } => clean(releases, dry_run)?,
+        Commands::Gc { dry_run } => gc(dry_run)?,
+        Commands::Prune {
+            older_than,
+            dry_run,
+        } => prune(older_than, dry_run)?,
+        Commands::Purge { yes } => purge(yes)?,
+        Commands::Virtualenv {
+            version,
+            interpreter,
+            project,
+            python,
+            requirements,
+            packages,
+            name,
+            force,
+            default,
+            system_site_packages,
+            mut venv_args,
+            upgrade_deps: upgrade,
+            pip_version,
+            setuptools_version,
+            activate,
+            local,
+        } => {
+            if system_site_packages {
+                venv_args.push("--system-site-packages".to_string());
+            }
+            let requirements = requirements.or_else(|| {
+                let default = std::path::PathBuf::from("requirements.txt");
+                default.is_file().then_some(default)
+            });
+            if local {
+                // A lone argument is the version, prompting with the current directory's name.
+                let (prompt, version) = match version {
+                    Some(version) => (project, version),
+                    None => (default_project()?, project.parse()?),
+                };
+                let version = version.for_interpreter(interpreter)?;
+                let virtualenv = create_local_virtualenv(&version, &prompt, &venv_args, force)?;
+                install_into(&virtualenv, requirements.as_deref(), &packages)?;
+                if activate {
+                    let activation = Activation::Subshell {
+                        shell: None,
+                        timeout: None,
+                        clean_env: None,
+                    };
+                    let code = activate_external_virtualenv(&virtualenv, activation)?;
+                    if code != 0 {
+                        std::process::exit(code);
+                    }
+                }
+                return Ok(());
+            }
+            let version = version
+                .map(|version| version.for_interpreter(interpreter))
+                .transpose()?;
+            let version = match (python, version) {
+                (Some(python), _) => create_virtualenv_from(&python, &project, &venv_args)?,
+                (None, Some(version)) => {
+                    if force {
+                        discard_virtualenv(&version, &project, name.as_deref())?;
+                    }
+                    create_virtualenv_with(&version, &project, name.as_deref(), &venv_args)?;
+                    version
+                }
+                (None, None) => unreachable!("clap requires a version or --python"),
+            };
+            if default {
+                set_default_version(&project, &version)?;
+            }
+            let name = name.as_deref();
+            if upgrade {
+                upgrade_deps(&version, &project, name)?;
+            }
+            let pins = pinned_deps(pip_version.as_deref(), setuptools_version.as_deref());
+            install_packages(&version, &project, name, &pins)?;
+            if let Some(requirements) = requirements {
+                install_requirements(&version, &project, name, &requirements)?;
+            }
+            install_packages(&version, &project, name, &packages)?;
+            if activate {
+                log::info!("Activating {project} ({version})");
+                let activation = Activation::Subshell {
+                    shell: None,
+                    timeout: None,
+                    clean_env: None,
+                };
+                let code =
+                    activate_virtualenv(&version, &project, name, &[], false, &[], activation)?;
+                if code != 0 {
+                    std::process::exit(code);
+                }
+            }
         }
-        Commands::RemoveVirtualenv { project, version } => {
-            remove_virtualenv(&project, &version)?;
+        Commands::Clone {
+            version,
+            source,
+            target,
+        } => {
+            clone_virtualenv(&version, &source, &target)?;
         }
+        Commands::Export { project, output } => export_project(&project, output.as_deref())?,
+        Commands::Import { manifest, project } => import_project(&manifest, project.as_deref())?,
+        Commands::Lock { project } => lock_project(&project)?,
+        Commands::Sync { project, refresh } => sync_project(project.as_deref(), refresh)?,
+        Commands::RemoveVirtualenv {
+            project,
+            version,
+            name,
+        } => match (name, version) {
+            (Some(name), _) => remove_named_virtualenv(&project, &name)?,
+            (None, Some(version)) => remove_virtualenv(&project, &version)?,
+            (None, None) => unreachable!("clap requires a version or --name"),
+        },
         Commands::RemoveProject { project } => {
             remove_project(&project)?;
         }
-        Commands::Activate { version, project } => {
-            activate_virtualenv(&version, &project)?;
+        Commands::Rename { old, new } => rename_project(&old, &new)?,
+        Commands::Activate {
+            version,
+            latest,
+            interpreter,
+            name,
+            project,
+            packages,
+            upgrade_deps,
+            pip_version,
+            setuptools_version,
+            export,
+            format,
+            shell,
+            shell_timeout,
+            clean_env,
+            keep,
+            no_create,
+            venv,
+            local,
+        } => {
+            // With nothing to choose a Project's virtualenv, a .venv here is what's wanted.
+            let defaulted = project.is_none()
+                && version.is_none()
+                && name.is_none()
+                && packages.is_empty()
+                && !upgrade_deps
+                && pip_version.is_none()
+                && setuptools_version.is_none();
+            let venv = match local {
+                true => Some(std::path::PathBuf::from(LOCAL_VIRTUALENV)),
+                false if defaulted => venv.or_else(local_virtualenv),
+                false => venv,
+            };
+            if let Some(venv) = venv {
+                let activation = match export {
+                    true => Activation::Export(format.unwrap_or_else(|| shell_env_format(None))),
+                    false => Activation::Subshell {
+                        shell,
+                        timeout: shell_timeout.map(std::time::Duration::from_secs),
+                        clean_env: clean_env.then_some(keep),
+                    },
+                };
+                let code = activate_external_virtualenv(&venv, activation)?;
+                if code != 0 {
+                    std::process::exit(code);
+                }
+                return Ok(());
+            }
+            let version = version
+                .map(|request| request.for_interpreter(interpreter))
+                .transpose()?
+                .map(|request| resolve_request(&request, latest, false))
+                .transpose()?;
+            let project = match project {
+                Some(project) => project,
+                None => default_project()?,
+            };
+            let version = match (version, &name) {
+                (None, Some(name)) => virtualenv_version(&project, name)?,
+                (version, _) => version,
+            };
+            let (project, version) = project_and_version(Some(project), version)?;
+            if no_create {
+                require_virtualenv(&version, &project, name.as_deref())?;
+            }
+            let pins = pinned_deps(pip_version.as_deref(), setuptools_version.as_deref());
+            let activation = match export {
+                true => {
+                    Activation::Export(format.unwrap_or_else(|| shell_env_format(Some(&project))))
+                }
+                false => Activation::Subshell {
+                    shell,
+                    timeout: shell_timeout.map(std::time::Duration::from_secs),
+                    clean_env: clean_env.then_some(keep),
+                },
+            };
+            let code = activate_virtualenv(
+                &version,
+                &project,
+                name.as_deref(),
+                &packages,
+                upgrade_deps,
+                &pins,
+                activation,
+            )?;
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+        Commands::Run {
+            project,
+            version,
+            command,
+        } => {
+            let status = run_in_virtualenv(&version, &project, &command)?;
+            std::process::exit(status.code().unwrap_or(1));
+        }
+        Commands::Direnv {
+            project,
+            version,
+            write,
+        } => {
+            let (project, version) = project_and_version(project, version)?;
+            direnv(&version, &project, write)?;
+        }
+        Commands::Matrix { project, command } => {
+            std::process::exit(run_matrix(&project, &command)?);
+        }
+        Commands::Env {
+            project,
+            version,
+            format,
+            temp_file,
+        } => {
+            let format = format.unwrap_or_else(|| shell_env_format(Some(&project)));
+            print_activation_env(&version, &project, format, temp_file)?
         }
+        Commands::Which { version, project } => print_python_path(&version, project.as_deref())?,
+        Commands::PythonPath { project, version } => {
+            let (project, version) = project_and_version(project, version)?;
+            print_python_path(&version, Some(&project))?
+        }
+        Commands::Info { project: None, .. } => print_info()?,
+        Commands::Info {
+            project: Some(project),
+            version,
+            open,
+        } => {
+            let (project, version) = project_and_version(Some(project), version)?;
+            print_virtualenv_info(&project, &version, open)?;
+        }
+        Commands::Du => print_disk_usage()?,
+        Commands::Doctor => doctor()?,
+        Commands::CheckNetwork => check_network()?,
+        Commands::Local {
+            version: None,
+            unset: false,
+        } => println!("{}", python_version_from_file()?),
+        Commands::Local { version, .. } => set_local_version(version.as_ref())?,
         Commands::SetShell { shell, project } => set_shell(&shell, project.as_deref())?,
-        Commands::ShellConfig { project } => print_shell_config(project.as_deref())?,
+        Commands::ShellConfig {
+            project,
+            shell,
+            hook,
+            print_path,
+            install,
+            update,
+        } => match install || update {
+            true => install_shell_config(shell.as_deref(), project.as_deref(), hook, update)?,
+            false => print_shell_config(shell.as_deref(), project.as_deref(), hook, print_path)?,
+        },
+        Commands::Hook { shell } => print_hook(shell.as_deref())?,
+        Commands::Completions { shell } => clap_complete::generate(
+            shell,
+            &mut Cli::command(),
+            "lilyenv",
+            &mut std::io::stdout(),
+        ),
         Commands::List { project } => match project {
             Some(project) => print_project_versions(project)?,
             None => print_all_versions()?,
         },
-        Commands::Upgrade { version } => match version.bugfix {
-            Some(_) => eprintln!("Only x.y Python versions can be upgraded, not x.y.z"),
-            None => download_python(&version, true)?,
+        Commands::ListPythons => print_installed_pythons()?,
+        Commands::Outdated { format, refresh } => {
+            let format = match json {
+                true => OutputFormat::Json,
+                false => format,
+            };
+            print_outdated(format, refresh)?
+        }
+        Commands::Versions {
+            installed,
+            available,
+            refresh,
+        } => {
+            let filter = match (installed, available) {
+                (true, _) => VersionsFilter::Installed,
+                (_, true) => VersionsFilter::Available,
+                _ => VersionsFilter::All,
+            };
+            print_versions(filter, refresh)?
+        }
+        Commands::Upgrade { version, recreate } => match version.bugfix {
+            Some(_) => {
+                return Err(Error::InvalidVersion(format!(
+                    "{version} (only x.y versions can be upgraded)"
+                )))
+            }
+            None => {
+                if !upgrade_python(&version)? {
+                    return Ok(());
+                }
+                let projects = projects_using(&version)?;
+                match recreate {
+                    true => {
+                        for project in projects {
+                            log::info!("Recreating virtualenv {project} ({version})");
+                            recreate_virtualenv(&version, &project)?;
+                        }
+                    }
+                    false if !projects.is_empty() => log::info!(
+                        "Virtualenvs for {} use Python {version}. Run `lilyenv upgrade --recreate {version}` to recreate them.",
+                        projects.join(", ")
+                    ),
+                    false => {}
+                }
+            }
         },
         Commands::SetProjectDirectory {
             project,
@@ -112,7 +1051,13 @@ fn run() -> Result<(), Error> {
 }
 
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("{e}");
+    let cli = Cli::parse();
+    let json = cli.json;
+    if let Err(e) = run(cli) {
+        match json {
+            true => eprintln!("{}", e.to_json()),
+            false => log::error!("{e}"),
+        }
+        std::process::exit(e.exit_code());
     }
 }
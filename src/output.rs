@@ -0,0 +1,103 @@
+use crate::releases::Python;
+use crate::version::{Interpreter, Version};
+use anstyle::{AnsiColor, Style};
+use serde::Serialize;
+use url::Url;
+
+// anstream strips these styles when stdout isn't a terminal or `NO_COLOR` is set.
+pub const INTERPRETER: Style = AnsiColor::Cyan.on_default();
+pub const VERSION: Style = Style::new().bold();
+pub const RELEASE_TAG: Style = AnsiColor::BrightBlack.on_default();
+pub const PROJECT: Style = AnsiColor::Green.on_default().bold();
+
+/// A version with its interpreter prefix, like `pypy`, highlighted separately.
+pub fn styled_version(version: &Version) -> String {
+    let version = version.to_string();
+    let prefix = version
+        .find(|c: char| c.is_ascii_digit())
+        .unwrap_or_default();
+    let (interpreter, number) = version.split_at(prefix);
+    format!("{INTERPRETER}{interpreter}{INTERPRETER:#}{VERSION}{number}{VERSION:#}")
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// How to order listed releases.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+pub enum ReleaseOrder {
+    #[default]
+    Version,
+    /// Oldest first, with releases without a date, like PyPy's, before the rest
+    Date,
+}
+
+/// Sort releases by version, PyPy release and release tag, or by release date then version.
+pub fn sort_releases(releases: &mut [Python], order: ReleaseOrder) {
+    match order {
+        ReleaseOrder::Version => releases.sort_by(|a, b| {
+            (a.version_key(), &a.release_tag).cmp(&(b.version_key(), &b.release_tag))
+        }),
+        ReleaseOrder::Date => releases.sort_by(|a, b| {
+            (a.released, a.version_key(), &a.release_tag).cmp(&(
+                b.released,
+                b.version_key(),
+                &b.release_tag,
+            ))
+        }),
+    }
+}
+
+#[derive(Serialize)]
+struct Release<'a> {
+    interpreter: Interpreter,
+    major: u16,
+    minor: u16,
+    bugfix: Option<u16>,
+    release_tag: &'a str,
+    name: &'a str,
+    url: &'a Url,
+    released: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub fn print_releases(releases: &[Python], format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            for python in releases {
+                let released = match python.released {
+                    Some(released) => format!(" — {}", released.format("%Y-%m-%d")),
+                    None => String::new(),
+                };
+                anstream::println!(
+                    "{} {RELEASE_TAG}({}){RELEASE_TAG:#}{released}",
+                    styled_version(&python.version),
+                    python.release_tag
+                );
+            }
+        }
+        OutputFormat::Json => {
+            let releases = releases
+                .iter()
+                .map(|python| Release {
+                    interpreter: python.version.interpreter,
+                    major: python.version.major,
+                    minor: python.version.minor,
+                    bugfix: python.version.bugfix,
+                    release_tag: &python.release_tag,
+                    name: &python.name,
+                    url: &python.url,
+                    released: python.released,
+                })
+                .collect::<Vec<_>>();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&releases)
+                    .expect("Releases can always be serialized.")
+            );
+        }
+    }
+}
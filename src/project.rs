@@ -0,0 +1,155 @@
+use crate::disk::WithPath;
+use crate::error::Error;
+use crate::version::{Version, VersionSpecifier};
+
+pub const PYTHON_VERSION_FILE: &str = ".python-version";
+pub const PYPROJECT_FILE: &str = "pyproject.toml";
+
+/// Check `project` can be used as a single directory name, so it can't nest or escape the
+/// virtualenvs directory. Both separators are rejected so manifests work on any platform.
+pub fn validate_project_name(project: &str) -> Result<&str, Error> {
+    if project.is_empty() || project == "." || project == ".." || project.contains(['/', '\\']) {
+        return Err(Error::InvalidProject(project.to_string()));
+    }
+    Ok(project)
+}
+
+/// The project name to use when none is given: the current directory's name.
+pub fn default_project() -> Result<String, Error> {
+    let current_dir = std::env::current_dir()?;
+    match current_dir.file_name().and_then(|name| name.to_str()) {
+        Some(name) => Ok(name.to_string()),
+        None => Err(Error::NoProject(current_dir.display().to_string())),
+    }
+}
+
+/// The nearest `.python-version` file in the current directory or its parents.
+fn python_version_file() -> Result<Option<std::path::PathBuf>, Error> {
+    let current_dir = std::env::current_dir()?;
+    Ok(current_dir
+        .ancestors()
+        .map(|dir| dir.join(PYTHON_VERSION_FILE))
+        .find(|file| file.is_file()))
+}
+
+/// Find the version in the nearest `.python-version` file in the current directory or its parents.
+pub fn python_version_from_file() -> Result<Version, Error> {
+    let file = python_version_file()?.ok_or(Error::NoVersionFile)?;
+    read_python_version(&file)
+}
+
+/// The nearest `.python-version` file's version, and the name of the directory it is in as the
+/// Project.
+pub fn python_version_project() -> Result<Option<(String, Version)>, Error> {
+    let Some(file) = python_version_file()? else {
+        return Ok(None);
+    };
+    let dir = file
+        .parent()
+        .expect("A .python-version file is in a directory.");
+    let project = match dir.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name.to_string(),
+        None => return Err(Error::NoProject(dir.display().to_string())),
+    };
+    Ok(Some((project, read_python_version(&file)?)))
+}
+
+/// Write `version` to a `.python-version` file in the current directory, or remove the file if
+/// no version is given.
+pub fn set_local_version(version: Option<&Version>) -> Result<(), Error> {
+    let file = std::path::Path::new(PYTHON_VERSION_FILE);
+    match version {
+        Some(version) => {
+            std::fs::write(file, format!("{version}\n")).with_path(file)?;
+            log::info!("Wrote {version} to {PYTHON_VERSION_FILE}");
+        }
+        None => match std::fs::remove_file(file) {
+            Ok(()) => log::info!("Removed {PYTHON_VERSION_FILE}"),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                log::info!("There is no {PYTHON_VERSION_FILE} in the current directory")
+            }
+            Err(err) => return Err(err).with_path(file),
+        },
+    }
+    Ok(())
+}
+
+/// The `requires-python` specifier in the nearest `pyproject.toml` in the current directory or its
+/// parents, if it has one.
+pub fn requires_python() -> Result<Option<VersionSpecifier>, Error> {
+    let current_dir = std::env::current_dir()?;
+    let Some(file) = current_dir
+        .ancestors()
+        .map(|dir| dir.join(PYPROJECT_FILE))
+        .find(|file| file.is_file())
+    else {
+        return Ok(None);
+    };
+    parse_requires_python(&std::fs::read_to_string(&file).with_path(&file)?).map_err(|reason| {
+        Error::InvalidManifest {
+            path: file.display().to_string(),
+            reason,
+        }
+    })
+}
+
+fn parse_requires_python(pyproject: &str) -> Result<Option<VersionSpecifier>, String> {
+    let pyproject = pyproject
+        .parse::<toml::Table>()
+        .map_err(|err| err.message().to_string())?;
+    match pyproject
+        .get("project")
+        .and_then(|project| project.get("requires-python"))
+    {
+        None => Ok(None),
+        Some(toml::Value::String(specifier)) => specifier
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("requires-python {specifier:?} isn't a supported specifier")),
+        Some(_) => Err("requires-python should be a string".to_string()),
+    }
+}
+
+fn read_python_version(file: &std::path::Path) -> Result<Version, Error> {
+    let contents = std::fs::read_to_string(file).with_path(file)?;
+    match contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+    {
+        Some(version) => version.parse(),
+        None => Err(Error::InvalidVersion(contents)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requires_python() {
+        let parse = |pyproject| parse_requires_python(pyproject).map(|s| s.map(|s| s.to_string()));
+        assert_eq!(
+            parse("[project]\nname = \"web\"\nrequires-python = \">=3.10\"\n"),
+            Ok(Some(">=3.10".to_string()))
+        );
+        assert_eq!(parse("[project]\nname = \"web\"\n"), Ok(None));
+        assert_eq!(parse("[tool.black]\n"), Ok(None));
+        assert!(parse("[project]\nrequires-python = 3\n").is_err());
+        assert!(parse("[project]\nrequires-python = \"python3\"\n").is_err());
+        assert!(parse("[project\n").is_err());
+    }
+
+    #[test]
+    fn test_validate_project_name() {
+        for project in ["web", "my-project", "v1.2", ".hidden"] {
+            assert_eq!(validate_project_name(project).unwrap(), project);
+        }
+        for project in ["", ".", "..", "a/b", "../web", "a\\b"] {
+            assert!(matches!(
+                validate_project_name(project),
+                Err(Error::InvalidProject(_))
+            ));
+        }
+    }
+}
@@ -1,9 +1,24 @@
+use crate::config::config;
+use crate::directories::{
+    config_file, downloads_dir, pypy_cache_file, pypy_index_cache_file, releases_cache_file,
+};
+use crate::disk::{read_cache, write_cache};
+use crate::download::Compression;
 use crate::error::Error;
-use crate::version::{parse_cpython_filename, parse_pypy_url, Version, PYPY_DOWNLOAD_URL};
+use crate::http::{client, github, with_retry, with_retry_async};
+use crate::version::{
+    matches, parse_cpython_filename, parse_graalpy_filename, parse_pypy_url, pypy_release,
+    Interpreter, Version, PYPY_DOWNLOAD_URL,
+};
 use current_platform::CURRENT_PLATFORM;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
 use url::Url;
 
-#[derive(Debug)]
+/// A downloadable Python build for the current platform.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Python {
     pub name: String,
     pub url: Url,
@@ -11,82 +26,1123 @@ pub struct Python {
     pub release_tag: String,
     pub debug: bool,
     pub freethreaded: bool,
+    pub sha256_url: Option<Url>,
+    /// The archive's size in bytes, when the release lists it.
+    pub size: Option<u64>,
+    /// When the release was published, for releases listed on GitHub.
+    #[serde(default)]
+    pub released: Option<chrono::DateTime<chrono::Utc>>,
 }
 
-pub async fn cpython_releases() -> Result<Vec<Python>, Error> {
-    let octocrab = octocrab::instance();
-    octocrab
-        .repos("indygreg", "python-build-standalone")
-        .releases()
-        .list()
-        .send()
+impl Python {
+    /// The key to order releases of the same Python version by, before the release tag.
+    pub fn version_key(&self) -> VersionKey {
+        version_key(&self.version, &self.release_tag)
+    }
+}
+
+pub type VersionKey = (Interpreter, u16, u16, Option<(u16, u16, u16)>, Version);
+
+/// Order versions by their Python version, then PyPy release, as a PyPy version's bugfix
+/// version is only its release's patch number, so PyPy v8.0.0 comes after v7.3.17.
+pub fn version_key(version: &Version, release_tag: &str) -> VersionKey {
+    let release = match version.interpreter {
+        Interpreter::PyPy => pypy_release(release_tag),
+        _ => None,
+    };
+    (
+        version.interpreter,
+        version.major,
+        version.minor,
+        release,
+        *version,
+    )
+}
+
+/// How long to reuse the cached CPython releases list, in seconds.
+const DEFAULT_RELEASES_TTL: i64 = 24 * 60 * 60;
+
+/// python-build-standalone releases before this don't have the `install_only` archives lilyenv
+/// was written for.
+const DEFAULT_RELEASES_SINCE: &str = "2022-02-26T00:00:00Z";
+
+static RELEASES_SINCE: OnceLock<chrono::DateTime<chrono::Utc>> = OnceLock::new();
+
+/// Override the configured cutoff for CPython releases. Must be called before listing releases.
+pub fn set_releases_since(since: chrono::DateTime<chrono::Utc>) {
+    let _ = RELEASES_SINCE.set(since);
+}
+
+pub(crate) fn releases_since() -> chrono::DateTime<chrono::Utc> {
+    *RELEASES_SINCE.get_or_init(|| {
+        config().releases_since.unwrap_or_else(|| {
+            DEFAULT_RELEASES_SINCE
+                .parse()
+                .expect("Could not parse hardcoded datetime.")
+        })
+    })
+}
+
+/// Bumped when the cached releases need to be fetched again, like when `Version` gains a field.
+const RELEASES_CACHE_FORMAT: u32 = 4;
+
+#[derive(Serialize, Deserialize)]
+struct ReleasesCache {
+    format: u32,
+    fetched_at: i64,
+    platform: String,
+    releases_since: i64,
+    releases: Vec<Python>,
+}
+
+fn releases_ttl() -> i64 {
+    std::env::var("LILYENV_RELEASES_TTL")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .unwrap_or(DEFAULT_RELEASES_TTL)
+}
+
+pub(crate) fn read_releases_cache() -> Option<Vec<Python>> {
+    let cache: ReleasesCache = read_cache(&releases_cache_file())?;
+    let fresh = chrono::Utc::now().timestamp() - cache.fetched_at < releases_ttl();
+    match fresh
+        && cache.format == RELEASES_CACHE_FORMAT
+        && cache.platform == target_platform()
+        && cache.releases_since == releases_since().timestamp()
+    {
+        true => Some(cache.releases),
+        false => None,
+    }
+}
+
+fn write_releases_cache(releases: Vec<Python>) -> Result<Vec<Python>, Error> {
+    let cache = ReleasesCache {
+        format: RELEASES_CACHE_FORMAT,
+        fetched_at: chrono::Utc::now().timestamp(),
+        platform: target_platform().to_string(),
+        releases_since: releases_since().timestamp(),
+        releases,
+    };
+    write_cache(&releases_cache_file(), &cache)?;
+    Ok(cache.releases)
+}
+
+/// List CPython releases, from the on-disk cache unless it is stale or `refresh` is set.
+pub async fn cpython_releases(refresh: bool) -> Result<Vec<Python>, Error> {
+    if !refresh {
+        if let Some(releases) = read_releases_cache() {
+            log::debug!(
+                "Using cached releases from {}",
+                releases_cache_file().display()
+            );
+            return Ok(releases);
+        }
+    }
+    write_releases_cache(fetch_cpython_releases().await?)
+}
+
+const DEFAULT_CPYTHON_REPOSITORY: &str = "astral-sh/python-build-standalone";
+
+/// The owner and name of the GitHub repository to list CPython releases from.
+pub(crate) fn cpython_repository() -> Result<(&'static str, &'static str), Error> {
+    let repository = config()
+        .cpython_repository
+        .as_deref()
+        .unwrap_or(DEFAULT_CPYTHON_REPOSITORY);
+    match repository.split_once('/') {
+        Some((owner, repo)) if !owner.is_empty() && !repo.is_empty() && !repo.contains('/') => {
+            Ok((owner, repo))
+        }
+        _ => Err(Error::Config(
+            config_file().display().to_string(),
+            format!("cpython_repository should look like owner/repo, not {repository}"),
+        )),
+    }
+}
+
+/// GitHub's largest page size for listing releases.
+const RELEASES_PER_PAGE: u8 = 100;
+
+/// How many pages of releases to fetch at once.
+const CONCURRENT_PAGES: usize = 4;
+
+async fn releases_page(
+    owner: &str,
+    repo: &str,
+    page: u32,
+) -> Result<octocrab::Page<octocrab::models::repos::Release>, Error> {
+    let octocrab = github()?;
+    with_retry_async(|| async {
+        Ok(octocrab
+            .repos(owner, repo)
+            .releases()
+            .list()
+            .per_page(RELEASES_PER_PAGE)
+            .page(page)
+            .send()
+            .await?)
+    })
+    .await
+}
+
+/// List a repository's releases created after `since`, following GitHub's pagination a few pages
+/// at a time. Releases are listed newest first, so no more pages are fetched once one reaches
+/// `since`.
+async fn list_releases(
+    owner: &str,
+    repo: &str,
+    since: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<octocrab::models::repos::Release>, Error> {
+    let mut releases = vec![];
+    // Keep a page's new enough releases, returning whether later pages may have more.
+    let mut take = |page: octocrab::Page<octocrab::models::repos::Release>| {
+        let more = page.items.len() == usize::from(RELEASES_PER_PAGE)
+            && page
+                .items
+                .iter()
+                .all(|release| release.created_at > Some(since));
+        releases.extend(
+            page.items
+                .into_iter()
+                .filter(|release| release.created_at > Some(since)),
+        );
+        more
+    };
+
+    let first = releases_page(owner, repo, 1).await?;
+    let last = first.number_of_pages().unwrap_or(1);
+    if !take(first) {
+        return Ok(releases);
+    }
+    let pages = (2..=last).collect::<Vec<_>>();
+    for batch in pages.chunks(CONCURRENT_PAGES) {
+        let batch = futures::future::try_join_all(
+            batch.iter().map(|&page| releases_page(owner, repo, page)),
+        )
+        .await?;
+        for page in batch {
+            if !take(page) {
+                return Ok(releases);
+            }
+        }
+    }
+    Ok(releases)
+}
+
+async fn fetch_cpython_releases() -> Result<Vec<Python>, Error> {
+    let (owner, repo) = cpython_repository()?;
+    // Each asset keeps its release's date, which flattening the releases would lose.
+    let assets = list_releases(owner, repo, releases_since())
         .await?
-        .items
         .into_iter()
-        .filter(|release| {
-            release.created_at
-                > Some(
-                    chrono::DateTime::parse_from_rfc3339("2022-02-26T00:00:00Z")
-                        .expect("Could not parse hardcoded datetime.")
-                        .into(),
-                )
+        .flat_map(|release| {
+            let released = release.published_at.or(release.created_at);
+            release
+                .assets
+                .into_iter()
+                .map(move |asset| (asset, released))
         })
-        .flat_map(|release| release.assets)
-        .filter(|asset| !asset.name.ends_with(".sha256"))
-        .filter(|asset| asset.name.contains(CURRENT_PLATFORM))
-        .map(|asset| {
-            let (release_tag, version) = parse_cpython_filename(&asset.name)?;
-            Ok(Python {
+        .filter(|(asset, _)| asset_matches_platform(&asset.name))
+        .collect::<Vec<_>>();
+    let checksums = checksum_urls(assets.iter().map(|(asset, _)| asset));
+    Ok(assets
+        .into_iter()
+        .filter(|(asset, _)| Compression::from_name(&asset.name).is_some())
+        .filter_map(|(asset, released)| {
+            let (release_tag, version) = skip_invalid(parse_cpython_filename(&asset.name))?;
+            Some(Python {
+                sha256_url: checksums.get(&asset.name).cloned(),
+                size: u64::try_from(asset.size).ok(),
                 name: asset.name,
                 url: asset.browser_download_url,
                 version,
                 release_tag,
                 debug: version.debug,
                 freethreaded: version.freethreaded,
+                released,
             })
         })
+        .collect())
+}
+
+/// The names of every CPython release asset, for every platform.
+pub async fn cpython_asset_names() -> Result<Vec<String>, Error> {
+    let (owner, repo) = cpython_repository()?;
+    Ok(list_releases(owner, repo, releases_since())
+        .await?
+        .into_iter()
+        .flat_map(|release| release.assets)
+        .map(|asset| asset.name)
+        .collect())
+}
+
+/// The target triple of an `install_only` CPython archive, like `x86_64-unknown-linux-gnu` for
+/// `cpython-3.12.4+20240713-x86_64-unknown-linux-gnu-install_only.tar.gz`.
+pub fn install_only_platform(name: &str) -> Option<&str> {
+    Compression::from_name(name)?;
+    let (_, rest) = name.split_once('+')?;
+    let (_, rest) = rest.split_once('-')?;
+    let (platform, _) = rest.split_once("-install_only")?;
+    Some(platform)
+}
+
+/// The `.sha256` checksum asset url for each release asset that has one, by asset name.
+fn checksum_urls<'a>(
+    assets: impl IntoIterator<Item = &'a octocrab::models::repos::Asset>,
+) -> HashMap<String, Url> {
+    assets
+        .into_iter()
+        .filter_map(|asset| {
+            asset
+                .name
+                .strip_suffix(".sha256")
+                .map(|name| (name.to_string(), asset.browser_download_url.clone()))
+        })
         .collect()
 }
 
-fn pypy_platform_tag() -> Result<&'static str, Error> {
-    match CURRENT_PLATFORM {
-        "x86_64-unknown-linux-gnu" => Ok("linux64"),
-        "x86_64-apple-darwin" => Ok("macos_x86_64"),
-        "aarch64-unknown-linux-gnu" => Ok("aarch64"),
-        "aarch64-apple-darwin" => Ok("macos_arm64"),
-        _ => Err(Error::Platform(CURRENT_PLATFORM.to_string())),
+/// Split a target triple into its architecture, operating system and (optional) environment.
+fn split_triple(triple: &str) -> Option<(&str, &str, Option<&str>)> {
+    let mut parts = triple.split('-');
+    let arch = parts.next()?;
+    let _vendor = parts.next()?;
+    let os = parts.next()?;
+    let env = match os {
+        "darwin" => None,
+        _ => parts.next(),
+    };
+    Some((arch, os, env))
+}
+
+/// The platform builds are downloaded for: a target triple split into its parts, and the version
+/// of its libc when that's the running glibc.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub triple: String,
+    pub arch: String,
+    pub os: String,
+    pub env: Option<String>,
+    pub libc_version: Option<(u32, u32)>,
+}
+
+impl Target {
+    /// Parse a target triple like `x86_64-unknown-linux-gnu`, without a libc version.
+    pub fn parse(triple: &str) -> Option<Self> {
+        let (arch, os, env) = split_triple(triple)?;
+        Some(Target {
+            triple: triple.to_string(),
+            arch: arch.to_string(),
+            os: os.to_string(),
+            env: env.map(str::to_string),
+            libc_version: None,
+        })
+    }
+
+    /// Whether builds for `triple` are for this platform. The vendor doesn't matter, but the
+    /// architecture variant does, so `x86_64_v3` builds don't match `x86_64`.
+    fn matches(&self, triple: &str) -> bool {
+        split_triple(triple).is_some_and(|(arch, os, env)| {
+            arch == self.arch && os == self.os && env == self.env.as_deref()
+        })
     }
 }
 
-pub fn pypy_releases() -> Result<Vec<Python>, Error> {
-    let html = reqwest::blocking::get("https://www.pypy.org/download.html")?.text()?;
-    let document = scraper::Html::parse_document(&html);
-    let selector = match scraper::Selector::parse("table>tbody>tr>td>p>a") {
-        Ok(selector) => selector,
-        Err(_) => Err(Error::Scraper(
-            "Could not find table of pypy downloads.".to_string(),
-        ))?,
+fn asset_matches(name: &str, target: &Target) -> bool {
+    name.split_once('+')
+        .and_then(|(_, rest)| rest.split_once('-'))
+        .is_some_and(|(_, triple)| target.matches(triple))
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Libc {
+    Gnu,
+    Musl,
+}
+
+impl Libc {
+    fn detect() -> Self {
+        let has_loader = |prefix: &str| {
+            ["/lib", "/lib64"].iter().any(|dir| {
+                std::fs::read_dir(dir).is_ok_and(|entries| {
+                    entries.flatten().any(|entry| {
+                        entry
+                            .file_name()
+                            .to_str()
+                            .is_some_and(|name| name.starts_with(prefix))
+                    })
+                })
+            })
+        };
+        // musl can be installed alongside glibc, so only prefer it when glibc is absent.
+        let musl_only = has_loader("ld-musl-") && !has_loader("ld-linux");
+        match musl_only || std::path::Path::new("/etc/alpine-release").exists() {
+            true => Libc::Musl,
+            false => Libc::Gnu,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Libc::Gnu => "gnu",
+            Libc::Musl => "musl",
+        }
+    }
+}
+
+static TARGET: OnceLock<Target> = OnceLock::new();
+
+/// The version of the running glibc, like `(2, 39)`.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn glibc_version() -> Option<(u32, u32)> {
+    // SAFETY: gnu_get_libc_version returns a pointer to a static, nul terminated string.
+    let version = unsafe { std::ffi::CStr::from_ptr(libc::gnu_get_libc_version()) };
+    let (major, minor) = version.to_str().ok()?.split_once('.')?;
+    let minor = minor.split('.').next()?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+fn glibc_version() -> Option<(u32, u32)> {
+    None
+}
+
+/// The platform lilyenv runs on, with the libc it detects or is given.
+fn native_target(libc: Option<Libc>) -> Target {
+    let triple = platform_with_libc(CURRENT_PLATFORM, libc);
+    let mut target = Target::parse(&triple).expect("lilyenv's own platform is a target triple.");
+    if target.env.as_deref() == Some("gnu") {
+        target.libc_version = glibc_version();
+    }
+    target
+}
+
+/// Detect the platform to download builds for, unless `--platform` or `--libc` chose one.
+fn detect_target() -> Target {
+    native_target(None)
+}
+
+/// Replace the libc of a Linux target triple, detecting the running libc if none is given.
+fn platform_with_libc(platform: &str, libc: Option<Libc>) -> String {
+    match platform
+        .strip_suffix("-linux-gnu")
+        .or_else(|| platform.strip_suffix("-linux-musl"))
+    {
+        Some(base) => {
+            let libc = libc.unwrap_or_else(Libc::detect);
+            format!("{base}-linux-{}", libc.as_str())
+        }
+        None => platform.to_string(),
+    }
+}
+
+/// Select the libc of the builds to download. Must be called before any releases are listed.
+pub fn set_libc(libc: Libc) {
+    let _ = TARGET.set(native_target(Some(libc)));
+}
+
+/// Download builds for another platform's target triple, like `aarch64-apple-darwin`, with `libc`
+/// replacing a Linux triple's libc. Must be called before any releases are listed.
+pub fn set_platform(platform: &str, libc: Option<Libc>) -> Result<(), Error> {
+    let platform = match libc {
+        Some(libc) => platform_with_libc(platform, Some(libc)),
+        None => platform.to_string(),
     };
-    let tag = pypy_platform_tag()?;
-    document
+    let target = Target::parse(&platform).ok_or(Error::Platform(platform))?;
+    let _ = TARGET.set(target);
+    Ok(())
+}
+
+pub fn target() -> &'static Target {
+    TARGET.get_or_init(detect_target)
+}
+
+pub fn target_platform() -> &'static str {
+    &target().triple
+}
+
+/// The target platform, if its builds can't run here because it is for another architecture or
+/// operating system.
+pub fn foreign_platform() -> Option<&'static str> {
+    let target = target();
+    let (current_arch, current_os, _) = split_triple(CURRENT_PLATFORM)?;
+    match (target.arch.as_str(), target.os.as_str()) == (current_arch, current_os) {
+        true => None,
+        false => Some(&target.triple),
+    }
+}
+
+pub fn asset_matches_platform(name: &str) -> bool {
+    asset_matches(name, target())
+}
+
+fn skip_invalid<T>(parsed: Result<T, Error>) -> Option<T> {
+    match parsed {
+        Ok(parsed) => Some(parsed),
+        Err(err) => {
+            log::warn!("{err} Skipping it.");
+            None
+        }
+    }
+}
+
+/// How a CPython archive ranks among the builds of one release, lowest first: the `pgo+lto`
+/// full build before the `pgo`, `lto` and `noopt` ones, and `install_only` before
+/// `install_only_stripped`.
+fn build_rank(name: &str) -> usize {
+    const BUILDS: [&str; 6] = [
+        "pgo+lto-full.",
+        "install_only.",
+        "pgo-full.",
+        "lto-full.",
+        "install_only_stripped.",
+        "noopt-full.",
+    ];
+    BUILDS
+        .iter()
+        .position(|build| name.contains(build))
+        .unwrap_or(BUILDS.len())
+}
+
+/// The newest release compatible with `version`, by `version_key` then the release tag, and
+/// the preferred build of it. Among equally preferred assets the first listed is kept.
+pub fn newest_compatible(releases: Vec<Python>, version: &Version) -> Option<Python> {
+    fn key(python: &Python) -> (VersionKey, &str, std::cmp::Reverse<usize>) {
+        (
+            python.version_key(),
+            &python.release_tag,
+            std::cmp::Reverse(build_rank(&python.name)),
+        )
+    }
+    releases
+        .into_iter()
+        .filter(|python| matches(version, &python.version))
+        .fold(None, |newest, python| match &newest {
+            Some(newest_python) if key(newest_python) >= key(&python) => newest,
+            _ => Some(python),
+        })
+}
+
+/// The platform in PyPy's download names for a target. PyPy only builds for glibc on Linux.
+fn pypy_platform_tag(target: &Target) -> Option<&'static str> {
+    match (
+        target.arch.as_str(),
+        target.os.as_str(),
+        target.env.as_deref(),
+    ) {
+        ("x86_64", "linux", Some("gnu")) => Some("linux64"),
+        ("i686", "linux", Some("gnu")) => Some("linux32"),
+        ("aarch64", "linux", Some("gnu")) => Some("aarch64"),
+        ("s390x", "linux", Some("gnu")) => Some("s390x"),
+        ("x86_64", "darwin", None) => Some("macos_x86_64"),
+        ("aarch64", "darwin", None) => Some("macos_arm64"),
+        ("x86_64", "windows", Some("msvc")) => Some("win64"),
+        _ => None,
+    }
+}
+
+/// Bumped when the cached PyPy pages need to be fetched again.
+const PYPY_CACHE_FORMAT: u32 = 1;
+
+/// A PyPy page, with the validators to check whether it has changed.
+#[derive(Serialize, Deserialize)]
+struct PypyPageCache {
+    format: u32,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    html: String,
+}
+
+/// Fetch a PyPy page, reusing the copy cached in `file` if the server says it hasn't changed.
+fn pypy_page(url: &str, file: &Path) -> Result<String, Error> {
+    let cache = read_cache::<PypyPageCache>(file).filter(|cache| cache.format == PYPY_CACHE_FORMAT);
+    let response = with_retry(|| {
+        let request = client()?.get(url);
+        let request = match cache.as_ref().and_then(|cache| cache.etag.as_ref()) {
+            Some(etag) => request.header(reqwest::header::IF_NONE_MATCH, etag),
+            None => request,
+        };
+        let request = match cache
+            .as_ref()
+            .and_then(|cache| cache.last_modified.as_ref())
+        {
+            Some(last_modified) => {
+                request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified)
+            }
+            None => request,
+        };
+        let response = request.send()?.error_for_status()?;
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let etag = header(reqwest::header::ETAG);
+        let last_modified = header(reqwest::header::LAST_MODIFIED);
+        Ok(Some(PypyPageCache {
+            format: PYPY_CACHE_FORMAT,
+            etag,
+            last_modified,
+            html: response.text()?,
+        }))
+    })?;
+    match (response, cache) {
+        (Some(page), _) => {
+            write_cache(file, &page)?;
+            Ok(page.html)
+        }
+        (None, Some(cache)) => {
+            log::debug!("Using cached {url} from {}", file.display());
+            Ok(cache.html)
+        }
+        (None, None) => Err(Error::Scraper(format!(
+            "{url} was said to be unchanged, but it isn't cached."
+        ))),
+    }
+}
+
+/// Where PyPy is downloaded from, ending in a `/`.
+pub(crate) fn pypy_download_url() -> String {
+    match &config().pypy_download_url {
+        Some(url) if url.as_str().ends_with('/') => url.to_string(),
+        Some(url) => format!("{url}/"),
+        None => PYPY_DOWNLOAD_URL.to_string(),
+    }
+}
+
+/// Every PyPy download link on the page, wherever it is in the page's structure, moved under
+/// `base`.
+fn pypy_download_links(html: &str, base: &str) -> Result<Vec<String>, Error> {
+    let document = scraper::Html::parse_document(html);
+    let selector =
+        scraper::Selector::parse("a[href]").expect("Could not parse hardcoded selector.");
+    let mut links = document
         .select(&selector)
-        .map(|link| {
-            link.value()
-                .attr("href")
-                .expect("A pypy download <a> tag has a href attribute.")
+        .filter_map(|link| link.value().attr("href"))
+        .filter_map(|link| {
+            link.strip_prefix(PYPY_DOWNLOAD_URL)
+                .or_else(|| link.strip_prefix(base))
         })
-        .filter(|link| link.starts_with(PYPY_DOWNLOAD_URL))
-        .filter(|link| link.contains(tag))
-        .map(|url| {
-            let (name, release_tag, version) = parse_pypy_url(url)?;
-            Ok(Python {
+        .map(|filename| format!("{base}{filename}"))
+        .collect::<Vec<_>>();
+    if links.is_empty() {
+        return Err(Error::Scraper(format!(
+            "Could not find any PyPy downloads on the PyPy download page. It may have changed, please report this at https://github.com/LilyFoote/lilyenv/issues. PyPy releases can be found at {PYPY_DOWNLOAD_URL}."
+        )));
+    }
+    links.sort_unstable();
+    links.dedup();
+    Ok(links)
+}
+
+/// Every file in the directory listing of `base`, which holds older PyPy releases as well as
+/// the ones on the download page. The listing links relative to `base`.
+fn pypy_index_links(html: &str, base: &str) -> Result<Vec<String>, Error> {
+    let base_url = Url::parse(base)?;
+    let document = scraper::Html::parse_document(html);
+    let selector =
+        scraper::Selector::parse("a[href]").expect("Could not parse hardcoded selector.");
+    let mut links = document
+        .select(&selector)
+        .filter_map(|link| link.value().attr("href"))
+        .filter_map(|link| base_url.join(link).ok())
+        .map(|url| url.to_string())
+        .filter(|url| {
+            url.strip_prefix(base)
+                .is_some_and(|filename| !filename.is_empty() && !filename.contains(['/', '?']))
+        })
+        .collect::<Vec<_>>();
+    links.sort_unstable();
+    links.dedup();
+    Ok(links)
+}
+
+fn pypy_python(url: &str, base: &str) -> Result<Python, Error> {
+    let (name, release_tag, version) = parse_pypy_url(url, base)?;
+    Ok(Python {
+        name,
+        url: Url::parse(url)?,
+        version,
+        release_tag,
+        debug: false,
+        freethreaded: false,
+        sha256_url: None,
+        size: None,
+        released: None,
+    })
+}
+
+/// Every PyPy release for the target platform, from the download page and the listing of older
+/// releases.
+pub fn pypy_releases() -> Result<Vec<Python>, Error> {
+    let base = pypy_download_url();
+    let links = pypy_download_links(
+        &pypy_page("https://www.pypy.org/download.html", &pypy_cache_file())?,
+        &base,
+    )?;
+    let older = match pypy_page(&base, &pypy_index_cache_file())
+        .and_then(|html| pypy_index_links(&html, &base))
+    {
+        Ok(older) => older,
+        Err(err) => {
+            log::warn!("Could not list older PyPy releases: {err}");
+            vec![]
+        }
+    };
+    let tag = match pypy_platform_tag(target()) {
+        Some(tag) => format!("-{tag}."),
+        None => return Err(Error::Platform(target_platform().to_string())),
+    };
+    let for_platform =
+        |link: &&String| link.contains(&tag) && Compression::from_name(link).is_some();
+    let mut releases = links
+        .iter()
+        .filter(for_platform)
+        .filter_map(|url| skip_invalid(pypy_python(url, &base)))
+        .collect::<Vec<_>>();
+    // The listing goes back to PyPy releases named in ways lilyenv can't install, so these are
+    // skipped quietly.
+    releases.extend(
+        older
+            .iter()
+            .filter(for_platform)
+            .filter(|url| !links.contains(url))
+            .filter_map(|url| match pypy_python(url, &base) {
+                Ok(python) => Some(python),
+                Err(err) => {
+                    log::debug!("{err} Skipping it.");
+                    None
+                }
+            }),
+    );
+    Ok(releases)
+}
+
+/// The releases for this platform with an archive already in the downloads directory, with
+/// `file://` urls, for installing without the network.
+pub fn downloaded_releases() -> Result<Vec<Python>, Error> {
+    let entries = match std::fs::read_dir(downloads_dir()) {
+        Ok(entries) => entries,
+        Err(err) => match err.kind() {
+            std::io::ErrorKind::NotFound => return Ok(vec![]),
+            _ => return Err(err)?,
+        },
+    };
+    let pypy_tag = pypy_platform_tag(target()).map(|tag| format!("-{tag}."));
+    let graalpy_tag = graalpy_platform_tag().ok().map(|tag| format!("-{tag}."));
+    let matches_tag =
+        |name: &str, tag: &Option<String>| tag.as_ref().is_some_and(|tag| name.contains(tag));
+    Ok(entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            Compression::from_name(&name)?;
+            let (release_tag, version) = match name.as_str() {
+                name if name.starts_with("cpython-") && asset_matches_platform(name) => {
+                    parse_cpython_filename(name).ok()?
+                }
+                name if name.starts_with("pypy") && matches_tag(name, &pypy_tag) => {
+                    let url = format!("{PYPY_DOWNLOAD_URL}{name}");
+                    let (_, release_tag, version) = parse_pypy_url(&url, PYPY_DOWNLOAD_URL).ok()?;
+                    (release_tag, version)
+                }
+                name if name.starts_with("graalpy-community-")
+                    && matches_tag(name, &graalpy_tag) =>
+                {
+                    let version = parse_graalpy_filename(name).ok()?;
+                    let release_tag = format!(
+                        "graal-{}.{}.{}",
+                        version.major, version.minor, version.bugfix?
+                    );
+                    (release_tag, version)
+                }
+                _ => return None,
+            };
+            Some(Python {
+                url: Url::from_file_path(entry.path()).ok()?,
                 name,
-                url: Url::parse(url)?,
                 version,
                 release_tag,
-                debug: false,
-                freethreaded: false,
+                debug: version.debug,
+                freethreaded: version.freethreaded,
+                sha256_url: None,
+                size: None,
+                released: None,
             })
         })
-        .collect()
+        .collect())
+}
+
+fn graalpy_platform_tag() -> Result<&'static str, Error> {
+    let target = target();
+    match (
+        target.arch.as_str(),
+        target.os.as_str(),
+        target.env.as_deref(),
+    ) {
+        ("x86_64", "linux", Some("gnu")) => Ok("linux-amd64"),
+        ("x86_64", "darwin", None) => Ok("macos-amd64"),
+        ("aarch64", "linux", Some("gnu")) => Ok("linux-aarch64"),
+        ("aarch64", "darwin", None) => Ok("macos-aarch64"),
+        _ => Err(Error::Platform(target.triple.clone())),
+    }
+}
+
+/// List the native GraalPy community builds from the GraalPy GitHub releases.
+pub async fn graalpy_releases() -> Result<Vec<Python>, Error> {
+    let tag = format!("-{}.", graalpy_platform_tag()?);
+    let octocrab = github()?;
+    let releases = with_retry_async(|| async {
+        Ok(octocrab
+            .repos("oracle", "graalpython")
+            .releases()
+            .list()
+            .send()
+            .await?)
+    })
+    .await?
+    .items;
+    Ok(releases
+        .into_iter()
+        .flat_map(|release| {
+            let checksums = checksum_urls(&release.assets);
+            let release_tag = release.tag_name;
+            let released = release.published_at.or(release.created_at);
+            release
+                .assets
+                .into_iter()
+                .filter(|asset| asset.name.starts_with("graalpy-community-"))
+                .filter(|asset| asset.name.contains(&tag))
+                .filter(|asset| Compression::from_name(&asset.name).is_some())
+                .filter_map(|asset| {
+                    let version = skip_invalid(parse_graalpy_filename(&asset.name))?;
+                    Some(Python {
+                        sha256_url: checksums.get(&asset.name).cloned(),
+                        size: u64::try_from(asset.size).ok(),
+                        name: asset.name,
+                        url: asset.browser_download_url,
+                        version,
+                        release_tag: release_tag.clone(),
+                        debug: false,
+                        freethreaded: false,
+                        released,
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpython(name: &str) -> Python {
+        let (release_tag, version) = parse_cpython_filename(name).unwrap();
+        Python {
+            name: name.to_string(),
+            url: Url::parse("https://example.com/").unwrap(),
+            version,
+            release_tag,
+            debug: version.debug,
+            freethreaded: version.freethreaded,
+            sha256_url: None,
+            size: None,
+            released: None,
+        }
+    }
+
+    #[test]
+    fn test_skip_unparsable_asset() {
+        for name in [
+            "cpython-3.14.0-x86_64-unknown-linux-gnu-install_only.tar.gz",
+            "cpython-next+20990101-x86_64-unknown-linux-gnu-install_only.tar.gz",
+            "cpython+20990101.tar.gz",
+            "SHA256SUMS",
+        ] {
+            assert!(
+                skip_invalid(parse_cpython_filename(name)).is_none(),
+                "{name}"
+            );
+        }
+        let name = "cpython-3.12.4+20240713-x86_64-unknown-linux-gnu-install_only.tar.gz";
+        assert!(skip_invalid(parse_cpython_filename(name)).is_some());
+    }
+
+    #[test]
+    fn test_newest_compatible() {
+        let releases = vec![
+            cpython("cpython-3.11.7+20240107-x86_64-unknown-linux-gnu-install_only.tar.gz"),
+            cpython("cpython-3.11.9+20240415-x86_64-unknown-linux-gnu-install_only.tar.gz"),
+            cpython(
+                "cpython-3.11.9+20240726-x86_64-unknown-linux-gnu-install_only_stripped.tar.gz",
+            ),
+            cpython("cpython-3.11.9+20240726-x86_64-unknown-linux-gnu-install_only.tar.gz"),
+            cpython("cpython-3.11.9+20240726-x86_64-unknown-linux-gnu-noopt-full.tar.zst"),
+            cpython("cpython-3.11.9+20240726-x86_64-unknown-linux-gnu-lto-full.tar.zst"),
+            cpython("cpython-3.11.9+20240726-x86_64-unknown-linux-gnu-pgo-full.tar.zst"),
+            cpython("cpython-3.11.9+20240726-x86_64-unknown-linux-gnu-pgo+lto-full.tar.zst"),
+            cpython("cpython-3.11.9+20240726-x86_64-unknown-linux-gnu-lto-full.tar.zst"),
+            cpython("cpython-3.11.8+20240726-x86_64-unknown-linux-gnu-install_only.tar.gz"),
+            cpython("cpython-3.12.1+20240107-x86_64-unknown-linux-gnu-install_only.tar.gz"),
+        ];
+        let full = newest_compatible(
+            releases
+                .iter()
+                .map(|python| cpython(&python.name))
+                .collect(),
+            &"3.11-full".parse().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            full.name,
+            "cpython-3.11.9+20240726-x86_64-unknown-linux-gnu-pgo+lto-full.tar.zst"
+        );
+        let newest = newest_compatible(releases, &"3.11".parse().unwrap()).unwrap();
+        assert_eq!(
+            newest.name,
+            "cpython-3.11.9+20240726-x86_64-unknown-linux-gnu-install_only.tar.gz"
+        );
+    }
+
+    #[test]
+    fn test_newest_compatible_pypy() {
+        let pypy = |release| {
+            pypy_python(
+                &format!("{PYPY_DOWNLOAD_URL}pypy3.10-{release}-linux64.tar.bz2"),
+                PYPY_DOWNLOAD_URL,
+            )
+            .unwrap()
+        };
+        let releases = || vec![pypy("v7.3.16"), pypy("v8.0.0"), pypy("v7.3.17")];
+        let newest = newest_compatible(releases(), &"pypy3.10".parse().unwrap()).unwrap();
+        assert_eq!(newest.release_tag, "v8.0.0");
+        let pinned = newest_compatible(releases(), &"pypy3.10.17".parse().unwrap()).unwrap();
+        assert_eq!(pinned.release_tag, "v7.3.17");
+        let installed = pypy("v7.3.17");
+        assert!(newest.version_key() > version_key(&installed.version, &installed.release_tag));
+    }
+
+    #[test]
+    fn test_platform_with_libc() {
+        assert_eq!(
+            platform_with_libc("x86_64-unknown-linux-gnu", Some(Libc::Musl)),
+            "x86_64-unknown-linux-musl"
+        );
+        assert_eq!(
+            platform_with_libc("aarch64-unknown-linux-musl", Some(Libc::Gnu)),
+            "aarch64-unknown-linux-gnu"
+        );
+        assert_eq!(
+            platform_with_libc("aarch64-apple-darwin", Some(Libc::Musl)),
+            "aarch64-apple-darwin"
+        );
+    }
+
+    #[test]
+    fn test_target_parse() {
+        let linux = Target::parse("aarch64-unknown-linux-musl").unwrap();
+        assert_eq!(
+            (linux.arch.as_str(), linux.os.as_str(), linux.env.as_deref()),
+            ("aarch64", "linux", Some("musl"))
+        );
+        let mac = Target::parse("x86_64-apple-darwin").unwrap();
+        assert_eq!((mac.os.as_str(), mac.env.as_deref()), ("darwin", None));
+        assert!(mac.matches("x86_64-apple-darwin"));
+        assert!(!mac.matches("aarch64-apple-darwin"));
+        assert_eq!(Target::parse("x86_64"), None);
+    }
+
+    #[test]
+    fn test_asset_matches() {
+        let linux = &Target::parse("x86_64-unknown-linux-gnu").unwrap();
+        assert!(asset_matches(
+            "cpython-3.12.1+20240107-x86_64-unknown-linux-gnu-install_only.tar.gz",
+            linux
+        ));
+        assert!(asset_matches(
+            "cpython-3.13.1+20250115-x86_64-unknown-linux-gnu-freethreaded+debug-full.tar.zst",
+            linux
+        ));
+        assert!(!asset_matches(
+            "cpython-3.12.1+20240107-x86_64_v3-unknown-linux-gnu-install_only.tar.gz",
+            linux
+        ));
+        assert!(!asset_matches(
+            "cpython-3.12.1+20240107-x86_64-unknown-linux-musl-install_only.tar.gz",
+            linux
+        ));
+        assert!(!asset_matches(
+            "cpython-3.12.1+20240107-aarch64-unknown-linux-gnu-install_only.tar.gz",
+            linux
+        ));
+
+        let mac = &Target::parse("aarch64-apple-darwin").unwrap();
+        assert!(asset_matches(
+            "cpython-3.12.1+20240107-aarch64-apple-darwin-install_only.tar.gz",
+            mac
+        ));
+        assert!(asset_matches(
+            "cpython-3.11.9+20240415-aarch64-apple-darwin-debug-full.tar.zst",
+            mac
+        ));
+        assert!(!asset_matches(
+            "cpython-3.12.1+20240107-x86_64-apple-darwin-install_only.tar.gz",
+            mac
+        ));
+
+        let windows = &Target::parse("x86_64-pc-windows-msvc").unwrap();
+        assert!(asset_matches(
+            "cpython-3.12.1+20240107-x86_64-pc-windows-msvc-shared-install_only.tar.gz",
+            windows
+        ));
+        assert!(!asset_matches(
+            "cpython-3.12.1+20240107-i686-pc-windows-msvc-shared-install_only.tar.gz",
+            windows
+        ));
+    }
+
+    #[test]
+    fn test_install_only_platform() {
+        for (name, expected) in [
+            (
+                "cpython-3.12.4+20240713-x86_64-unknown-linux-gnu-install_only.tar.gz",
+                Some("x86_64-unknown-linux-gnu"),
+            ),
+            (
+                "cpython-3.13.0+20241016-aarch64-apple-darwin-install_only_stripped.tar.gz",
+                Some("aarch64-apple-darwin"),
+            ),
+            (
+                "cpython-3.12.4+20240713-x86_64_v3-unknown-linux-musl-install_only.tar.gz",
+                Some("x86_64_v3-unknown-linux-musl"),
+            ),
+            (
+                "cpython-3.12.4+20240713-x86_64-unknown-linux-gnu-install_only.tar.gz.sha256",
+                None,
+            ),
+            (
+                "cpython-3.12.4+20240713-x86_64-unknown-linux-gnu-pgo+lto-full.tar.zst",
+                None,
+            ),
+        ] {
+            assert_eq!(install_only_platform(name), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_pypy_download_links() {
+        let html = r#"<html><body>
+            <div><a href="https://downloads.python.org/pypy/pypy3.10-v7.3.17-linux64.tar.bz2">Linux</a></div>
+            <table><tr><td><a href="https://downloads.python.org/pypy/pypy3.10-v7.3.17-macos_arm64.tar.bz2">macOS</a></td></tr></table>
+            <a href="https://downloads.python.org/pypy/pypy3.10-v7.3.17-linux64.tar.bz2">Linux again</a>
+            <a href="https://www.pypy.org/">Home</a>
+            <a>No link</a>
+        </body></html>"#;
+        assert_eq!(
+            pypy_download_links(html, PYPY_DOWNLOAD_URL).unwrap(),
+            vec![
+                "https://downloads.python.org/pypy/pypy3.10-v7.3.17-linux64.tar.bz2",
+                "https://downloads.python.org/pypy/pypy3.10-v7.3.17-macos_arm64.tar.bz2",
+            ]
+        );
+        assert_eq!(
+            pypy_download_links(html, "https://mirror.example.com/pypy/").unwrap(),
+            vec![
+                "https://mirror.example.com/pypy/pypy3.10-v7.3.17-linux64.tar.bz2",
+                "https://mirror.example.com/pypy/pypy3.10-v7.3.17-macos_arm64.tar.bz2",
+            ]
+        );
+        assert!(matches!(
+            pypy_download_links(
+                "<html><a href=\"https://www.pypy.org/\">Home</a></html>",
+                PYPY_DOWNLOAD_URL
+            ),
+            Err(Error::Scraper(_))
+        ));
+    }
+
+    #[test]
+    fn test_pypy_index_links() {
+        let html = r#"<html><body><pre>
+            <a href="../">../</a>
+            <a href="?C=M;O=A">Last modified</a>
+            <a href="pypy3.10-v7.3.17-linux64.tar.bz2">pypy3.10-v7.3.17-linux64.tar.bz2</a>
+            <a href="pypy3.9-v7.3.11-linux64.tar.bz2">pypy3.9-v7.3.11-linux64.tar.bz2</a>
+            <a href="https://downloads.python.org/pypy/pypy3.8-v7.3.9-linux64.tar.bz2">absolute</a>
+            <a href="nightly/">nightly/</a>
+        </pre></body></html>"#;
+        assert_eq!(
+            pypy_index_links(html, PYPY_DOWNLOAD_URL).unwrap(),
+            vec![
+                "https://downloads.python.org/pypy/pypy3.10-v7.3.17-linux64.tar.bz2",
+                "https://downloads.python.org/pypy/pypy3.8-v7.3.9-linux64.tar.bz2",
+                "https://downloads.python.org/pypy/pypy3.9-v7.3.11-linux64.tar.bz2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pypy_listing_release_series() {
+        let html = r#"<html><body><pre>
+            <a href="pypy3.10-v8.0.1-linux64.tar.bz2">pypy3.10-v8.0.1-linux64.tar.bz2</a>
+            <a href="pypy3.10-v7.3.17-linux64.tar.bz2">pypy3.10-v7.3.17-linux64.tar.bz2</a>
+            <a href="pypy3.10-v8.0.0-linux64.tar.bz2">pypy3.10-v8.0.0-linux64.tar.bz2</a>
+            <a href="pypy3.10-v7.3.16-linux64.tar.bz2">pypy3.10-v7.3.16-linux64.tar.bz2</a>
+        </pre></body></html>"#;
+        let mut releases = pypy_index_links(html, PYPY_DOWNLOAD_URL)
+            .unwrap()
+            .iter()
+            .map(|url| pypy_python(url, PYPY_DOWNLOAD_URL).unwrap())
+            .collect::<Vec<_>>();
+        crate::output::sort_releases(&mut releases, crate::output::ReleaseOrder::Version);
+        assert_eq!(
+            releases
+                .iter()
+                .map(|python| (python.version.to_string(), python.release_tag.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("pypy3.10.16".to_string(), "v7.3.16"),
+                ("pypy3.10.17".to_string(), "v7.3.17"),
+                ("pypy3.10.0".to_string(), "v8.0.0"),
+                ("pypy3.10.1".to_string(), "v8.0.1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pypy_platform_tag() {
+        assert_eq!(
+            pypy_platform_tag(&Target::parse("x86_64-unknown-linux-gnu").unwrap()),
+            Some("linux64")
+        );
+        assert_eq!(
+            pypy_platform_tag(&Target::parse("aarch64-unknown-linux-gnu").unwrap()),
+            Some("aarch64")
+        );
+        assert_eq!(
+            pypy_platform_tag(&Target::parse("aarch64-apple-darwin").unwrap()),
+            Some("macos_arm64")
+        );
+        assert_eq!(
+            pypy_platform_tag(&Target::parse("x86_64-apple-darwin").unwrap()),
+            Some("macos_x86_64")
+        );
+        assert_eq!(
+            pypy_platform_tag(&Target::parse("x86_64-pc-windows-msvc").unwrap()),
+            Some("win64")
+        );
+        assert_eq!(
+            pypy_platform_tag(&Target::parse("x86_64-unknown-linux-musl").unwrap()),
+            None
+        );
+        assert_eq!(
+            pypy_platform_tag(&Target::parse("riscv64gc-unknown-linux-gnu").unwrap()),
+            None
+        );
+    }
 }
@@ -1,5 +1,9 @@
-use crate::directories::shell_file;
+use crate::config::config;
+use crate::directories::{shell_file, shell_startup_file, virtualenv_dir};
+use crate::disk::WithPath;
 use crate::error::Error;
+use crate::project::python_version_project;
+use crate::virtualenvs::EnvFormat;
 
 pub fn set_shell(shell: &str, project: Option<&str>) -> Result<(), Error> {
     let file = shell_file(project);
@@ -8,8 +12,8 @@ pub fn set_shell(shell: &str, project: Option<&str>) -> Result<(), Error> {
         Err(err) => match err.kind() {
             std::io::ErrorKind::NotFound => {
                 let dir = file.parent().expect("shell file always has a parent");
-                std::fs::create_dir_all(dir)?;
-                std::fs::write(&file, shell)?;
+                std::fs::create_dir_all(dir).with_path(dir)?;
+                std::fs::write(&file, shell).with_path(&file)?;
             }
             _ => Err(err)?,
         },
@@ -17,25 +21,328 @@ pub fn set_shell(shell: &str, project: Option<&str>) -> Result<(), Error> {
     Ok(())
 }
 
+/// The user's shell from `SHELL`, falling back to the platform's standard shell when it isn't
+/// set, as on Windows.
+pub fn detect_shell() -> String {
+    match std::env::var("SHELL") {
+        Ok(shell) if !shell.trim().is_empty() => shell,
+        _ if cfg!(windows) => "powershell".to_string(),
+        _ => "/bin/sh".to_string(),
+    }
+}
+
 pub fn get_shell(project: Option<&str>) -> Result<String, Error> {
     match std::fs::read_to_string(shell_file(project)) {
         Ok(shell) => Ok(shell),
         Err(err) => match err.kind() {
             std::io::ErrorKind::NotFound => match project {
                 Some(_project) => get_shell(None),
-                None => Ok(std::env::var("SHELL")?),
+                None => match &config().default_shell {
+                    Some(shell) => Ok(shell.clone()),
+                    None => Ok(detect_shell()),
+                },
             },
             _ => Err(err)?,
         },
     }
 }
 
-pub fn print_shell_config(project: Option<&str>) -> Result<(), Error> {
-    match get_shell(project)?.as_str() {
-        "bash" => println!(include_str!("bash_config")),
-        "zsh" => println!(include_str!("zsh_config")),
-        "fish" => println!(include_str!("fish_config")),
-        _ => println!("Unknown shell"),
+/// The name of `shell` without its directory, or `.exe` on Windows.
+fn shell_name(shell: &str) -> &str {
+    std::path::Path::new(shell)
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or(shell)
+}
+
+/// Startup files lilyenv wrote for an activated subshell, removed when this is dropped once the
+/// subshell exits.
+pub struct SubshellStartup {
+    _dir: Option<tempfile::TempDir>,
+}
+
+/// The variables a subshell started with `--clean-env` still inherits, as a shell needs them to
+/// work. `PATH` is set by activating.
+const ESSENTIAL_ENV: &[&str] = &[
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "TERM",
+    "LANG",
+    "LC_ALL",
+    "TMPDIR",
+    "SYSTEMROOT",
+    "USERPROFILE",
+    "TEMP",
+    "TMP",
+    "COMSPEC",
+    "PATHEXT",
+];
+
+/// The command to start `shell` as an activated subshell. Bash and zsh get startup files that run
+/// the user's own and then add `VIRTUAL_ENV_PROMPT` to the prompt, so it shows whether or not the
+/// user has installed `lilyenv shell-config`. With `clean_env`, only the essential variables and
+/// those listed are inherited.
+pub fn subshell_command(
+    shell: &str,
+    clean_env: Option<&[String]>,
+) -> Result<(std::process::Command, SubshellStartup), Error> {
+    let mut command = std::process::Command::new(shell);
+    if let Some(keep) = clean_env {
+        // Clearing removes any variables already set on the command, so this must come first.
+        command.env_clear();
+        let keep = ESSENTIAL_ENV
+            .iter()
+            .copied()
+            .chain(keep.iter().map(String::as_str));
+        for name in keep {
+            if let Some(value) = std::env::var_os(name) {
+                command.env(name, value);
+            }
+        }
+    }
+    let files: &[(&str, String)] = match shell_name(shell) {
+        "bash" => &[(
+            "bashrc",
+            [include_str!("bash_rcfile"), include_str!("bash_prompt")].join(""),
+        )],
+        "zsh" => &[
+            (".zshenv", include_str!("zsh_zshenv").to_string()),
+            (
+                ".zshrc",
+                [include_str!("zsh_zshrc"), include_str!("zsh_prompt")].join(""),
+            ),
+        ],
+        _ => return Ok((command, SubshellStartup { _dir: None })),
+    };
+    // A new directory with a random name only the user can use, so nobody else can swap in
+    // their own startup files.
+    let mut builder = tempfile::Builder::new();
+    builder.prefix("lilyenv-shell-");
+    #[cfg(unix)]
+    builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o700));
+    let temp_dir = builder.tempdir().with_path(std::env::temp_dir())?;
+    let dir = temp_dir.path().to_path_buf();
+    let startup = SubshellStartup {
+        _dir: Some(temp_dir),
+    };
+    for (name, contents) in files {
+        let file = dir.join(name);
+        std::fs::write(&file, contents).with_path(&file)?;
+    }
+    match shell_name(shell) {
+        "bash" => {
+            command.arg("--rcfile").arg(dir.join("bashrc"));
+        }
+        _ => {
+            let zdotdir = std::env::var_os("ZDOTDIR")
+                .or_else(|| std::env::var_os("HOME"))
+                .unwrap_or_default();
+            command.env("LILYENV_ZDOTDIR", zdotdir).env("ZDOTDIR", &dir);
+        }
+    }
+    Ok((command, startup))
+}
+
+/// Print the configuration for `shell`: the prompt, the activation functions and optionally the
+/// hook, or with `print_path` only the statement putting lilyenv on `PATH`.
+pub fn print_shell_config(
+    shell: Option<&str>,
+    project: Option<&str>,
+    hook: bool,
+    print_path: bool,
+) -> Result<(), Error> {
+    let shell = match shell {
+        Some(shell) => shell.to_string(),
+        None => get_shell(project)?,
+    };
+    println!("{}", shell_config(&shell, hook, print_path)?);
+    Ok(())
+}
+
+fn shell_config(shell: &str, hook: bool, print_path: bool) -> Result<String, Error> {
+    let (prompt, functions, hook_config) = match shell_name(shell) {
+        "bash" => (
+            include_str!("bash_prompt"),
+            include_str!("bash_config"),
+            Some(include_str!("bash_hook")),
+        ),
+        "zsh" => (
+            include_str!("zsh_prompt"),
+            include_str!("zsh_config"),
+            Some(include_str!("zsh_hook")),
+        ),
+        "fish" => (
+            include_str!("fish_prompt"),
+            include_str!("fish_config"),
+            Some(include_str!("fish_hook")),
+        ),
+        "pwsh" | "powershell" => (
+            include_str!("powershell_prompt"),
+            include_str!("powershell_config"),
+            None,
+        ),
+        "nu" => (include_str!("nu_prompt"), include_str!("nu_config"), None),
+        _ => Err(Error::UnsupportedShell(shell.to_string()))?,
+    };
+    if print_path {
+        return path_config(shell_name(shell));
+    }
+    let hook_config = match (hook, hook_config) {
+        (false, _) => None,
+        (true, Some(hook_config)) => Some(hook_config),
+        (true, None) => Err(Error::UnsupportedHook(shell.to_string()))?,
+    };
+    Ok([prompt, functions]
+        .into_iter()
+        .chain(hook_config)
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+const BLOCK_START: &str = "# >>> lilyenv shell-config >>>";
+const BLOCK_END: &str = "# <<< lilyenv shell-config <<<";
+
+/// Add the shell configuration to `shell`'s startup file between marker comments, or with
+/// `update`, replace the configuration already there with this lilyenv's.
+pub fn install_shell_config(
+    shell: Option<&str>,
+    project: Option<&str>,
+    hook: bool,
+    update: bool,
+) -> Result<(), Error> {
+    let shell = match shell {
+        Some(shell) => shell.to_string(),
+        None => get_shell(project)?,
+    };
+    let config = shell_config(&shell, hook, false)?;
+    let file = shell_startup_file(shell_name(&shell))
+        .ok_or_else(|| Error::UnsupportedShell(shell.clone()))?;
+    let existing = match std::fs::read_to_string(&file) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).with_path(&file),
+    };
+    let block = format!("{BLOCK_START}\n{}\n{BLOCK_END}\n", config.trim_end());
+    let contents = match existing.find(BLOCK_START) {
+        Some(_) if !update => {
+            log::info!(
+                "{} already has the lilyenv shell configuration. Pass --update to replace it.",
+                file.display()
+            );
+            return Ok(());
+        }
+        Some(start) => {
+            let end = existing[start..]
+                .find(BLOCK_END)
+                .map(|end| start + end + BLOCK_END.len())
+                .ok_or_else(|| Error::UnterminatedShellConfig(file.display().to_string()))?;
+            let rest = existing[end..]
+                .strip_prefix('\n')
+                .unwrap_or(&existing[end..]);
+            format!("{}{block}{rest}", &existing[..start])
+        }
+        None => {
+            let separator = match existing.is_empty() || existing.ends_with('\n') {
+                true => "",
+                false => "\n",
+            };
+            format!("{existing}{separator}{block}")
+        }
+    };
+    if contents == existing {
+        log::info!("{} is already up to date.", file.display());
+        return Ok(());
+    }
+    if let Some(parent) = file.parent() {
+        std::fs::create_dir_all(parent).with_path(parent)?;
+    }
+    // Written in place rather than atomically, so a symlinked startup file stays a symlink.
+    std::fs::write(&file, contents).with_path(&file)?;
+    let action = match existing.contains(BLOCK_START) {
+        true => "Updated the lilyenv shell configuration in",
+        false => "Added the lilyenv shell configuration to",
+    };
+    log::info!("{action} {}. Start a new shell to use it.", file.display());
+    Ok(())
+}
+
+/// A statement for `shell` putting the directory of the running lilyenv first on `PATH`.
+fn path_config(shell: &str) -> Result<String, Error> {
+    let exe = std::env::current_exe()?;
+    let dir = exe.parent().unwrap_or(&exe).display().to_string();
+    Ok(match shell {
+        "fish" => format!("set -gx PATH {} $PATH", quote(shell, &dir)),
+        // PowerShell has no escapes in single quoted strings, only doubled quotes.
+        "pwsh" | "powershell" => format!(
+            "$env:PATH = '{}' + [IO.Path]::PathSeparator + $env:PATH",
+            dir.replace('\'', "''")
+        ),
+        // Json strings are also valid nushell double quoted strings.
+        "nu" => format!(
+            "$env.PATH = ($env.PATH | prepend {})",
+            serde_json::to_string(&dir).expect("A string is valid json.")
+        ),
+        _ => format!("export PATH={}:\"$PATH\"", quote(shell, &dir)),
+    })
+}
+
+/// The `lilyenv env` format `project`'s shell, or the default shell, can run, falling back to
+/// `export` statements when the shell isn't known.
+pub fn shell_env_format(project: Option<&str>) -> EnvFormat {
+    match get_shell(project).as_deref().map(shell_name) {
+        Ok("pwsh" | "powershell") => EnvFormat::Powershell,
+        Ok("nu") => EnvFormat::Nushell,
+        _ => EnvFormat::Shell,
+    }
+}
+
+/// Quote a value for `shell`. Fish doesn't support the `'\''` idiom for a quote within quotes.
+fn quote(shell: &str, value: &str) -> String {
+    match shell {
+        "fish" => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'")),
+        _ => format!("'{}'", value.replace('\'', "'\\''")),
+    }
+}
+
+/// Print the statements the shell hook evaluates on each prompt: activating the virtualenv for
+/// the nearest `.python-version` file, named after its directory, or deactivating the one the
+/// hook activated after leaving it. Virtualenvs activated by hand are left alone, and
+/// virtualenvs are never created by the hook.
+pub fn print_hook(shell: Option<&str>) -> Result<(), Error> {
+    let shell = match shell {
+        Some(shell) => shell.to_string(),
+        None => get_shell(None)?,
+    };
+    let shell = shell_name(&shell);
+    let active = std::env::var("_LILYENV_HOOK").ok();
+    let wanted = match python_version_project() {
+        Ok(Some((project, version))) if virtualenv_dir(&project, &version).exists() => {
+            Some((project, version))
+        }
+        Ok(_) => None,
+        Err(err) => {
+            log::debug!("Not activating a virtualenv: {err}");
+            None
+        }
+    };
+    match (wanted, active) {
+        (Some((project, version)), active) => {
+            let key = format!("{project} {version}");
+            let manual = active.is_none() && std::env::var_os("VIRTUAL_ENV").is_some();
+            if active.as_deref() != Some(key.as_str()) && !manual {
+                println!(
+                    "lilyenv_activate {} {version} && export _LILYENV_HOOK={}",
+                    quote(shell, &project),
+                    quote(shell, &key)
+                );
+            }
+        }
+        // `lilyenv_deactivate` also forgets `_LILYENV_HOOK`, so a virtualenv activated by hand
+        // replaces the hook's.
+        (None, Some(_)) => println!("lilyenv_deactivate"),
+        (None, None) => {}
     }
     Ok(())
 }
@@ -1,64 +1,122 @@
 use crate::error::Error;
+use serde::{Deserialize, Serialize};
 
 pub const PYPY_DOWNLOAD_URL: &str = "https://downloads.python.org/pypy/";
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+/// The Python implementation a version belongs to.
+#[derive(
+    clap::ValueEnum, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize,
+)]
 pub enum Interpreter {
+    #[value(name = "cpython")]
     CPython,
+    #[value(name = "pypy")]
     PyPy,
+    #[value(name = "graalpy")]
+    GraalPy,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+impl Interpreter {
+    /// The prefix of this interpreter's versions, like `pypy` in `pypy3.10`.
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Interpreter::CPython => "",
+            Interpreter::PyPy => "pypy",
+            Interpreter::GraalPy => "graalpy",
+        }
+    }
+
+    /// The interpreter for `version`, parsed as `parsed`, when `--interpreter` asks for this one.
+    /// A version without a prefix is CPython's only by default, so it becomes this interpreter's,
+    /// but a prefix naming a different interpreter is a conflict.
+    fn select(self, parsed: Interpreter, version: &str) -> Result<Interpreter, Error> {
+        match parsed {
+            Interpreter::CPython => Ok(self),
+            parsed if parsed == self => Ok(self),
+            _ => Err(Error::InterpreterConflict {
+                interpreter: format!("{self:?}").to_lowercase(),
+                version: version.to_string(),
+            }),
+        }
+    }
+}
+
+/// A pre-release label like `rc1`. Variants are ordered so pre-releases sort before the final
+/// release.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
 pub enum PreRelease {
-    None,
     Alpha(u8),
     Beta(u8),
     RC(u8),
+    None,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+/// A Python version like `3.12`, `3.13.1t` or `pypy3.10`. Without a bugfix version it matches
+/// the newest bugfix release. `-full` versions, like `3.12-full`, are CPython's full
+/// distribution, with the build artifacts, rather than the smaller `install_only` one.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
 pub struct Version {
     pub interpreter: Interpreter,
-    pub major: u8,
-    pub minor: u8,
-    pub bugfix: Option<u8>,
+    pub major: u16,
+    pub minor: u16,
+    pub bugfix: Option<u16>,
+    pub prerelease: PreRelease,
     pub debug: bool,
     pub freethreaded: bool,
-    pub prerelease: PreRelease,
+    // Missing from metadata written before full distributions could be installed.
+    #[serde(default)]
+    pub full: bool,
+}
+
+/// Whether `candidate`, a release, satisfies `requested`, a version asked for by the user.
+///
+/// A request with a bugfix version only matches that exact release. A request without one
+/// matches any stable release of the same minor version with the same build options, so
+/// prereleases are only used when asked for exactly.
+pub fn matches(requested: &Version, candidate: &Version) -> bool {
+    if requested == candidate {
+        return true;
+    }
+    requested.bugfix.is_none()
+        && candidate.interpreter == requested.interpreter
+        && candidate.major == requested.major
+        && candidate.minor == requested.minor
+        && candidate.debug == requested.debug
+        && candidate.freethreaded == requested.freethreaded
+        && candidate.full == requested.full
+        && candidate.prerelease == PreRelease::None
+        && requested.prerelease == PreRelease::None
 }
 
 impl Version {
+    /// Whether this release satisfies the requested `other`. See [`matches`].
     pub fn compatible(&self, other: &Self) -> bool {
-        if self == other {
-            true
-        } else {
-            self.interpreter == other.interpreter
-                && self.major == other.major
-                && self.minor == other.minor
-                && self.debug == other.debug
-                && self.freethreaded == other.freethreaded
-                && other.bugfix.is_none()
-                && self.prerelease == PreRelease::None
-                && other.prerelease == PreRelease::None
-        }
+        matches(other, self)
+    }
+
+    /// This version for `interpreter`, so `3.11` becomes `pypy3.11` for PyPy. Errors if the
+    /// version is another interpreter's.
+    fn with_interpreter(self, interpreter: Interpreter) -> Result<Version, Error> {
+        Ok(Version {
+            interpreter: interpreter.select(self.interpreter, &self.to_string())?,
+            ..self
+        })
     }
 }
 
 impl std::fmt::Display for Version {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let prefix = match self.interpreter {
-            Interpreter::CPython => "",
-            Interpreter::PyPy => "pypy",
-        };
+        let prefix = self.interpreter.prefix();
         let prerelease = match self.prerelease {
             PreRelease::None => "".to_string(),
             PreRelease::Alpha(n) => format!("a{n}"),
             PreRelease::Beta(n) => format!("b{n}"),
             PreRelease::RC(n) => format!("rc{n}"),
         };
-        let debug = match self.debug {
-            false => "",
-            true => "-debug",
+        let debug = match (self.debug, self.full) {
+            (true, _) => "-debug",
+            (false, true) => "-full",
+            (false, false) => "",
         };
         let freethreaded = match self.freethreaded {
             false => "",
@@ -90,6 +148,309 @@ impl std::str::FromStr for Version {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Operator {
+    Compatible,
+    Equal,
+    NotEqual,
+    LessEqual,
+    GreaterEqual,
+    Less,
+    Greater,
+}
+
+/// A single PEP 440 version clause, like `>=3.10` or `==3.11.*`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Clause {
+    pub operator: Operator,
+    pub release: Vec<u16>,
+    pub wildcard: bool,
+}
+
+impl Clause {
+    fn matches(&self, release: [u16; 3]) -> bool {
+        let prefix = |length: usize| release[..length] == self.release[..length];
+        let mut padded = [0; 3];
+        padded[..self.release.len()].copy_from_slice(&self.release);
+        match self.operator {
+            Operator::Equal if self.wildcard => prefix(self.release.len()),
+            Operator::NotEqual if self.wildcard => !prefix(self.release.len()),
+            Operator::Equal => release == padded,
+            Operator::NotEqual => release != padded,
+            Operator::LessEqual => release <= padded,
+            Operator::GreaterEqual => release >= padded,
+            Operator::Less => release < padded,
+            Operator::Greater => release > padded,
+            Operator::Compatible => release >= padded && prefix(self.release.len() - 1),
+        }
+    }
+}
+
+/// A comma separated list of PEP 440 version clauses, like `>=3.10,<3.12`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VersionSpecifier {
+    pub clauses: Vec<Clause>,
+    specifier: String,
+}
+
+impl VersionSpecifier {
+    /// Whether a CPython release satisfies every clause. Pre-releases, debug, freethreaded and
+    /// full builds never match.
+    pub fn matches(&self, version: &Version) -> bool {
+        let release = [version.major, version.minor, version.bugfix.unwrap_or(0)];
+        version.interpreter == Interpreter::CPython
+            && version.prerelease == PreRelease::None
+            && !version.debug
+            && !version.freethreaded
+            && !version.full
+            && self.clauses.iter().all(|clause| clause.matches(release))
+    }
+}
+
+impl std::fmt::Display for VersionSpecifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.specifier)
+    }
+}
+
+impl std::str::FromStr for VersionSpecifier {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let specifier: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+        match parse_specifier(&specifier) {
+            Ok(("", clauses)) => Ok(VersionSpecifier {
+                clauses,
+                specifier: s.to_string(),
+            }),
+            _ => Err(Error::InvalidVersion(s.into())),
+        }
+    }
+}
+
+/// Either an exact Python version, a bare major version like `3` or `pypy3` meaning its newest
+/// release, a PEP 440 specifier to resolve against the releases, or `latest` or `latest-pypy`
+/// meaning an interpreter's newest stable release.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum VersionRequest {
+    Version(Version),
+    Major(Interpreter, u16),
+    Specifier(VersionSpecifier),
+    Latest(Interpreter),
+    /// A version from one release, like `3.11.8@20240224`, for when a version was rebuilt.
+    Release(Version, String),
+}
+
+impl std::fmt::Display for VersionRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VersionRequest::Version(version) => write!(f, "{version}"),
+            VersionRequest::Major(interpreter, major) => {
+                write!(f, "{}{major}", interpreter.prefix())
+            }
+            VersionRequest::Specifier(specifier) => write!(f, "{specifier}"),
+            VersionRequest::Latest(Interpreter::CPython) => write!(f, "latest"),
+            VersionRequest::Latest(interpreter) => write!(f, "latest-{}", interpreter.prefix()),
+            VersionRequest::Release(version, release_tag) => write!(f, "{version}@{release_tag}"),
+        }
+    }
+}
+
+impl VersionRequest {
+    /// This request for `interpreter`, as for [`Version::with_interpreter`]. Specifiers only
+    /// resolve to CPython releases, so conflict with any other interpreter.
+    fn with_interpreter(self, interpreter: Interpreter) -> Result<VersionRequest, Error> {
+        let request = self.to_string();
+        Ok(match self {
+            VersionRequest::Version(version) => {
+                VersionRequest::Version(version.with_interpreter(interpreter)?)
+            }
+            VersionRequest::Major(parsed, major) => {
+                VersionRequest::Major(interpreter.select(parsed, &request)?, major)
+            }
+            VersionRequest::Specifier(specifier) => match interpreter {
+                Interpreter::CPython => VersionRequest::Specifier(specifier),
+                _ => Err(Error::InterpreterConflict {
+                    interpreter: format!("{interpreter:?}").to_lowercase(),
+                    version: request,
+                })?,
+            },
+            VersionRequest::Latest(parsed) => {
+                VersionRequest::Latest(interpreter.select(parsed, &request)?)
+            }
+            VersionRequest::Release(version, release_tag) => {
+                VersionRequest::Release(version.with_interpreter(interpreter)?, release_tag)
+            }
+        })
+    }
+}
+
+/// A version as given on the command line, remembering the interpreter prefix it was given with,
+/// so `--interpreter` can tell `3.12`, which it may change, from an explicit `cpython3.12`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Prefixed<T> {
+    pub value: T,
+    prefix: Option<Interpreter>,
+    given: String,
+}
+
+impl<T> Prefixed<T> {
+    /// Fail if the version was given with a prefix naming another interpreter than `interpreter`.
+    fn check_prefix(&self, interpreter: Interpreter) -> Result<(), Error> {
+        match self.prefix {
+            Some(prefix) if prefix != interpreter => Err(Error::InterpreterConflict {
+                interpreter: format!("{interpreter:?}").to_lowercase(),
+                version: self.given.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Prefixed<Version> {
+    /// The version for the interpreter chosen with `--interpreter`, if any, so `3.11` becomes
+    /// `pypy3.11` for PyPy. Errors if the version's prefix names a different interpreter.
+    pub fn for_interpreter(self, interpreter: Option<Interpreter>) -> Result<Version, Error> {
+        let Some(interpreter) = interpreter else {
+            return Ok(self.value);
+        };
+        self.check_prefix(interpreter)?;
+        self.value.with_interpreter(interpreter)
+    }
+}
+
+impl Prefixed<VersionRequest> {
+    /// The request for the interpreter chosen with `--interpreter`, as for
+    /// [`Prefixed::<Version>::for_interpreter`].
+    pub fn for_interpreter(
+        self,
+        interpreter: Option<Interpreter>,
+    ) -> Result<VersionRequest, Error> {
+        let Some(interpreter) = interpreter else {
+            return Ok(self.value);
+        };
+        self.check_prefix(interpreter)?;
+        self.value.with_interpreter(interpreter)
+    }
+}
+
+impl<T: std::str::FromStr<Err = Error>> std::str::FromStr for Prefixed<T> {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = s.parse()?;
+        let prefix = match parse_prefix(strip_latest(s).unwrap_or(s)) {
+            Ok((_, prefix)) => prefix,
+            Err(_) => None,
+        };
+        Ok(Prefixed {
+            value,
+            prefix,
+            given: s.to_string(),
+        })
+    }
+}
+
+impl std::str::FromStr for VersionRequest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with(['<', '>', '=', '!', '~']) {
+            return Ok(VersionRequest::Specifier(s.parse()?));
+        }
+        if s.eq_ignore_ascii_case("latest") {
+            return Ok(VersionRequest::Latest(Interpreter::CPython));
+        }
+        if let Some(prefix) = strip_latest(s) {
+            return [Interpreter::PyPy, Interpreter::GraalPy]
+                .into_iter()
+                .find(|interpreter| interpreter.prefix().eq_ignore_ascii_case(prefix))
+                .map(VersionRequest::Latest)
+                .ok_or_else(|| Error::InvalidVersion(s.into()));
+        }
+        if let Some((version, release_tag)) = s.split_once('@') {
+            if release_tag.is_empty() {
+                return Err(Error::InvalidVersion(s.into()));
+            }
+            return Ok(VersionRequest::Release(
+                version.parse()?,
+                release_tag.into(),
+            ));
+        }
+        match parse_major(s) {
+            Ok(("", (interpreter, major))) => Ok(VersionRequest::Major(interpreter, major)),
+            _ => Ok(VersionRequest::Version(s.parse()?)),
+        }
+    }
+}
+
+/// The interpreter after a `latest-` prefix in any case, like `pypy` in `Latest-pypy`.
+fn strip_latest(s: &str) -> Option<&str> {
+    let prefix = "latest-";
+    s.get(..prefix.len())
+        .filter(|start| start.eq_ignore_ascii_case(prefix))
+        .map(|_| &s[prefix.len()..])
+}
+
+/// The interpreter prefix of a version, in any case, like `pypy` or `PyPy`, if it has one.
+/// `cpython` may be given explicitly.
+fn parse_prefix(input: &str) -> nom::IResult<&str, Option<Interpreter>> {
+    use nom::branch::alt;
+    use nom::bytes::complete::tag_no_case;
+    use nom::combinator::{opt, value};
+    opt(alt((
+        value(Interpreter::PyPy, tag_no_case("pypy")),
+        value(Interpreter::GraalPy, tag_no_case("graalpy")),
+        value(Interpreter::CPython, tag_no_case("cpython")),
+    )))(input)
+}
+
+/// The interpreter of a version, which is CPython without a prefix.
+fn parse_interpreter(input: &str) -> nom::IResult<&str, Interpreter> {
+    let (rest, interpreter) = parse_prefix(input)?;
+    Ok((rest, interpreter.unwrap_or(Interpreter::CPython)))
+}
+
+fn parse_major(input: &str) -> nom::IResult<&str, (Interpreter, u16)> {
+    nom::sequence::pair(parse_interpreter, nom::character::complete::u16)(input)
+}
+
+fn parse_clause(input: &str) -> nom::IResult<&str, Clause> {
+    use nom::branch::alt;
+    use nom::bytes::complete::tag;
+    use nom::character::complete::u16;
+    use nom::combinator::{opt, value, verify};
+    use nom::multi::separated_list1;
+    let (rest, operator) = alt((
+        value(Operator::Compatible, tag("~=")),
+        value(Operator::Equal, tag("==")),
+        value(Operator::NotEqual, tag("!=")),
+        value(Operator::LessEqual, tag("<=")),
+        value(Operator::GreaterEqual, tag(">=")),
+        value(Operator::Less, tag("<")),
+        value(Operator::Greater, tag(">")),
+    ))(input)?;
+    let (rest, release) = verify(separated_list1(tag("."), u16), |release: &Vec<u16>| {
+        release.len() <= 3 && (operator != Operator::Compatible || release.len() >= 2)
+    })(rest)?;
+    let (rest, wildcard) = match operator {
+        Operator::Equal | Operator::NotEqual => opt(tag(".*"))(rest)?,
+        _ => (rest, None),
+    };
+    Ok((
+        rest,
+        Clause {
+            operator,
+            release,
+            wildcard: wildcard.is_some(),
+        },
+    ))
+}
+
+fn parse_specifier(input: &str) -> nom::IResult<&str, Vec<Clause>> {
+    nom::multi::separated_list1(nom::bytes::complete::tag(","), parse_clause)(input)
+}
+
 fn parse_prerelease(input: &str) -> nom::IResult<&str, PreRelease> {
     use nom::branch::alt;
     use nom::bytes::complete::tag;
@@ -111,18 +472,18 @@ fn parse_prerelease(input: &str) -> nom::IResult<&str, PreRelease> {
 
 fn parse_version(version: &str) -> nom::IResult<&str, Version> {
     use nom::bytes::complete::tag;
-    use nom::character::complete::u8;
+    use nom::character::complete::u16;
     use nom::sequence::separated_pair;
-    let (rest, interpreter) = nom::combinator::opt(tag("pypy"))(version)?;
-    let (rest, (major, minor)) = separated_pair(u8, tag("."), u8)(rest)?;
-    let (rest, bugfix) = nom::combinator::opt(nom::sequence::preceded(tag("."), u8))(rest)?;
-    let (rest, prerelease) = parse_prerelease(rest)?;
-    let (rest, freethreaded) = nom::combinator::opt(tag("t"))(rest)?;
-    let (rest, debug) = nom::combinator::opt(tag("-debug"))(rest)?;
-    let interpreter = match interpreter {
-        Some(_) => Interpreter::PyPy,
-        None => Interpreter::CPython,
+    let (rest, interpreter) = parse_interpreter(version)?;
+    let (rest, (major, minor)) = separated_pair(u16, tag("."), u16)(rest)?;
+    let (rest, bugfix) = nom::combinator::opt(nom::sequence::preceded(tag("."), u16))(rest)?;
+    let (rest, prerelease) = match bugfix {
+        Some(_) => parse_prerelease(rest)?,
+        None => (rest, PreRelease::None),
     };
+    let (rest, freethreaded) = nom::combinator::opt(tag("t"))(rest)?;
+    let (rest, build) =
+        nom::combinator::opt(nom::branch::alt((tag("-debug"), tag("-full"))))(rest)?;
     Ok((
         rest,
         Version {
@@ -130,8 +491,9 @@ fn parse_version(version: &str) -> nom::IResult<&str, Version> {
             major,
             minor,
             bugfix,
-            debug: debug.is_some(),
+            debug: build == Some("-debug"),
             freethreaded: freethreaded.is_some(),
+            full: build == Some("-full"),
             prerelease,
         },
     ))
@@ -152,6 +514,10 @@ fn _parse_cpython_filename(filename: &str) -> nom::IResult<&str, (String, Versio
     if debug.is_some() {
         version.debug = true;
     }
+    // Debug builds only come as full distributions, so they're already told apart by `debug`.
+    if !version.debug && filename.ends_with("-full.tar.zst") {
+        version.full = true;
+    }
     Ok((input, (release_tag.to_string(), version)))
 }
 
@@ -162,12 +528,37 @@ pub fn parse_cpython_filename(filename: &str) -> Result<(String, Version), Error
     }
 }
 
-fn _parse_pypy_url(url: &str) -> nom::IResult<&str, (String, String, Version)> {
+/// Parse a PyPy release tag like `v7.3.17`.
+fn parse_pypy_release(release_tag: &str) -> nom::IResult<&str, (u16, u16, u16)> {
+    use nom::bytes::complete::tag;
+    use nom::character::complete::u16;
+    use nom::sequence::{preceded, tuple};
+    preceded(
+        tag("v"),
+        tuple((u16, preceded(tag("."), u16), preceded(tag("."), u16))),
+    )(release_tag)
+}
+
+/// The PyPy release of a release tag like `v7.3.17`, as `(7, 3, 17)`.
+pub fn pypy_release(release_tag: &str) -> Option<(u16, u16, u16)> {
+    match parse_pypy_release(release_tag) {
+        Ok(("", release)) => Some(release),
+        _ => None,
+    }
+}
+
+fn _parse_pypy_url<'a>(
+    url: &'a str,
+    base: &str,
+) -> nom::IResult<&'a str, (String, String, Version)> {
     use nom::bytes::complete::{tag, take_until};
-    let (filename, _) = tag(PYPY_DOWNLOAD_URL)(url)?;
-    let (rest, version) = parse_version(filename)?;
+    let (filename, _) = tag(base)(url)?;
+    let (rest, mut version) = parse_version(filename)?;
     let (rest, _) = tag("-")(rest)?;
     let (rest, release_tag) = take_until("-")(rest)?;
+    if let Ok((_, (_, _, patch))) = parse_pypy_release(release_tag) {
+        version.bugfix = Some(patch);
+    }
 
     Ok((
         rest,
@@ -175,13 +566,47 @@ fn _parse_pypy_url(url: &str) -> nom::IResult<&str, (String, String, Version)> {
     ))
 }
 
-pub fn parse_pypy_url(url: &str) -> Result<(String, String, Version), Error> {
-    match _parse_pypy_url(url) {
+/// Parse a PyPy download url under `base`, like `PYPY_DOWNLOAD_URL` or a mirror of it.
+pub fn parse_pypy_url(url: &str, base: &str) -> Result<(String, String, Version), Error> {
+    match _parse_pypy_url(url, base) {
         Ok((_, (filename, release_tag, version))) => Ok((filename, release_tag, version)),
         Err(_) => Err(Error::ParseAsset(url.to_string())),
     }
 }
 
+/// Parse a GraalPy asset name like `graalpy-community-24.1.1-linux-amd64.tar.gz`. GraalPy
+/// versions are GraalPy's own release numbers rather than the Python version they implement.
+fn _parse_graalpy_filename(filename: &str) -> nom::IResult<&str, Version> {
+    use nom::bytes::complete::tag;
+    use nom::character::complete::u16;
+    use nom::sequence::{preceded, tuple};
+    let (rest, (major, minor, bugfix)) = preceded(
+        tag("graalpy-community-"),
+        tuple((u16, preceded(tag("."), u16), preceded(tag("."), u16))),
+    )(filename)?;
+    let (rest, _) = tag("-")(rest)?;
+    Ok((
+        rest,
+        Version {
+            interpreter: Interpreter::GraalPy,
+            major,
+            minor,
+            bugfix: Some(bugfix),
+            debug: false,
+            freethreaded: false,
+            full: false,
+            prerelease: PreRelease::None,
+        },
+    ))
+}
+
+pub fn parse_graalpy_filename(filename: &str) -> Result<Version, Error> {
+    match _parse_graalpy_filename(filename) {
+        Ok((_, version)) => Ok(version),
+        Err(_) => Err(Error::ParseAsset(filename.to_string())),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -197,6 +622,7 @@ mod tests {
                 bugfix: None,
                 debug: false,
                 freethreaded: false,
+                full: false,
                 prerelease: PreRelease::None,
             }
         );
@@ -210,6 +636,7 @@ mod tests {
                 bugfix: Some(1),
                 debug: false,
                 freethreaded: false,
+                full: false,
                 prerelease: PreRelease::None,
             }
         );
@@ -223,6 +650,7 @@ mod tests {
                 bugfix: None,
                 debug: false,
                 freethreaded: false,
+                full: false,
                 prerelease: PreRelease::None,
             }
         );
@@ -236,6 +664,7 @@ mod tests {
                 bugfix: Some(4),
                 debug: false,
                 freethreaded: false,
+                full: false,
                 prerelease: PreRelease::None,
             }
         );
@@ -249,6 +678,7 @@ mod tests {
                 bugfix: None,
                 debug: true,
                 freethreaded: false,
+                full: false,
                 prerelease: PreRelease::None,
             }
         );
@@ -262,6 +692,7 @@ mod tests {
                 bugfix: Some(1),
                 debug: true,
                 freethreaded: false,
+                full: false,
                 prerelease: PreRelease::None,
             }
         );
@@ -275,6 +706,7 @@ mod tests {
                 bugfix: None,
                 debug: true,
                 freethreaded: false,
+                full: false,
                 prerelease: PreRelease::None,
             }
         );
@@ -288,9 +720,105 @@ mod tests {
                 bugfix: Some(4),
                 debug: true,
                 freethreaded: false,
+                full: false,
+                prerelease: PreRelease::None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_version_full() {
+        let version = "3.12-full".parse::<Version>().unwrap();
+        assert!(version.full);
+        assert!(!version.debug);
+        assert_eq!(version.to_string(), "3.12-full");
+
+        let (_, full) = parse_cpython_filename(
+            "cpython-3.12.1+20240107-x86_64-unknown-linux-gnu-pgo+lto-full.tar.zst",
+        )
+        .unwrap();
+        assert!(full.full);
+        assert_eq!(full.to_string(), "3.12.1-full");
+        assert!(full.compatible(&version));
+        assert!(!full.compatible(&"3.12".parse::<Version>().unwrap()));
+
+        let (_, debug) = parse_cpython_filename(
+            "cpython-3.12.1+20240107-x86_64-unknown-linux-gnu-debug-full.tar.zst",
+        )
+        .unwrap();
+        assert!(!debug.full);
+        assert!(!">=3.12".parse::<VersionSpecifier>().unwrap().matches(&full));
+    }
+
+    #[test]
+    fn test_version_freethreaded() {
+        let version = "3.13t".parse::<Version>().unwrap();
+        assert_eq!(
+            version,
+            Version {
+                interpreter: Interpreter::CPython,
+                major: 3,
+                minor: 13,
+                bugfix: None,
+                debug: false,
+                freethreaded: true,
+                full: false,
                 prerelease: PreRelease::None,
             }
         );
+        assert_eq!(version.to_string(), "3.13t");
+        assert_eq!(
+            "3.13.1t-debug".parse::<Version>().unwrap().to_string(),
+            "3.13.1t-debug"
+        );
+
+        let release = "3.13.1t".parse::<Version>().unwrap();
+        assert!(release.compatible(&version));
+        assert!(!release.compatible(&"3.13".parse::<Version>().unwrap()));
+        let release = "3.13.1".parse::<Version>().unwrap();
+        assert!(!release.compatible(&version));
+    }
+
+    #[test]
+    fn test_version_prerelease() {
+        for prerelease in ["3.13.0a1", "3.13.0b2", "3.13.0rc1", "3.13.0rc1t-debug"] {
+            assert_eq!(
+                prerelease.parse::<Version>().unwrap().to_string(),
+                prerelease
+            );
+        }
+        let rc = "3.13.0rc1".parse::<Version>().unwrap();
+        assert_eq!(rc.prerelease, PreRelease::RC(1));
+        assert!(!rc.compatible(&"3.13".parse::<Version>().unwrap()));
+        assert!(rc.compatible(&rc));
+
+        let mut versions: Vec<Version> = ["3.13.0", "3.13.0rc1", "3.13.0a2", "3.12.7", "3.13.0b1"]
+            .iter()
+            .map(|version| version.parse().unwrap())
+            .collect();
+        versions.sort();
+        let versions: Vec<String> = versions.iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            versions,
+            ["3.12.7", "3.13.0a2", "3.13.0b1", "3.13.0rc1", "3.13.0"]
+        );
+        assert!("3.13rc1".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn test_version_large_components() {
+        let version = "3.10.256".parse::<Version>().unwrap();
+        assert_eq!(version.bugfix, Some(256));
+        assert_eq!(version.to_string(), "3.10.256");
+        assert_eq!("pypy3.300".parse::<Version>().unwrap().minor, 300);
+        assert!(">=3.10.255"
+            .parse::<VersionSpecifier>()
+            .unwrap()
+            .matches(&version));
+        assert!(matches!(
+            "3.10.65536".parse::<Version>(),
+            Err(Error::InvalidVersion(_))
+        ));
     }
 
     #[test]
@@ -345,6 +873,7 @@ mod tests {
                 bugfix: Some(13),
                 debug: false,
                 freethreaded: false,
+                full: false,
                 prerelease: PreRelease::None,
             }
         );
@@ -364,6 +893,7 @@ mod tests {
                 bugfix: Some(9),
                 debug: true,
                 freethreaded: false,
+                full: false,
                 prerelease: PreRelease::None,
             }
         );
@@ -383,6 +913,7 @@ mod tests {
                 bugfix: Some(0),
                 debug: true,
                 freethreaded: false,
+                full: false,
                 prerelease: PreRelease::RC(2),
             }
         );
@@ -403,6 +934,7 @@ mod tests {
                 bugfix: Some(1),
                 debug: true,
                 freethreaded: true,
+                full: false,
                 prerelease: PreRelease::None,
             }
         );
@@ -411,7 +943,7 @@ mod tests {
     #[test]
     fn test_parse_pypy_url() {
         let url = "https://downloads.python.org/pypy/pypy3.10-v7.3.15-linux64.tar.bz2";
-        let (filename, release_tag, version) = parse_pypy_url(url).unwrap();
+        let (filename, release_tag, version) = parse_pypy_url(url, PYPY_DOWNLOAD_URL).unwrap();
         assert_eq!(filename, "pypy3.10-v7.3.15-linux64.tar.bz2");
         assert_eq!(release_tag, "v7.3.15");
         assert_eq!(
@@ -420,11 +952,311 @@ mod tests {
                 interpreter: Interpreter::PyPy,
                 major: 3,
                 minor: 10,
-                bugfix: None,
+                bugfix: Some(15),
                 debug: false,
                 freethreaded: false,
+                full: false,
                 prerelease: PreRelease::None,
             }
         );
     }
+
+    #[test]
+    fn test_parse_pypy_url_exact_release() {
+        let url = "https://downloads.python.org/pypy/pypy3.10-v7.3.17-aarch64.tar.bz2";
+        let (_, _, version) = parse_pypy_url(url, PYPY_DOWNLOAD_URL).unwrap();
+        assert_eq!(version.to_string(), "pypy3.10.17");
+        assert!(version.compatible(&"pypy3.10.17".parse().unwrap()));
+        assert!(version.compatible(&"pypy3.10".parse().unwrap()));
+        assert!(!version.compatible(&"pypy3.10.16".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_version_interpreter_prefix_case() {
+        for (input, interpreter, canonical) in [
+            ("pypy3.10", Interpreter::PyPy, "pypy3.10"),
+            ("PyPy3.10", Interpreter::PyPy, "pypy3.10"),
+            ("Pypy3.10.17", Interpreter::PyPy, "pypy3.10.17"),
+            ("GraalPy24.1", Interpreter::GraalPy, "graalpy24.1"),
+            ("cpython3.12", Interpreter::CPython, "3.12"),
+            ("CPython3.12.4", Interpreter::CPython, "3.12.4"),
+            ("3.12", Interpreter::CPython, "3.12"),
+            ("3.12.4", Interpreter::CPython, "3.12.4"),
+        ] {
+            let version = input.parse::<Version>().unwrap();
+            assert_eq!(version.interpreter, interpreter, "{input}");
+            assert_eq!(version.to_string(), canonical);
+        }
+        assert!(matches!(
+            "PyPy3".parse::<VersionRequest>(),
+            Ok(VersionRequest::Major(Interpreter::PyPy, 3))
+        ));
+        assert!(matches!(
+            "cpython3".parse::<VersionRequest>(),
+            Ok(VersionRequest::Major(Interpreter::CPython, 3))
+        ));
+        assert_eq!(
+            "latest-PyPy".parse::<VersionRequest>().unwrap(),
+            VersionRequest::Latest(Interpreter::PyPy)
+        );
+        assert!(matches!(
+            "py3.10".parse::<Version>(),
+            Err(Error::InvalidVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_graalpy_version_from_str() {
+        let version = "graalpy24.1".parse::<Version>().unwrap();
+        assert_eq!(version.interpreter, Interpreter::GraalPy);
+        assert_eq!(
+            (version.major, version.minor, version.bugfix),
+            (24, 1, None)
+        );
+        assert_eq!(version.to_string(), "graalpy24.1");
+        assert_eq!(
+            "graalpy24.1.1".parse::<Version>().unwrap().to_string(),
+            "graalpy24.1.1"
+        );
+    }
+
+    #[test]
+    fn test_parse_graalpy_filename() {
+        let version =
+            parse_graalpy_filename("graalpy-community-24.1.1-linux-amd64.tar.gz").unwrap();
+        assert_eq!(
+            version,
+            Version {
+                interpreter: Interpreter::GraalPy,
+                major: 24,
+                minor: 1,
+                bugfix: Some(1),
+                debug: false,
+                freethreaded: false,
+                full: false,
+                prerelease: PreRelease::None,
+            }
+        );
+        assert!(version.compatible(&"graalpy24.1".parse().unwrap()));
+        assert!(matches!(
+            parse_graalpy_filename("graalpy-24.1.1-linux-amd64.tar.gz"),
+            Err(Error::ParseAsset(_))
+        ));
+    }
+
+    #[test]
+    fn test_version_specifier() {
+        let release = |version: &str| version.parse::<Version>().unwrap();
+        let specifier = ">=3.10, <3.12".parse::<VersionSpecifier>().unwrap();
+        assert_eq!(specifier.to_string(), ">=3.10, <3.12");
+        assert!(specifier.matches(&release("3.10.0")));
+        assert!(specifier.matches(&release("3.11.9")));
+        assert!(!specifier.matches(&release("3.9.18")));
+        assert!(!specifier.matches(&release("3.12.0")));
+        assert!(!specifier.matches(&release("3.11.9-debug")));
+        assert!(!specifier.matches(&release("3.11.9t")));
+        assert!(!specifier.matches(&release("pypy3.10.17")));
+
+        let specifier = "~=3.11".parse::<VersionSpecifier>().unwrap();
+        assert!(specifier.matches(&release("3.11.0")));
+        assert!(specifier.matches(&release("3.13.1")));
+        assert!(!specifier.matches(&release("4.0.0")));
+        assert!(!specifier.matches(&release("3.10.13")));
+
+        let specifier = "~=3.11.2".parse::<VersionSpecifier>().unwrap();
+        assert!(specifier.matches(&release("3.11.9")));
+        assert!(!specifier.matches(&release("3.11.1")));
+        assert!(!specifier.matches(&release("3.12.0")));
+
+        let specifier = "==3.12.*,!=3.12.1".parse::<VersionSpecifier>().unwrap();
+        assert!(specifier.matches(&release("3.12.0")));
+        assert!(!specifier.matches(&release("3.12.1")));
+        assert!(specifier.matches(&release("3.12.8")));
+        assert!(!specifier.matches(&release("3.13.0")));
+
+        let specifier = "==3.12".parse::<VersionSpecifier>().unwrap();
+        assert!(specifier.matches(&release("3.12.0")));
+        assert!(!specifier.matches(&release("3.12.1")));
+
+        let prerelease = "3.13.0rc2".parse::<Version>().unwrap();
+        assert!(!">=3.12"
+            .parse::<VersionSpecifier>()
+            .unwrap()
+            .matches(&prerelease));
+    }
+
+    #[test]
+    fn test_invalid_version_specifier() {
+        for specifier in [
+            "~=3",
+            ">=",
+            ">=3.10,",
+            "=>3.10",
+            ">=3.10.*",
+            "3.10",
+            ">=3.1.2.3",
+        ] {
+            let err = specifier.parse::<VersionSpecifier>();
+            assert!(matches!(err, Err(Error::InvalidVersion(_))), "{specifier}");
+        }
+    }
+
+    #[test]
+    fn test_version_request_from_str() {
+        assert!(matches!(
+            "3.12".parse::<VersionRequest>(),
+            Ok(VersionRequest::Version(_))
+        ));
+        assert!(matches!(
+            ">=3.12".parse::<VersionRequest>(),
+            Ok(VersionRequest::Specifier(_))
+        ));
+        assert!(matches!(
+            "~=3".parse::<VersionRequest>(),
+            Err(Error::InvalidVersion(_))
+        ));
+        assert!(matches!(
+            "3".parse::<VersionRequest>(),
+            Ok(VersionRequest::Major(Interpreter::CPython, 3))
+        ));
+        assert!(matches!(
+            "pypy3".parse::<VersionRequest>(),
+            Ok(VersionRequest::Major(Interpreter::PyPy, 3))
+        ));
+        assert!(matches!(
+            "3t".parse::<VersionRequest>(),
+            Err(Error::InvalidVersion(_))
+        ));
+        for (request, interpreter) in [
+            ("latest", Interpreter::CPython),
+            ("latest-pypy", Interpreter::PyPy),
+            ("latest-graalpy", Interpreter::GraalPy),
+        ] {
+            let parsed = request.parse::<VersionRequest>().unwrap();
+            assert_eq!(parsed, VersionRequest::Latest(interpreter));
+            assert_eq!(parsed.to_string(), request);
+        }
+        for (request, interpreter) in [
+            ("LATEST", Interpreter::CPython),
+            ("Latest-pypy", Interpreter::PyPy),
+            ("LATEST-GRAALPY", Interpreter::GraalPy),
+        ] {
+            assert_eq!(
+                request.parse::<VersionRequest>().unwrap(),
+                VersionRequest::Latest(interpreter)
+            );
+        }
+        assert!(matches!(
+            "latest-cpython".parse::<VersionRequest>(),
+            Err(Error::InvalidVersion(_))
+        ));
+        let parsed = "3.11.8@20240224".parse::<VersionRequest>().unwrap();
+        assert_eq!(
+            parsed,
+            VersionRequest::Release("3.11.8".parse().unwrap(), "20240224".into())
+        );
+        assert_eq!(parsed.to_string(), "3.11.8@20240224");
+        for request in ["3.11.8@", "3@20240224", "@20240224"] {
+            assert!(matches!(
+                request.parse::<VersionRequest>(),
+                Err(Error::InvalidVersion(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        for (requested, candidate, expected) in [
+            ("3.11.5", "3.11.5", true),
+            ("3.11", "3.11.5", true),
+            ("3.11", "3.11.0", true),
+            ("3.11.5", "3.11.4", false),
+            ("3.11.5", "3.11.6", false),
+            ("3.11", "3.12.1", false),
+            ("3.11", "pypy3.11.5", false),
+            ("pypy3.10", "3.10.5", false),
+            ("pypy3.10", "pypy3.10.17", true),
+            ("3.11", "3.11.5-debug", false),
+            ("3.13", "3.13.0rc1", false),
+            ("3.13.0rc1", "3.13.0rc1", true),
+        ] {
+            let requested = requested.parse::<Version>().unwrap();
+            let candidate = candidate.parse::<Version>().unwrap();
+            assert_eq!(
+                matches(&requested, &candidate),
+                expected,
+                "{requested} requested, {candidate} found"
+            );
+        }
+    }
+
+    #[test]
+    fn test_with_interpreter() {
+        let version = |s: &str| s.parse::<Prefixed<Version>>().unwrap();
+        let request = |s: &str| s.parse::<Prefixed<VersionRequest>>().unwrap();
+        fn conflict<T>(result: Result<T, Error>) -> bool {
+            matches!(result, Err(Error::InterpreterConflict { .. }))
+        }
+
+        assert_eq!(
+            version("3.11")
+                .for_interpreter(Some(Interpreter::PyPy))
+                .unwrap(),
+            "pypy3.11".parse().unwrap()
+        );
+        assert_eq!(
+            version("3.11").for_interpreter(None).unwrap(),
+            "3.11".parse().unwrap()
+        );
+        assert_eq!(
+            version("pypy3.11")
+                .for_interpreter(Some(Interpreter::PyPy))
+                .unwrap(),
+            "pypy3.11".parse().unwrap()
+        );
+        assert!(conflict(
+            version("pypy3.11").for_interpreter(Some(Interpreter::CPython))
+        ));
+        assert!(conflict(
+            version("pypy3.11").for_interpreter(Some(Interpreter::GraalPy))
+        ));
+        // Only a missing prefix can be overridden, not an explicit cpython one.
+        assert!(conflict(
+            version("cpython3.12").for_interpreter(Some(Interpreter::PyPy))
+        ));
+        assert_eq!(
+            version("cpython3.12")
+                .for_interpreter(Some(Interpreter::CPython))
+                .unwrap(),
+            "3.12".parse().unwrap()
+        );
+
+        assert_eq!(
+            request("latest")
+                .for_interpreter(Some(Interpreter::PyPy))
+                .unwrap(),
+            VersionRequest::Latest(Interpreter::PyPy)
+        );
+        assert!(conflict(
+            request("latest-pypy").for_interpreter(Some(Interpreter::CPython))
+        ));
+        assert_eq!(
+            request("3")
+                .for_interpreter(Some(Interpreter::GraalPy))
+                .unwrap(),
+            VersionRequest::Major(Interpreter::GraalPy, 3)
+        );
+        assert!(conflict(
+            request("cpython3").for_interpreter(Some(Interpreter::GraalPy))
+        ));
+        assert!(conflict(
+            request("cpython3.12@20240713").for_interpreter(Some(Interpreter::PyPy))
+        ));
+        assert!(request(">=3.10")
+            .for_interpreter(Some(Interpreter::CPython))
+            .is_ok());
+        assert!(conflict(
+            request(">=3.10").for_interpreter(Some(Interpreter::PyPy))
+        ));
+    }
 }
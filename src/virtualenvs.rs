@@ -1,51 +1,782 @@
+use crate::config::config;
 use crate::directories::{
-    is_downloaded, project_dir, project_file, python_dir, virtualenv_dir, virtualenvs_dir,
+    default_version_file, find_interpreter_root, is_downloaded, last_version_file,
+    named_virtualenv_dir, project_dir, project_file, python_dir, python_executable, pyvenv_cfg,
+    venv_bin_dir, venv_python, venv_site_packages, virtualenv_dir, virtualenv_version_file,
+    virtualenvs_dir,
+};
+use crate::disk::WithPath;
+use crate::download::{
+    download_python, installed_pythons, installed_release, reported_version, resolve_request,
+    sync_release,
 };
-use crate::download::download_python;
 use crate::error::Error;
-use crate::shell::get_shell;
-use crate::version::Version;
+use crate::interrupt::IgnoreInterrupts;
+use crate::output::{PROJECT, VERSION};
+use crate::project::validate_project_name;
+use crate::releases::foreign_platform;
+use crate::shell::{get_shell, subshell_command};
+use crate::version::{Interpreter, PreRelease, Version, VersionRequest, VersionSpecifier};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+static PROMPT: OnceLock<String> = OnceLock::new();
+
+const DEFAULT_PROMPT: &str = "{project} ({version}) ";
+
+/// What creates virtualenvs and installs packages into them.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Python's venv module and pip
+    #[default]
+    Venv,
+    /// uv, which is much faster
+    Uv,
+    /// uv if it's on PATH, or else venv
+    Auto,
+}
+
+static BACKEND: OnceLock<Backend> = OnceLock::new();
+
+/// Override the configured backend for creating virtualenvs and installing packages.
+pub fn set_backend(backend: Backend) {
+    let _ = BACKEND.set(backend);
+}
+
+/// The `uv` executable to use instead of venv and pip, if the backend is uv.
+fn uv() -> Result<Option<&'static std::path::Path>, Error> {
+    static UV: OnceLock<Option<std::path::PathBuf>> = OnceLock::new();
+    let backend = BACKEND
+        .get()
+        .copied()
+        .or(config().backend)
+        .unwrap_or_default();
+    if backend == Backend::Venv {
+        return Ok(None);
+    }
+    let uv = UV
+        .get_or_init(|| {
+            let name = if cfg!(windows) { "uv.exe" } else { "uv" };
+            std::env::split_paths(&std::env::var_os("PATH")?)
+                .map(|dir| dir.join(name))
+                .find(|path| path.is_file())
+        })
+        .as_deref();
+    match (backend, uv) {
+        (Backend::Uv, None) => Err(Error::UvNotFound),
+        (_, uv) => Ok(uv),
+    }
+}
+
+/// Override the configured prompt template for activated virtualenvs.
+pub fn set_prompt(prompt: String) {
+    let _ = PROMPT.set(prompt);
+}
 
+/// The `VIRTUAL_ENV_PROMPT` for a virtualenv, filling in the prompt template's `{project}`,
+/// `{version}` and `{interpreter}` placeholders.
+fn prompt(version: &Version, project: &str) -> String {
+    let template = PROMPT
+        .get()
+        .or(config().prompt.as_ref())
+        .map_or(DEFAULT_PROMPT, String::as_str);
+    let interpreter = match version.interpreter {
+        Interpreter::CPython => "CPython",
+        Interpreter::PyPy => "PyPy",
+        Interpreter::GraalPy => "GraalPy",
+    };
+    template
+        .replace("{project}", project)
+        .replace("{version}", &version.to_string())
+        .replace("{interpreter}", interpreter)
+}
+
+/// Create a virtualenv for `project`, downloading the Python version first if needed.
 pub fn create_virtualenv(version: &Version, project: &str) -> Result<(), Error> {
+    create_virtualenv_with(version, project, None, &[])
+}
+
+/// A virtualenv's directory, named after its version unless it's given a `name`.
+fn virtualenv_path(project: &str, version: &Version, name: Option<&str>) -> std::path::PathBuf {
+    match name {
+        Some(name) => named_virtualenv_dir(project, name),
+        None => virtualenv_dir(project, version),
+    }
+}
+
+/// Fail if the virtualenv doesn't exist, for activating without creating it.
+pub fn require_virtualenv(
+    version: &Version,
+    project: &str,
+    name: Option<&str>,
+) -> Result<(), Error> {
+    if !virtualenv_path(project, version, name).is_dir() {
+        return Err(Error::VirtualenvNotFound(describe(project, version, name)));
+    }
+    Ok(())
+}
+
+/// How to refer to a virtualenv in messages.
+fn describe(project: &str, version: &Version, name: Option<&str>) -> String {
+    format!("{} ({version})", describe_project(project, name))
+}
+
+/// The Project as shown in the prompt, with a named virtualenv's name.
+fn describe_project(project: &str, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("{project}/{name}"),
+        None => project.to_string(),
+    }
+}
+
+/// Names that parse as versions would be mistaken for that version's own virtualenv.
+fn validate_virtualenv_name(name: &str) -> Result<(), Error> {
+    if validate_project_name(name).is_err() || name.parse::<Version>().is_ok() {
+        return Err(Error::InvalidVirtualenvName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// The version recorded for `project`'s virtualenv called `name`, if it exists.
+pub fn virtualenv_version(project: &str, name: &str) -> Result<Option<Version>, Error> {
+    read_version_file(&virtualenv_version_file(&named_virtualenv_dir(
+        project, name,
+    )))
+}
+
+/// Create a virtualenv like `create_virtualenv`, passing extra arguments to `python -m venv`.
+/// With a `name`, several virtualenvs of `project` can use the same version.
+pub fn create_virtualenv_with(
+    version: &Version,
+    project: &str,
+    name: Option<&str>,
+    venv_args: &[String],
+) -> Result<(), Error> {
+    validate_project_name(project)?;
+    if let Some(name) = name {
+        validate_virtualenv_name(name)?;
+        match virtualenv_version(project, name)? {
+            Some(existing) if existing != *version => {
+                return Err(Error::VirtualenvExists(describe(
+                    project,
+                    &existing,
+                    Some(name),
+                )))
+            }
+            Some(_) => return Ok(()),
+            None => {}
+        }
+    }
+    let virtualenv = virtualenv_path(project, version, name);
+    if virtualenv.exists() {
+        // A named virtualenv without a version file, or one of the Project's settings files.
+        if name.is_some() {
+            return Err(Error::VirtualenvExists(describe(project, version, name)));
+        }
+        return Ok(());
+    }
+    reject_foreign_platform()?;
     let python = python_dir(version);
     if !is_downloaded(&python)? {
-        download_python(version, false)?;
+        download_python(version, false, false)?;
     }
-    let next = std::fs::read_dir(&python)?
-        .next()
-        .expect("Downloaded python at {python:?} should not be empty.")?
-        .path();
-    let python_executable = next.join("bin/python3");
+    run_venv(
+        &python_executable(&python_install(version)?),
+        &virtualenv,
+        venv_args,
+    )?;
+    check_venv_version(&virtualenv, version)?;
+    if name.is_some() {
+        let file = virtualenv_version_file(&virtualenv);
+        std::fs::write(&file, version.to_string()).with_path(&file)?;
+    }
+    log::info!(
+        "Created virtualenv {} in {}",
+        describe(project, version, name),
+        virtualenv.display()
+    );
+    Ok(())
+}
+
+/// The project-local virtualenv many other tools expect, in the current directory.
+pub const LOCAL_VIRTUALENV: &str = ".venv";
+
+/// The current directory's `.venv`, if it is a virtualenv.
+pub fn local_virtualenv() -> Option<std::path::PathBuf> {
+    let virtualenv = std::path::PathBuf::from(LOCAL_VIRTUALENV);
+    pyvenv_cfg(&virtualenv).is_file().then_some(virtualenv)
+}
+
+/// Create `.venv` in the current directory with `version`, instead of one of a Project's
+/// virtualenvs, prompting with `prompt`. It is recreated with `force`, and otherwise left alone
+/// if it exists.
+pub fn create_local_virtualenv(
+    version: &Version,
+    prompt: &str,
+    venv_args: &[String],
+    force: bool,
+) -> Result<std::path::PathBuf, Error> {
+    let virtualenv = std::path::PathBuf::from(LOCAL_VIRTUALENV);
+    if virtualenv.exists() {
+        if !force {
+            log::info!("Virtualenv {LOCAL_VIRTUALENV} already exists");
+            return Ok(virtualenv);
+        }
+        std::fs::remove_dir_all(&virtualenv).with_path(&virtualenv)?;
+    }
+    reject_foreign_platform()?;
+    if !is_downloaded(&python_dir(version))? {
+        download_python(version, false, false)?;
+    }
+    let mut args = venv_args.to_vec();
+    args.extend(["--prompt".to_string(), prompt.to_string()]);
+    run_venv(
+        &python_executable(&python_install(version)?),
+        &virtualenv,
+        &args,
+    )?;
+    check_venv_version(&virtualenv, version)?;
+    log::info!("Created virtualenv {LOCAL_VIRTUALENV} using Python {version}");
+    Ok(virtualenv)
+}
+
+/// Install a requirements file and packages into a virtualenv given by its path.
+pub fn install_into(
+    virtualenv: &std::path::Path,
+    requirements: Option<&std::path::Path>,
+    packages: &[String],
+) -> Result<(), Error> {
+    if let Some(requirements) = requirements {
+        log::info!("Installing {}", requirements.display());
+        pip_install(virtualenv, &["-r".as_ref(), requirements.as_os_str()])?;
+    }
+    if !packages.is_empty() {
+        log::info!("Installing {}", packages.join(", "));
+        let args = packages
+            .iter()
+            .map(std::ffi::OsStr::new)
+            .collect::<Vec<_>>();
+        pip_install(virtualenv, &args)?;
+    }
+    Ok(())
+}
+
+/// Virtualenvs need a Python that runs here, so refuse to use one for another platform.
+fn reject_foreign_platform() -> Result<(), Error> {
+    match foreign_platform() {
+        Some(platform) => Err(Error::ForeignPlatform(platform.to_string())),
+        None => Ok(()),
+    }
+}
+
+fn python_install(version: &Version) -> Result<std::path::PathBuf, Error> {
+    let python = python_dir(version);
+    match find_interpreter_root(&python)? {
+        Some(install) => Ok(install),
+        None => Err(Error::BrokenInterpreter {
+            version: version.to_string(),
+            path: python.display().to_string(),
+            reason: "no Python executable was found".to_string(),
+        }),
+    }
+}
+
+/// Print the Python executable lilyenv uses for `version`, or the one in `project`'s virtualenv.
+pub fn print_python_path(version: &Version, project: Option<&str>) -> Result<(), Error> {
+    let executable = match project {
+        Some(project) => {
+            let virtualenv = virtualenv_dir(project, version);
+            if !virtualenv.exists() {
+                return Err(Error::VirtualenvNotFound(format!("{project} ({version})")));
+            }
+            venv_python(&virtualenv)
+        }
+        None => {
+            if !is_downloaded(&python_dir(version))? {
+                return Err(Error::NotDownloaded(version.to_string()));
+            }
+            python_executable(&python_install(version)?)
+        }
+    };
+    println!("{}", std::path::absolute(executable)?.display());
+    Ok(())
+}
+
+/// Create a virtualenv for `project` using an interpreter lilyenv didn't download, keyed by the
+/// x.y version it reports.
+pub fn create_virtualenv_from(
+    python: &std::path::Path,
+    project: &str,
+    venv_args: &[String],
+) -> Result<Version, Error> {
+    validate_project_name(project)?;
+    reject_foreign_platform()?;
+    let reported = reported_version(python).map_err(|reason| Error::ExternalInterpreter {
+        path: python.display().to_string(),
+        reason,
+    })?;
+    let version = Version {
+        bugfix: None,
+        prerelease: PreRelease::None,
+        ..reported
+    };
+    let virtualenv = virtualenv_dir(project, &version);
+    if virtualenv.exists() {
+        log::info!("Virtualenv {project} ({version}) already exists");
+        return Ok(version);
+    }
+    run_venv(python, &virtualenv, venv_args)?;
+    log::info!(
+        "Created virtualenv {project} ({version}) in {} using {}",
+        virtualenv.display(),
+        python.display()
+    );
+    Ok(version)
+}
+
+fn run_venv(
+    python: &std::path::Path,
+    virtualenv: &std::path::Path,
+    venv_args: &[String],
+) -> Result<(), Error> {
+    let mut command = match uv()? {
+        // Seeding installs pip, so the virtualenv works like one made by venv.
+        Some(uv) => {
+            let mut command = std::process::Command::new(uv);
+            command
+                .arg("venv")
+                .arg("--seed")
+                .arg("--python")
+                .arg(python);
+            command
+        }
+        None => {
+            let mut command = std::process::Command::new(python);
+            command.arg("-m").arg("venv");
+            command
+        }
+    };
+    let output = command.args(venv_args).arg(virtualenv).output()?;
+    if !output.status.success() {
+        // Don't leave a broken virtualenv behind to be mistaken for a working one.
+        if virtualenv.exists() {
+            std::fs::remove_dir_all(virtualenv).with_path(virtualenv)?;
+        }
+        return Err(Error::VenvCreation {
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Whether the Python version a virtualenv's `pyvenv.cfg` records, like `3.12.4`, is `version`.
+/// PyPy's bugfix version is its own patch number, and GraalPy's versions are its own release
+/// numbers, so only the Python versions that can be compared are.
+fn venv_version_matches(version: &Version, reported: &str) -> bool {
+    let reported = reported
+        .split('.')
+        .map(|part| part.trim().parse::<u16>().ok())
+        .collect::<Vec<_>>();
+    let [Some(major), Some(minor), bugfix @ ..] = &reported[..] else {
+        return false;
+    };
+    match version.interpreter {
+        Interpreter::GraalPy => true,
+        Interpreter::PyPy => (*major, *minor) == (version.major, version.minor),
+        Interpreter::CPython => {
+            (*major, *minor) == (version.major, version.minor)
+                && match (version.bugfix, bugfix.first()) {
+                    (Some(requested), Some(reported)) => Some(requested) == *reported,
+                    _ => true,
+                }
+        }
+    }
+}
+
+/// Check the new virtualenv uses the Python that was asked for, removing it if not, to catch
+/// resolving `version` to the wrong interpreter.
+fn check_venv_version(virtualenv: &std::path::Path, version: &Version) -> Result<(), Error> {
+    let mut cfg = read_pyvenv_cfg(virtualenv)?;
+    // venv records `version`, and uv `version_info`.
+    let Some(reported) = cfg.remove("version").or_else(|| cfg.remove("version_info")) else {
+        return Ok(());
+    };
+    if venv_version_matches(version, &reported) {
+        return Ok(());
+    }
+    std::fs::remove_dir_all(virtualenv).with_path(virtualenv)?;
+    Err(Error::VersionMismatch {
+        requested: version.to_string(),
+        actual: reported,
+        path: virtualenv.display().to_string(),
+    })
+}
+
+/// A `pip install` command for `virtualenv`, run by uv if that's the backend.
+fn pip_install_command(virtualenv: &std::path::Path) -> Result<std::process::Command, Error> {
+    let mut command = match uv()? {
+        Some(uv) => {
+            let mut command = std::process::Command::new(uv);
+            command
+                .arg("pip")
+                .arg("install")
+                .arg("--python")
+                .arg(venv_python(virtualenv));
+            command
+        }
+        None => {
+            let mut command = std::process::Command::new(venv_python(virtualenv));
+            command.arg("-m").arg("pip").arg("install");
+            command
+        }
+    };
+    command.arg("--quiet");
+    Ok(command)
+}
+
+fn pip_install(virtualenv: &std::path::Path, args: &[&std::ffi::OsStr]) -> Result<(), Error> {
+    let output = pip_install_command(virtualenv)?.args(args).output()?;
+    if !output.status.success() {
+        return Err(Error::PipInstall(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Upgrade the virtualenv's pip, setuptools and wheel, like `python -m venv --upgrade-deps` but
+/// also for Pythons older than 3.9.
+pub fn upgrade_deps(version: &Version, project: &str, name: Option<&str>) -> Result<(), Error> {
+    log::info!("Upgrading pip, setuptools and wheel");
+    pip_install(
+        &virtualenv_path(project, version, name),
+        &[
+            "--upgrade".as_ref(),
+            "pip".as_ref(),
+            "setuptools".as_ref(),
+            "wheel".as_ref(),
+        ],
+    )
+}
+
+/// Requirements pinning pip and setuptools to the given versions, which may be an exact version
+/// like `23.2` or a specifier like `<24`.
+pub fn pinned_deps(pip: Option<&str>, setuptools: Option<&str>) -> Vec<String> {
+    [("pip", pip), ("setuptools", setuptools)]
+        .into_iter()
+        .filter_map(|(package, spec)| {
+            let spec = spec?.trim();
+            match spec.starts_with(|c: char| c.is_ascii_digit()) {
+                true => Some(format!("{package}=={spec}")),
+                false => Some(format!("{package}{spec}")),
+            }
+        })
+        .collect()
+}
+
+pub fn install_requirements(
+    version: &Version,
+    project: &str,
+    name: Option<&str>,
+    requirements: &std::path::Path,
+) -> Result<(), Error> {
+    install_into(
+        &virtualenv_path(project, version, name),
+        Some(requirements),
+        &[],
+    )
+}
+
+pub fn install_packages(
+    version: &Version,
+    project: &str,
+    name: Option<&str>,
+    packages: &[String],
+) -> Result<(), Error> {
+    install_into(&virtualenv_path(project, version, name), None, packages)
+}
+
+fn freeze(version: &Version, project: &str) -> Result<Vec<String>, Error> {
     let virtualenv = virtualenv_dir(project, version);
-    std::process::Command::new(python_executable)
+    if !virtualenv.exists() {
+        return Err(Error::VirtualenvNotFound(format!("{project} ({version})")));
+    }
+    let output = std::process::Command::new(venv_python(&virtualenv))
         .arg("-m")
-        .arg("venv")
-        .arg(virtualenv)
+        .arg("pip")
+        .arg("freeze")
         .output()?;
+    if !output.status.success() {
+        return Err(Error::PipFreeze(format!("{project} ({version})")));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Recreate a virtualenv from its Python, reinstalling the packages it had installed. The old
+/// virtualenv is kept aside until this succeeds, and put back if it doesn't.
+pub fn recreate_virtualenv(version: &Version, project: &str) -> Result<(), Error> {
+    let requirements = freeze(version, project)?;
+    let virtualenv = virtualenv_dir(project, version);
+    let mut old = virtualenv.as_os_str().to_owned();
+    old.push(".old");
+    let old = std::path::PathBuf::from(old);
+    if old.exists() {
+        std::fs::remove_dir_all(&old).with_path(&old)?;
+    }
+    std::fs::rename(&virtualenv, &old).with_path(&virtualenv)?;
+    let recreated = create_virtualenv(version, project)
+        .and_then(|()| install_packages(version, project, None, &requirements));
+    match recreated {
+        Ok(()) => std::fs::remove_dir_all(&old).with_path(&old),
+        Err(err) => {
+            if virtualenv.exists() {
+                std::fs::remove_dir_all(&virtualenv).with_path(&virtualenv)?;
+            }
+            std::fs::rename(&old, &virtualenv).with_path(&virtualenv)?;
+            Err(err)
+        }
+    }
+}
+
+pub fn clone_virtualenv(version: &Version, source: &str, target: &str) -> Result<(), Error> {
+    validate_project_name(target)?;
+    if virtualenv_dir(target, version).exists() {
+        return Err(Error::VirtualenvExists(format!("{target} ({version})")));
+    }
+    let requirements = freeze(version, source)?;
+    create_virtualenv(version, target)?;
+
+    let virtualenv = virtualenv_dir(target, version);
+    let mut failed = vec![];
+    for requirement in &requirements {
+        log::info!("Installing {requirement}");
+        let status = pip_install_command(&virtualenv)?
+            .arg(requirement)
+            .status()?;
+        if !status.success() {
+            failed.push(requirement.as_str());
+        }
+    }
+    if !failed.is_empty() {
+        return Err(Error::PipInstall(format!(
+            "Could not install {} in {target} ({version})",
+            failed.join(", ")
+        )));
+    }
     Ok(())
 }
 
 pub fn remove_virtualenv(project: &str, version: &Version) -> Result<(), Error> {
-    let virtualenv = virtualenv_dir(project, version);
-    std::fs::remove_dir_all(virtualenv)?;
+    validate_project_name(project)?;
+    remove_virtualenv_dir(
+        &virtualenv_dir(project, version),
+        &describe(project, version, None),
+    )?;
+    if default_version(project)? == Some(*version) {
+        let file = default_version_file(project);
+        std::fs::remove_file(&file).with_path(&file)?;
+    }
+    if last_version(project)? == Some(*version) {
+        let file = last_version_file(project);
+        std::fs::remove_file(&file).with_path(&file)?;
+    }
+    remove_empty_project(project)
+}
+
+/// Remove `project`'s virtualenv called `name`.
+pub fn remove_named_virtualenv(project: &str, name: &str) -> Result<(), Error> {
+    validate_project_name(project)?;
+    validate_virtualenv_name(name)?;
+    let label = match virtualenv_version(project, name)? {
+        Some(version) => describe(project, &version, Some(name)),
+        None => format!("{project}/{name}"),
+    };
+    remove_virtualenv_dir(&named_virtualenv_dir(project, name), &label)?;
+    remove_empty_project(project)
+}
+
+fn remove_virtualenv_dir(virtualenv: &std::path::Path, label: &str) -> Result<(), Error> {
+    if !virtualenv.is_dir() {
+        return Err(Error::VirtualenvNotFound(label.to_string()));
+    }
+    if let Some(active) = std::env::var_os("VIRTUAL_ENV") {
+        if std::path::Path::new(&active) == virtualenv {
+            return Err(Error::VirtualenvActive(label.to_string()));
+        }
+    }
+    std::fs::remove_dir_all(virtualenv).with_path(virtualenv)?;
+    Ok(())
+}
+
+pub(crate) fn remove_empty_project(project: &str) -> Result<(), Error> {
+    let project_dir = project_dir(project);
+    if std::fs::read_dir(&project_dir)
+        .with_path(&project_dir)?
+        .next()
+        .is_none()
+    {
+        std::fs::remove_dir(&project_dir).with_path(&project_dir)?;
+    }
+    Ok(())
+}
+
+/// Rename a Project by recreating each of its virtualenvs under the new name with the same
+/// packages, since virtualenvs can't be moved, then removing the old ones. The Project's settings,
+/// like its directory and default version, are kept.
+pub fn rename_project(old: &str, new: &str) -> Result<(), Error> {
+    validate_project_name(old)?;
+    validate_project_name(new)?;
+    let old_dir = project_dir(old);
+    let new_dir = project_dir(new);
+    if new_dir.exists() {
+        return Err(Error::VirtualenvExists(new.to_string()));
+    }
+    if let Some(active) = std::env::var_os("VIRTUAL_ENV") {
+        if std::path::Path::new(&active).starts_with(&old_dir) {
+            return Err(Error::VirtualenvActive(old.to_string()));
+        }
+    }
+    let names = named_virtualenvs(old)?;
+    if !names.is_empty() {
+        return Err(Error::NamedVirtualenvs {
+            project: old.to_string(),
+            names: names.join(", "),
+        });
+    }
+    for version in project_virtualenvs(old)? {
+        // The old project is only removed once every virtualenv has all its packages again.
+        if let Err(err) = clone_virtualenv(&version, old, new) {
+            if new_dir.exists() {
+                std::fs::remove_dir_all(&new_dir).with_path(&new_dir)?;
+            }
+            return Err(err);
+        }
+    }
+    for entry in std::fs::read_dir(&old_dir).with_path(&old_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            std::fs::create_dir_all(&new_dir).with_path(&new_dir)?;
+            std::fs::copy(entry.path(), new_dir.join(entry.file_name())).with_path(entry.path())?;
+        }
+    }
+    remove_project(old)?;
+    log::info!("Renamed {old} to {new}");
     Ok(())
 }
 
 pub fn remove_project(project: &str) -> Result<(), Error> {
-    std::fs::remove_dir_all(project_dir(project))?;
+    validate_project_name(project)?;
+    let project_dir = project_dir(project);
+    std::fs::remove_dir_all(&project_dir).with_path(&project_dir)?;
     Ok(())
 }
 
 pub fn set_project_directory(project: &str, default_directory: &str) -> Result<(), Error> {
-    std::fs::write(project_file(project), default_directory)?;
+    let file = project_file(project);
+    std::fs::write(&file, default_directory).with_path(&file)?;
     Ok(())
 }
 
 pub fn unset_project_directory(project: &str) -> Result<(), Error> {
-    std::fs::remove_file(project_file(project))?;
+    let file = project_file(project);
+    std::fs::remove_file(&file).with_path(&file)?;
     Ok(())
 }
 
+/// Use `version` when activating `project` without a version.
+pub fn set_default_version(project: &str, version: &Version) -> Result<(), Error> {
+    let file = default_version_file(project);
+    std::fs::write(&file, version.to_string()).with_path(&file)?;
+    Ok(())
+}
+
+fn default_version(project: &str) -> Result<Option<Version>, Error> {
+    read_version_file(&default_version_file(project))
+}
+
+/// The version `project` was last activated with.
+fn last_version(project: &str) -> Result<Option<Version>, Error> {
+    read_version_file(&last_version_file(project))
+}
+
+fn read_version_file(file: &std::path::Path) -> Result<Option<Version>, Error> {
+    match std::fs::read_to_string(file) {
+        Ok(version) => Ok(Some(version.trim().parse()?)),
+        Err(err) => match err.kind() {
+            std::io::ErrorKind::NotFound => Ok(None),
+            _ => Err(err)?,
+        },
+    }
+}
+
+/// The version to activate `project` with when none is given: its default version, the version
+/// it was last activated with, or its only virtualenv's version.
+pub fn project_version(project: &str) -> Result<Version, Error> {
+    if let Some(version) = default_version(project)? {
+        return Ok(version);
+    }
+    if let Some(version) = last_version(project)? {
+        if virtualenv_dir(project, &version).exists() {
+            return Ok(version);
+        }
+    }
+    let project_dir = project_dir(project);
+    let versions = match project_dir.is_dir() {
+        true => list_versions(project_dir)?,
+        false => vec![],
+    };
+    let versions = versions
+        .into_iter()
+        .filter(|version| version.parse::<Version>().is_ok())
+        .collect::<Vec<_>>();
+    match &versions[..] {
+        [] => Err(Error::NoVersionFile),
+        [version] => version.parse(),
+        versions => Err(Error::AmbiguousVersion {
+            project: project.to_string(),
+            versions: versions.join(", "),
+        }),
+    }
+}
+
+/// The version to use for `project` when its `pyproject.toml` has a `requires-python`
+/// `specifier`: the version it would otherwise use if that satisfies it, or the newest installed
+/// Python that does, or the newest release that does.
+pub fn requires_python_version(
+    project: &str,
+    specifier: &VersionSpecifier,
+) -> Result<Version, Error> {
+    match project_version(project) {
+        Ok(version) if specifier.matches(&version) => return Ok(version),
+        Ok(_) | Err(Error::NoVersionFile) | Err(Error::AmbiguousVersion { .. }) => {}
+        Err(err) => return Err(err),
+    }
+    let installed = installed_pythons()?
+        .into_iter()
+        .map(|python| python.version)
+        .filter(|version| specifier.matches(version))
+        .max();
+    let version = match installed {
+        Some(version) => version,
+        None => resolve_request(&VersionRequest::Specifier(specifier.clone()), false, false)?,
+    };
+    // Virtualenvs are usually for an x.y version, which gets its newest bugfix release.
+    let minor = Version {
+        bugfix: None,
+        ..version
+    };
+    match specifier.matches(&minor) {
+        true => Ok(minor),
+        false => Ok(version),
+    }
+}
+
 fn project_directory(project: &str) -> Result<Option<String>, Error> {
     match std::fs::read_to_string(project_file(project)) {
         Ok(default_directory) => Ok(Some(default_directory)),
@@ -56,47 +787,692 @@ fn project_directory(project: &str) -> Result<Option<String>, Error> {
     }
 }
 
-pub fn activate_virtualenv(version: &Version, project: &str) -> Result<(), Error> {
-    let virtualenv = virtualenv_dir(project, version);
-    if !virtualenv.exists() {
-        create_virtualenv(version, project)?
-    }
+#[cfg(not(windows))]
+const PATH_SEPARATOR: char = ':';
+#[cfg(windows)]
+const PATH_SEPARATOR: char = ';';
+
+const TERMINFO_DIRS: [&str; 3] = ["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"];
+
+/// `PATH` with the virtualenv's scripts first.
+fn venv_path(virtualenv: &std::path::Path) -> Result<String, Error> {
     let path = std::env::var("PATH")?;
-    let path = format!("{}:{path}", virtualenv.join("bin").display());
+    Ok(format!(
+        "{}{PATH_SEPARATOR}{path}",
+        venv_bin_dir(virtualenv).display()
+    ))
+}
+
+fn activation_env(
+    version: &Version,
+    project: &str,
+    name: Option<&str>,
+) -> Result<Vec<(String, String)>, Error> {
+    validate_project_name(project)?;
+    reject_foreign_platform()?;
+    let virtualenv = virtualenv_path(project, version, name);
+    create_virtualenv_with(version, project, name, &[])?;
+    if let Some(home) = missing_base_interpreter(&virtualenv)? {
+        let label = describe(project, version, name);
+        if !confirm_recreate(&label, &home)? {
+            let mut command = format!("lilyenv virtualenv {project} {version} --force");
+            if let Some(name) = name {
+                command.push_str(&format!(" --name {name}"));
+            }
+            return Err(Error::BaseInterpreterMissing {
+                virtualenv: label,
+                home,
+                command,
+            });
+        }
+        discard_virtualenv(version, project, name)?;
+        create_virtualenv_with(version, project, name, &[])?;
+    }
+    let python = python_dir(version).join("python");
+    let mut env = vec![
+        ("VIRTUAL_ENV".to_string(), virtualenv.display().to_string()),
+        (
+            "VIRTUAL_ENV_PROMPT".to_string(),
+            prompt(version, &describe_project(project, name)),
+        ),
+        ("PATH".to_string(), venv_path(&virtualenv)?),
+        (
+            "LD_LIBRARY_PATH".to_string(),
+            python.join("lib").display().to_string(),
+        ),
+    ];
+    // The standalone builds look for terminfo in their own install directory, so point them at
+    // the system's terminfo databases. These locations differ between Linux distributions and
+    // macOS, so only include the ones that exist.
+    let terminfo_dirs = TERMINFO_DIRS
+        .iter()
+        .filter(|dir| std::path::Path::new(dir).is_dir())
+        .copied()
+        .collect::<Vec<_>>();
+    if !terminfo_dirs.is_empty() {
+        env.push(("TERMINFO_DIRS".to_string(), terminfo_dirs.join(":")));
+    }
+    for (key, value) in project_env(project)? {
+        match env.iter().any(|(set, _)| *set == key) {
+            true => log::warn!("Ignoring {key} in {ENV_FILE}, as lilyenv sets it itself."),
+            false => env.push((key, value)),
+        }
+    }
+    Ok(env)
+}
+
+/// A file of `KEY=VALUE` lines setting extra environment variables when activating a Project.
+pub const ENV_FILE: &str = ".lilyenv-env";
 
-    let mut shell = std::process::Command::new(get_shell(Some(project))?);
+/// The variables in the Project's `.lilyenv-env`, which is in its directory as set with
+/// `set-project-directory`, or else the current directory.
+fn project_env(project: &str) -> Result<Vec<(String, String)>, Error> {
+    let file = match project_directory(project)? {
+        Some(directory) => std::path::Path::new(&directory).join(ENV_FILE),
+        None => std::path::PathBuf::from(ENV_FILE),
+    };
+    let contents = match std::fs::read_to_string(&file) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err).with_path(&file),
+    };
+    parse_env_file(&contents).map_err(|reason| Error::InvalidEnvFile {
+        path: file.display().to_string(),
+        reason,
+    })
+}
+
+/// Parse `KEY=VALUE` lines, ignoring blank lines and `#` comments. Lines may start with `export`
+/// as in shell scripts, and values may be wrapped in single or double quotes, which are removed.
+fn parse_env_file(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut env = vec![];
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {} isn't KEY=VALUE", number + 1));
+        };
+        let key = key.trim();
+        let valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!(
+                "line {} sets {key:?}, which isn't a valid variable name",
+                number + 1
+            ));
+        }
+        let value = value.trim();
+        let value = ['"', '\'']
+            .into_iter()
+            .find_map(|quote| value.strip_prefix(quote)?.strip_suffix(quote))
+            .unwrap_or(value);
+        env.push((key.to_string(), value.to_string()));
+    }
+    Ok(env)
+}
+
+/// How to activate a virtualenv.
+#[derive(Debug, Clone)]
+pub enum Activation {
+    /// Start a subshell, using `shell` instead of the Project's, and stop it after `timeout`.
+    /// With `clean_env`, it only inherits the essential environment variables and those listed.
+    Subshell {
+        shell: Option<String>,
+        timeout: Option<std::time::Duration>,
+        clean_env: Option<Vec<String>>,
+    },
+    /// Print the statements in this format that activate it in the current shell.
+    Export(EnvFormat),
+}
+
+/// The base interpreter directory in the virtualenv's `pyvenv.cfg`, if it has gone, as it does
+/// when the Python the virtualenv was created from is uninstalled.
+fn missing_base_interpreter(virtualenv: &std::path::Path) -> Result<Option<String>, Error> {
+    if !pyvenv_cfg(virtualenv).exists() {
+        return Ok(None);
+    }
+    Ok(read_pyvenv_cfg(virtualenv)?
+        .remove("home")
+        .filter(|home| !std::path::Path::new(home).exists()))
+}
+
+/// Every virtualenv whose base interpreter is missing, with its Project, except the active one.
+pub fn broken_virtualenvs() -> Result<Vec<(String, std::path::PathBuf)>, Error> {
+    let active = std::env::var_os("VIRTUAL_ENV").map(std::path::PathBuf::from);
+    let mut broken = vec![];
+    for (project, version) in all_virtualenvs()? {
+        let virtualenv = project_dir(&project).join(version);
+        if active.as_ref() != Some(&virtualenv) && missing_base_interpreter(&virtualenv)?.is_some()
+        {
+            broken.push((project, virtualenv));
+        }
+    }
+    Ok(broken)
+}
+
+/// Ask whether to recreate a virtualenv with a missing base interpreter, when there's someone
+/// to ask.
+fn confirm_recreate(label: &str, home: &str) -> Result<bool, Error> {
+    use std::io::{IsTerminal, Write};
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return Ok(false);
+    }
+    eprintln!("The Python virtualenv {label} was created from is missing from {home}.");
+    eprint!("Recreate the virtualenv? Its packages will need installing again. [y/N] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Remove a virtualenv's directory so it can be created again, keeping the Project's settings.
+pub fn discard_virtualenv(
+    version: &Version,
+    project: &str,
+    name: Option<&str>,
+) -> Result<(), Error> {
+    validate_project_name(project)?;
+    let virtualenv = virtualenv_path(project, version, name);
+    match virtualenv.exists() {
+        true => remove_virtualenv_dir(&virtualenv, &describe(project, version, name)),
+        false => Ok(()),
+    }
+}
+
+/// Activate a virtualenv in a subshell, or print the statements that activate it in the current
+/// shell.
+pub fn activate_virtualenv(
+    version: &Version,
+    project: &str,
+    name: Option<&str>,
+    packages: &[String],
+    upgrade: bool,
+    pins: &[String],
+    activation: Activation,
+) -> Result<i32, Error> {
+    let env = activation_env(version, project, name)?;
+    if upgrade {
+        upgrade_deps(version, project, name)?;
+    }
+    install_packages(version, project, name, pins)?;
+    install_packages(version, project, name, packages)?;
+    // A named virtualenv's version alone doesn't say which virtualenv to activate.
+    if name.is_none() {
+        let file = last_version_file(project);
+        std::fs::write(&file, version.to_string()).with_path(&file)?;
+    }
+    let (shell, timeout, clean_env) = match activation {
+        Activation::Export(format) => {
+            println!("{}", format_env(env, format));
+            return Ok(0);
+        }
+        Activation::Subshell {
+            shell,
+            timeout,
+            clean_env,
+        } => {
+            let shell = match shell {
+                Some(shell) => shell,
+                None => get_shell(Some(project))?,
+            };
+            (shell, timeout, clean_env)
+        }
+    };
+
+    let (mut shell, _startup) = subshell_command(&shell, clean_env.as_deref())?;
     let shell = match project_directory(project)? {
         Some(directory) => shell.current_dir(directory),
         _ => &mut shell,
     };
-    let python = python_dir(version).join("python");
-    let mut shell = shell
-        .env("VIRTUAL_ENV", &virtualenv)
-        .env("VIRTUAL_ENV_PROMPT", format!("{project} ({version}) "))
-        .env("PATH", path)
-        .env(
-            "TERMINFO_DIRS",
-            "/etc/terminfo:/lib/terminfo:/usr/share/terminfo",
-        )
-        .env("LD_LIBRARY_PATH", python.join("lib"))
-        .spawn()?;
-    shell.wait()?;
+    wait_for_shell(shell.envs(env).spawn()?, timeout)
+}
+
+/// Activate a virtualenv lilyenv didn't create, prompting with the prompt it was created with, or
+/// else its directory's name, as `venv`'s own activate scripts do.
+pub fn activate_external_virtualenv(
+    virtualenv: &std::path::Path,
+    activation: Activation,
+) -> Result<i32, Error> {
+    if !pyvenv_cfg(virtualenv).is_file() {
+        return Err(Error::NotAVirtualenv(virtualenv.display().to_string()));
+    }
+    let virtualenv = std::path::absolute(virtualenv)?;
+    // venv quotes the prompt it records, and uv doesn't.
+    let prompt = read_pyvenv_cfg(&virtualenv)?
+        .remove("prompt")
+        .map(|prompt| prompt.trim_matches(['\'', '"']).to_string());
+    let name = prompt.unwrap_or_else(|| {
+        virtualenv
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    });
+    let env = vec![
+        ("VIRTUAL_ENV".to_string(), virtualenv.display().to_string()),
+        ("VIRTUAL_ENV_PROMPT".to_string(), format!("({name}) ")),
+        ("PATH".to_string(), venv_path(&virtualenv)?),
+    ];
+    let (shell, timeout, clean_env) = match activation {
+        Activation::Export(format) => {
+            println!("{}", format_env(env, format));
+            return Ok(0);
+        }
+        Activation::Subshell {
+            shell,
+            timeout,
+            clean_env,
+        } => {
+            let shell = match shell {
+                Some(shell) => shell,
+                None => get_shell(None)?,
+            };
+            (shell, timeout, clean_env)
+        }
+    };
+    let (mut shell, _startup) = subshell_command(&shell, clean_env.as_deref())?;
+    wait_for_shell(shell.envs(env).spawn()?, timeout)
+}
+
+/// Wait for an activated shell to exit, returning its exit code, or kill it after `timeout`.
+fn wait_for_shell(
+    mut shell: std::process::Child,
+    timeout: Option<std::time::Duration>,
+) -> Result<i32, Error> {
+    // Ctrl-C in the shell is the shell's to handle, and mustn't kill lilyenv under it.
+    let _interrupts = IgnoreInterrupts::new();
+    let status = match timeout {
+        None => shell.wait()?,
+        Some(timeout) => {
+            let deadline = std::time::Instant::now() + timeout;
+            loop {
+                if let Some(status) = shell.try_wait()? {
+                    break status;
+                }
+                if std::time::Instant::now() >= deadline {
+                    shell.kill()?;
+                    shell.wait()?;
+                    return Err(Error::ShellTimeout(timeout.as_secs()));
+                }
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+        }
+    };
+    Ok(status.code().unwrap_or(1))
+}
+
+pub fn run_in_virtualenv(
+    version: &Version,
+    project: &str,
+    command: &[String],
+) -> Result<std::process::ExitStatus, Error> {
+    let env = activation_env(version, project, None)?;
+    let (program, args) = command
+        .split_first()
+        .expect("clap requires at least one command argument.");
+    Ok(std::process::Command::new(program)
+        .args(args)
+        .envs(env)
+        .status()?)
+}
+
+/// The versions of a Project's usable virtualenvs, skipping named virtualenvs, stray directories
+/// and virtualenvs whose Python has gone.
+fn project_virtualenvs(project: &str) -> Result<Vec<Version>, Error> {
+    let project_dir = project_dir(project);
+    let versions = match project_dir.is_dir() {
+        true => list_versions(project_dir)?,
+        false => vec![],
+    };
+    if versions.is_empty() {
+        return Err(Error::VirtualenvNotFound(project.to_string()));
+    }
+    let mut virtualenvs = vec![];
+    for version in versions {
+        let Ok(parsed) = version.parse::<Version>() else {
+            if virtualenv_version(project, &version)?.is_some() {
+                log::debug!("Skipping the named virtualenv {project}/{version}.");
+            } else {
+                log::warn!("Skipping {project}/{version}, which is not a virtualenv.");
+            }
+            continue;
+        };
+        if !venv_python(&virtualenv_dir(project, &parsed)).exists() {
+            log::warn!("Skipping {project} ({version}), which has no Python.");
+            continue;
+        }
+        virtualenvs.push(parsed);
+    }
+    Ok(virtualenvs)
+}
+
+/// The names of a Project's named virtualenvs.
+fn named_virtualenvs(project: &str) -> Result<Vec<String>, Error> {
+    let mut names = vec![];
+    for name in list_versions(project_dir(project))? {
+        if name.parse::<Version>().is_err() && virtualenv_version(project, &name)?.is_some() {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+/// Run `command` in each of `project`'s virtualenvs in turn and print which passed. Returns 0 if
+/// every run passed and 1 otherwise.
+pub fn run_matrix(project: &str, command: &[String]) -> Result<i32, Error> {
+    let mut results = vec![];
+    for version in project_virtualenvs(project)? {
+        log::info!("Running in {project} ({version})");
+        let result = match run_in_virtualenv(&version, project, command) {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(match status.code() {
+                Some(code) => format!("exited with {code}"),
+                None => "was killed by a signal".to_string(),
+            }),
+            Err(err) => Err(err.to_string()),
+        };
+        results.push((version, result));
+    }
+
+    let mut failed = 0;
+    for (version, result) in results {
+        match result {
+            Ok(()) => println!("pass: {version}"),
+            Err(reason) => {
+                failed += 1;
+                println!("FAIL: {version} {reason}");
+            }
+        }
+    }
+    match failed {
+        0 => Ok(0),
+        _ => Ok(1),
+    }
+}
+
+/// A snapshot of a Project's virtualenvs and the packages installed in each, for recreating them
+/// on another machine.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub project: String,
+    pub virtualenvs: Vec<ManifestVirtualenv>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestVirtualenv {
+    pub version: String,
+    /// The `pip freeze` output for the virtualenv.
+    pub packages: Vec<String>,
+}
+
+/// Print a JSON manifest of `project`'s virtualenvs, or write it to `output`.
+pub fn export_project(project: &str, output: Option<&std::path::Path>) -> Result<(), Error> {
+    let virtualenvs = project_virtualenvs(project)?
+        .into_iter()
+        .map(|version| {
+            Ok(ManifestVirtualenv {
+                packages: freeze(&version, project)?,
+                version: version.to_string(),
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let manifest = Manifest {
+        project: project.to_string(),
+        virtualenvs,
+    };
+    let manifest =
+        serde_json::to_string_pretty(&manifest).expect("A manifest is valid json.") + "\n";
+    match output {
+        Some(output) => std::fs::write(output, manifest).with_path(output)?,
+        None => print!("{manifest}"),
+    }
+    Ok(())
+}
+
+/// Create the virtualenvs in a manifest written by `export_project`, downloading their Pythons
+/// if needed, and install their packages. With `project`, the virtualenvs are created for that
+/// Project instead of the one in the manifest.
+pub fn import_project(manifest: &std::path::Path, project: Option<&str>) -> Result<(), Error> {
+    let invalid = |reason: String| Error::InvalidManifest {
+        path: manifest.display().to_string(),
+        reason,
+    };
+    let contents = std::fs::read_to_string(manifest).with_path(manifest)?;
+    let manifest: Manifest =
+        serde_json::from_str(&contents).map_err(|err| invalid(err.to_string()))?;
+    let project = validate_project_name(project.unwrap_or(&manifest.project))?;
+    let virtualenvs = manifest
+        .virtualenvs
+        .iter()
+        .map(|virtualenv| Ok((virtualenv.version.parse::<Version>()?, &virtualenv.packages)))
+        .collect::<Result<Vec<_>, Error>>()
+        .map_err(|err| invalid(err.to_string()))?;
+    for (version, packages) in virtualenvs {
+        create_virtualenv(&version, project)?;
+        install_packages(&version, project, None, packages)?;
+        log::info!("Imported {project} ({version})");
+    }
+    Ok(())
+}
+
+/// The file `lilyenv lock` writes and `lilyenv sync` reads, in the current directory.
+pub const LOCK_FILE: &str = "lilyenv.lock";
+
+/// The exact Python releases a Project's virtualenvs use, so everyone installs the same ones.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LockFile {
+    pub project: String,
+    #[serde(default, rename = "python")]
+    pub pythons: Vec<LockedPython>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LockedPython {
+    /// The virtualenv's version, like `3.12`.
+    pub version: String,
+    /// The exact version installed for it, like `3.12.4`.
+    pub release: String,
+    pub release_tag: String,
+    pub url: url::Url,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// Write the exact release of each of `project`'s virtualenvs' Pythons to `lilyenv.lock`.
+pub fn lock_project(project: &str) -> Result<(), Error> {
+    let pythons = project_virtualenvs(project)?
+        .into_iter()
+        .map(|version| {
+            let (metadata, sha256) = installed_release(&version)?;
+            Ok(LockedPython {
+                version: version.to_string(),
+                release: metadata.release.to_string(),
+                release_tag: metadata.release_tag,
+                url: metadata.url,
+                sha256,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let lock = LockFile {
+        project: project.to_string(),
+        pythons,
+    };
+    let contents = format!(
+        "# Written by `lilyenv lock`. Install these Pythons with `lilyenv sync`.\n{}",
+        toml::to_string(&lock).expect("A lock file is valid toml.")
+    );
+    let file = std::path::Path::new(LOCK_FILE);
+    std::fs::write(file, contents).with_path(file)?;
+    log::info!(
+        "Wrote the Pythons of {project}'s {} virtualenvs to {LOCK_FILE}",
+        lock.pythons.len()
+    );
+    Ok(())
+}
+
+/// Install exactly the Pythons in `lilyenv.lock` and create the virtualenvs using them. With
+/// `project`, the virtualenvs are created for that Project instead of the one in the lock file.
+pub fn sync_project(project: Option<&str>, refresh: bool) -> Result<(), Error> {
+    let file = std::path::Path::new(LOCK_FILE);
+    let invalid = |reason: String| Error::InvalidManifest {
+        path: file.display().to_string(),
+        reason,
+    };
+    let contents = std::fs::read_to_string(file).with_path(file)?;
+    let lock: LockFile = toml::from_str(&contents).map_err(|err| invalid(err.to_string()))?;
+    let project = validate_project_name(project.unwrap_or(&lock.project))?;
+    let pythons = lock
+        .pythons
+        .iter()
+        .map(|python| Ok((python.version.parse()?, python.release.parse()?, python)))
+        .collect::<Result<Vec<(Version, Version, _)>, Error>>()
+        .map_err(|err| invalid(err.to_string()))?;
+    for (version, release, python) in pythons {
+        sync_release(
+            &version,
+            &release,
+            &python.release_tag,
+            python.sha256.as_deref(),
+            refresh,
+        )?;
+        create_virtualenv(&version, project)?;
+    }
+    Ok(())
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+pub enum EnvFormat {
+    #[default]
+    Shell,
+    Json,
+    Powershell,
+    Nushell,
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn shell_exports(env: &[(String, String)]) -> String {
+    env.iter()
+        .map(|(key, value)| format!("export {key}={}", shell_quote(value)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// PowerShell has no escapes in single quoted strings, only doubled quotes.
+fn powershell_assignments(env: &[(String, String)]) -> String {
+    env.iter()
+        .map(|(key, value)| format!("$env:{key} = '{}'", value.replace('\'', "''")))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A nushell `load-env` statement. Json strings are also valid nushell double quoted strings.
+fn nushell_load_env(env: &[(String, String)]) -> String {
+    let fields = env
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{key}: {}",
+                serde_json::to_string(value).expect("A string is valid json.")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("load-env {{{fields}}}")
+}
+
+fn format_env(env: Vec<(String, String)>, format: EnvFormat) -> String {
+    match format {
+        EnvFormat::Shell => shell_exports(&env),
+        EnvFormat::Powershell => powershell_assignments(&env),
+        EnvFormat::Nushell => nushell_load_env(&env),
+        EnvFormat::Json => {
+            let env = env
+                .into_iter()
+                .map(|(key, value)| (key, serde_json::Value::String(value)))
+                .collect::<serde_json::Map<_, _>>();
+            serde_json::to_string_pretty(&env).expect("A map of strings is valid json.")
+        }
+    }
+}
+
+pub fn print_activation_env(
+    version: &Version,
+    project: &str,
+    format: EnvFormat,
+    temp_file: bool,
+) -> Result<(), Error> {
+    let env = activation_env(version, project, None)?;
+    let output = format_env(env, format);
+    match temp_file {
+        false => println!("{output}"),
+        true => {
+            let extension = match format {
+                EnvFormat::Shell => "sh",
+                EnvFormat::Json => "json",
+                EnvFormat::Powershell => "ps1",
+                EnvFormat::Nushell => "nu",
+            };
+            // A new file with a random name only the user can read, as it may hold secrets from
+            // the project's environment file and mustn't follow a symlink someone else planted.
+            let temp_dir = std::env::temp_dir();
+            let (mut file, path) = tempfile::Builder::new()
+                .prefix(&format!("lilyenv-{project}-{version}-"))
+                .suffix(&format!(".{extension}"))
+                .tempfile()
+                .and_then(|file| file.keep().map_err(|err| err.error))
+                .with_path(&temp_dir)?;
+            std::io::Write::write_all(&mut file, output.as_bytes()).with_path(&path)?;
+            println!("{}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Print, or add to `.envrc`, a direnv snippet that activates `project`'s virtualenv with
+/// `lilyenv env` whenever direnv loads it.
+pub fn direnv(version: &Version, project: &str, write: bool) -> Result<(), Error> {
+    // Create the virtualenv now rather than the first time direnv loads the snippet.
+    create_virtualenv(version, project)?;
+    let activate = format!("eval \"$(lilyenv env {} {version})\"", shell_quote(project));
+    let snippet = format!("# Activate the lilyenv virtualenv {project} ({version})\n{activate}\n");
+    if !write {
+        print!("{snippet}");
+        return Ok(());
+    }
+    let envrc = std::path::Path::new(".envrc");
+    let existing = match std::fs::read_to_string(envrc) {
+        Ok(existing) => existing,
+        Err(err) => match err.kind() {
+            std::io::ErrorKind::NotFound => String::new(),
+            _ => return Err(err)?,
+        },
+    };
+    if existing.lines().any(|line| line == activate) {
+        log::info!(".envrc already activates {project} ({version})");
+        return Ok(());
+    }
+    let separator = match existing.is_empty() || existing.ends_with('\n') {
+        true => "",
+        false => "\n",
+    };
+    std::fs::write(envrc, format!("{existing}{separator}{snippet}")).with_path(envrc)?;
+    log::info!("Added {project} ({version}) to .envrc. Run `direnv allow` to use it.");
     Ok(())
 }
 
 pub fn cd_site_packages(project: &str, version: &Version) -> Result<(), Error> {
     let virtualenv = virtualenv_dir(project, version);
-    let lib = virtualenv.join("lib");
-    let next = std::fs::read_dir(&lib)?
-        .next()
-        .unwrap_or_else(|| {
-            panic!(
-                "Expected subdirectory missing from virtualenv at {:?}.",
-                &lib
-            )
-        })?
-        .path();
-    let site_packages = next.join("site-packages");
+    if !virtualenv.exists() {
+        return Err(Error::VirtualenvNotFound(format!("{project} ({version})")));
+    }
+    let site_packages = venv_site_packages(&virtualenv)?.ok_or_else(|| {
+        Error::VirtualenvNotFound(format!("site-packages for {project} ({version})"))
+    })?;
 
     let mut shell = std::process::Command::new(get_shell(Some(project))?)
         .current_dir(site_packages)
@@ -105,8 +1481,22 @@ pub fn cd_site_packages(project: &str, version: &Version) -> Result<(), Error> {
     Ok(())
 }
 
+/// The `key = value` settings `python -m venv` writes to a virtualenv's `pyvenv.cfg`.
+pub fn read_pyvenv_cfg(
+    virtualenv: &std::path::Path,
+) -> Result<std::collections::HashMap<String, String>, Error> {
+    let cfg = pyvenv_cfg(virtualenv);
+    let contents = std::fs::read_to_string(&cfg).with_path(&cfg)?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect())
+}
+
 fn list_versions(path: std::path::PathBuf) -> Result<Vec<String>, Error> {
-    Ok(std::fs::read_dir(path)?
+    let mut versions = std::fs::read_dir(&path)
+        .with_path(&path)?
         .collect::<Result<Vec<_>, _>>()?
         .into_iter()
         .filter(|version| {
@@ -122,13 +1512,68 @@ fn list_versions(path: std::path::PathBuf) -> Result<Vec<String>, Error> {
                 .expect("Could not convert a version to utf-8.")
                 .to_string()
         })
-        .collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    versions.sort_unstable_by_key(|version| (version.parse::<Version>().ok(), version.clone()));
+    Ok(versions)
+}
+
+/// Every virtualenv as a project and version pair, sorted by project.
+pub fn all_virtualenvs() -> Result<Vec<(String, String)>, Error> {
+    let projects = match std::fs::read_dir(virtualenvs_dir()) {
+        Ok(projects) => projects,
+        Err(err) => match err.kind() {
+            std::io::ErrorKind::NotFound => return Ok(vec![]),
+            _ => return Err(err)?,
+        },
+    };
+    let mut projects = projects
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|project| project.path().is_dir())
+        .collect::<Vec<_>>();
+    projects.sort_unstable_by_key(|project| project.file_name());
+    let mut virtualenvs = vec![];
+    for project in projects {
+        let name = project
+            .file_name()
+            .to_str()
+            .expect("Could not convert a project directory name to utf-8")
+            .to_string();
+        for version in list_versions(project.path())? {
+            virtualenvs.push((name.clone(), version));
+        }
+    }
+    Ok(virtualenvs)
+}
+
+pub fn projects_using(version: &Version) -> Result<Vec<String>, Error> {
+    let projects = match std::fs::read_dir(virtualenvs_dir()) {
+        Ok(projects) => projects,
+        Err(err) => match err.kind() {
+            std::io::ErrorKind::NotFound => return Ok(vec![]),
+            _ => return Err(err)?,
+        },
+    };
+    let mut projects = projects
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|project| project.path().join(version.to_string()).is_dir())
+        .map(|project| {
+            project
+                .file_name()
+                .to_str()
+                .expect("Could not convert a project directory name to utf-8")
+                .to_string()
+        })
+        .collect::<Vec<_>>();
+    projects.sort_unstable();
+    Ok(projects)
 }
 
 pub fn print_project_versions(project: String) -> Result<(), Error> {
     let virtualenvs = project_dir(&project);
     let versions = list_versions(virtualenvs)?;
-    println!("{}", versions.join(" "));
+    anstream::println!("{VERSION}{}{VERSION:#}", versions.join(" "));
     Ok(())
 }
 
@@ -146,11 +1591,16 @@ pub fn print_all_versions() -> Result<(), Error> {
             }
         },
     };
+    let mut projects = projects
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|project| project.path().is_dir())
+        .collect::<Vec<_>>();
+    projects.sort_unstable_by_key(|project| project.file_name());
     for project in projects {
-        let project = project?;
         let versions = list_versions(project.path())?;
-        println!(
-            "{}: {}",
+        anstream::println!(
+            "{PROJECT}{}{PROJECT:#}: {VERSION}{}{VERSION:#}",
             project
                 .file_name()
                 .to_str()
@@ -160,3 +1610,40 @@ pub fn print_all_versions() -> Result<(), Error> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_env_file() {
+        let contents = "# Settings\n\nDJANGO_SETTINGS_MODULE=web.settings\nexport DEBUG = \"1\"\nNAME='a b'\nEMPTY=\n";
+        assert_eq!(
+            parse_env_file(contents).unwrap(),
+            [
+                ("DJANGO_SETTINGS_MODULE", "web.settings"),
+                ("DEBUG", "1"),
+                ("NAME", "a b"),
+                ("EMPTY", ""),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+        assert!(parse_env_file("DEBUG").is_err());
+        assert!(parse_env_file("1X=1").is_err());
+        assert!(parse_env_file("A;B=1").is_err());
+    }
+
+    #[test]
+    fn test_venv_version_matches() {
+        let version = |version: &str| version.parse::<Version>().unwrap();
+        assert!(venv_version_matches(&version("3.12"), "3.12.4"));
+        assert!(venv_version_matches(&version("3.12.4"), "3.12.4"));
+        assert!(venv_version_matches(&version("3.13.0rc1"), "3.13.0"));
+        assert!(!venv_version_matches(&version("3.12.3"), "3.12.4"));
+        assert!(!venv_version_matches(&version("3.12"), "3.11.9"));
+        assert!(venv_version_matches(&version("pypy3.10.17"), "3.10.14"));
+        assert!(!venv_version_matches(&version("pypy3.10"), "3.9.18"));
+        assert!(venv_version_matches(&version("graalpy24.1"), "3.11.7"));
+        assert!(!venv_version_matches(&version("3.12"), "unknown"));
+    }
+}
@@ -0,0 +1,101 @@
+//! Install a Python from a fixture archive and create a virtualenv with it, keeping all of
+//! lilyenv's files in a temporary root and staying offline so the network and the real lilyenv
+//! directories are never touched.
+#![cfg(target_os = "linux")]
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// Stands in for the archive's interpreter, logging how it was run.
+const FAKE_PYTHON: &str = r#"#!/bin/sh
+echo "$@" >> "$LILYENV_HOME/invocations"
+case "$1" in
+    --version) echo "Python 3.12.4" ;;
+    -m) mkdir -p "$3/bin" && echo "version = 3.12.4" > "$3/pyvenv.cfg" ;;
+esac
+"#;
+
+fn append(builder: &mut tar::Builder<impl std::io::Write>, path: &str, data: &str, mode: u32) {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(mode);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, path, data.as_bytes())
+        .unwrap();
+}
+
+/// Write an archive laid out like python-build-standalone's `install_only` ones.
+fn write_fixture(archive: &Path) {
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+        std::fs::File::create(archive).unwrap(),
+        flate2::Compression::default(),
+    ));
+    append(&mut builder, "python/bin/python3", FAKE_PYTHON, 0o755);
+    append(
+        &mut builder,
+        "python/lib/python3.12/_sysconfigdata__linux_x86_64-linux-gnu.py",
+        "build_time_vars = {'prefix': '/install', 'LIBDIR': '/install/lib'}\n",
+        0o644,
+    );
+    append(
+        &mut builder,
+        "python/lib/pkgconfig/python3.pc",
+        "prefix=/install\n",
+        0o644,
+    );
+    builder.into_inner().unwrap().finish().unwrap();
+}
+
+#[test]
+fn test_install_and_create_virtualenv() {
+    let temp = tempfile::Builder::new()
+        .prefix("lilyenv-test-")
+        .tempdir()
+        .unwrap();
+    let root = temp.path();
+    std::env::set_var("LILYENV_HOME", root);
+    std::env::set_var("XDG_CACHE_HOME", root.join("cache"));
+    std::env::set_var("XDG_CONFIG_HOME", root.join("config"));
+    lilyenv::http::set_offline();
+
+    let downloads = root.join("cache").join("lilyenv").join("downloads");
+    std::fs::create_dir_all(&downloads).unwrap();
+    let name = format!(
+        "cpython-3.12.4+20240713-{}-install_only.tar.gz",
+        lilyenv::releases::target_platform()
+    );
+    write_fixture(&downloads.join(name));
+
+    let version = "3.12".parse::<lilyenv::Version>().unwrap();
+    lilyenv::download_python(&version, false, false).unwrap();
+    let install = root.join("pythons").join("3.12").join("python");
+    let python = install.join("bin").join("python3");
+    assert!(python.metadata().unwrap().permissions().mode() & 0o111 != 0);
+    let sysconfig = std::fs::read_to_string(
+        install.join("lib/python3.12/_sysconfigdata__linux_x86_64-linux-gnu.py"),
+    )
+    .unwrap();
+    assert!(sysconfig.contains(&format!("'prefix': '{}'", install.display())));
+    assert!(!sysconfig.contains("/install/lib"));
+
+    lilyenv::create_virtualenv(&version, "web").unwrap();
+    let virtualenv = root.join("virtualenvs").join("web").join("3.12");
+    assert!(virtualenv.is_dir());
+    let invocations = std::fs::read_to_string(root.join("invocations")).unwrap();
+    assert_eq!(
+        invocations.lines().collect::<Vec<_>>(),
+        [
+            "--version".to_string(),
+            format!("-m venv {}", virtualenv.display())
+        ]
+    );
+
+    lilyenv::virtualenvs::create_virtualenv_with(&version, "web", Some("debug"), &[]).unwrap();
+    let named = root.join("virtualenvs").join("web").join("debug");
+    assert!(named.is_dir());
+    assert_eq!(
+        lilyenv::virtualenvs::virtualenv_version("web", "debug").unwrap(),
+        Some(version)
+    );
+}
@@ -0,0 +1,19 @@
+use lilyenv::{Interpreter, Version};
+
+#[test]
+fn test_parse_version() {
+    let version = "pypy3.10".parse::<Version>().unwrap();
+    assert_eq!(version.interpreter, Interpreter::PyPy);
+    assert!("pypy3.10.17"
+        .parse::<Version>()
+        .unwrap()
+        .compatible(&version));
+}
+
+#[test]
+fn test_invalid_version() {
+    assert!(matches!(
+        "python3".parse::<Version>(),
+        Err(lilyenv::Error::InvalidVersion(_))
+    ));
+}